@@ -29,6 +29,20 @@ CREATE INDEX IF NOT EXISTS directory_entry_anilist_id_idx ON directory_entry(ani
 CREATE INDEX IF NOT EXISTS directory_entry_creator_id_idx ON directory_entry(creator_id);
 CREATE INDEX IF NOT EXISTS directory_entry_tmdb_id_idx ON directory_entry(tmdb_id);
 
+-- The MyAnimeList and Kitsu IDs of an entry, from AniList and the anime-relations file.
+-- These are in their own table since directory_entry can't be altered.
+-- anilist_checked_at is set once AniList was asked for the MyAnimeList ID so that
+-- media without one aren't asked about again.
+CREATE TABLE IF NOT EXISTS entry_external_id (
+  entry_id INTEGER PRIMARY KEY REFERENCES directory_entry(id) ON DELETE CASCADE,
+  mal_id INTEGER,
+  kitsu_id INTEGER,
+  anilist_checked_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS entry_external_id_mal_id_idx ON entry_external_id(mal_id);
+CREATE INDEX IF NOT EXISTS entry_external_id_kitsu_id_idx ON entry_external_id(kitsu_id);
+
 -- This is for the authentication aspect
 -- Note that usernames are all lowercase
 -- Email is *not* stored anywhere
@@ -78,3 +92,89 @@ CREATE INDEX IF NOT EXISTS audit_log_entry_id_idx ON audit_log(entry_id);
 -- BEGIN
 --   DELETE FROM audit_log WHERE id <= cast((julianday('now') - 2440587.5)*86400.0 * 1000 as integer) - (180 * 86400000);
 -- END;
+
+-- Metadata for the files stored within a directory entry.
+-- The filesystem is still the source of truth for the file contents, this
+-- table just records the information that the filesystem cannot.
+-- If episode_begin is set and episode_end is NULL then the file is for a single episode.
+CREATE TABLE IF NOT EXISTS file (
+  id INTEGER PRIMARY KEY,
+  entry_id INTEGER NOT NULL REFERENCES directory_entry(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  size INTEGER NOT NULL DEFAULT 0,
+  sha256 TEXT NOT NULL,
+  uploader_id INTEGER REFERENCES account(id) ON DELETE SET NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  modified_at TEXT,
+  episode_begin INTEGER,
+  episode_end INTEGER,
+  UNIQUE(entry_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS file_entry_id_idx ON file(entry_id);
+CREATE INDEX IF NOT EXISTS file_uploader_id_idx ON file(uploader_id);
+CREATE INDEX IF NOT EXISTS file_sha256_idx ON file(sha256);
+
+-- The detected language and caption style of every text subtitle file.
+-- These are updated whenever the file's lines are indexed.
+CREATE TABLE IF NOT EXISTS file_classification (
+  file_id INTEGER PRIMARY KEY REFERENCES file(id) ON DELETE CASCADE,
+  language INTEGER NOT NULL DEFAULT 0,
+  closed_captions INTEGER NOT NULL DEFAULT 0
+);
+
+-- The lines of every text subtitle file, used for full-text search.
+-- Times are in milliseconds and the text has its formatting removed.
+CREATE TABLE IF NOT EXISTS subtitle_line (
+  id INTEGER PRIMARY KEY,
+  file_id INTEGER NOT NULL REFERENCES file(id) ON DELETE CASCADE,
+  start_ms INTEGER NOT NULL,
+  end_ms INTEGER NOT NULL,
+  text TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS subtitle_line_file_id_idx ON subtitle_line(file_id);
+
+-- The rowid of this table is the subtitle_line ID.
+-- Japanese text is split into overlapping bigrams before being inserted since
+-- the unicode61 tokenizer does not segment it, see lines.rs for details.
+CREATE VIRTUAL TABLE IF NOT EXISTS subtitle_line_fts USING fts5(tokens, content='', contentless_delete=1);
+
+CREATE TRIGGER IF NOT EXISTS subtitle_line_delete_fts AFTER DELETE ON subtitle_line
+BEGIN
+  DELETE FROM subtitle_line_fts WHERE rowid = OLD.id;
+END;
+
+-- The number of times each file was downloaded, aggregated per day (UTC).
+-- Nothing about who downloaded the file is stored.
+CREATE TABLE IF NOT EXISTS file_download (
+  file_id INTEGER NOT NULL REFERENCES file(id) ON DELETE CASCADE,
+  day TEXT NOT NULL,
+  count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (file_id, day)
+) WITHOUT ROWID;
+
+-- Chunked uploads that are still in progress, see upload.rs for details.
+-- The chunks themselves are stored on disk.
+CREATE TABLE IF NOT EXISTS upload_session (
+  id TEXT PRIMARY KEY,
+  entry_id INTEGER NOT NULL REFERENCES directory_entry(id) ON DELETE CASCADE,
+  account_id INTEGER NOT NULL REFERENCES account(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  sha256 TEXT NOT NULL,
+  chunk_size INTEGER NOT NULL,
+  options TEXT NOT NULL DEFAULT '{}',
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS upload_session_account_id_idx ON upload_session(account_id);
+
+-- Alternative titles of an entry, e.g. abbreviations and synonyms, used for searching.
+-- These are seeded from AniList and TMDB when an entry is created and are managed by editors.
+CREATE TABLE IF NOT EXISTS entry_alias (
+  entry_id INTEGER NOT NULL REFERENCES directory_entry(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  PRIMARY KEY (entry_id, name)
+) WITHOUT ROWID;
@@ -0,0 +1,98 @@
+//! Alternative titles of entries, e.g. abbreviations and synonyms.
+//!
+//! Aliases are searched alongside the entry's own titles. They're seeded from
+//! AniList synonyms and TMDB alternative titles when an entry is created and
+//! are otherwise managed by editors.
+
+use std::collections::BTreeSet;
+
+use crate::anilist::MediaTitle;
+
+/// The maximum number of aliases an entry can have.
+pub const MAX_ALIASES: usize = 50;
+
+/// The maximum length of a single alias, in bytes.
+pub const MAX_ALIAS_LENGTH: usize = 256;
+
+/// Cleans up a list of aliases.
+///
+/// Whitespace is trimmed, empty and duplicate aliases are removed, and the result is sorted
+/// the same way the database returns them.
+pub fn normalize<I, S>(aliases: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    aliases
+        .into_iter()
+        .map(|s| s.as_ref().trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Returns the aliases to seed a new entry with from the titles given by AniList or TMDB.
+///
+/// Aliases that are the same as one of the entry's own titles or that are too long are
+/// skipped, and at most [`MAX_ALIASES`] are returned.
+pub fn seed(titles: &MediaTitle, candidates: Vec<String>) -> Vec<String> {
+    let own = std::iter::once(titles.romaji.as_str())
+        .chain(titles.english.as_deref())
+        .chain(titles.native.as_deref())
+        .map(str::to_lowercase)
+        .collect::<BTreeSet<_>>();
+    let mut aliases = normalize(candidates);
+    aliases.retain(|alias| alias.len() <= MAX_ALIAS_LENGTH && !own.contains(&alias.to_lowercase()));
+    aliases.truncate(MAX_ALIASES);
+    aliases
+}
+
+/// Adds the aliases to the entry, ignoring the ones it already has.
+pub fn insert(conn: &rusqlite::Connection, entry_id: i64, aliases: &[String]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached("INSERT OR IGNORE INTO entry_alias(entry_id, name) VALUES (?, ?)")?;
+    for alias in aliases {
+        stmt.execute((entry_id, alias))?;
+    }
+    Ok(())
+}
+
+/// Replaces every alias of the entry with the given ones.
+///
+/// This should be called within a transaction.
+pub fn replace(conn: &rusqlite::Connection, entry_id: i64, aliases: &[String]) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM entry_alias WHERE entry_id = ?", [entry_id])?;
+    insert(conn, entry_id, aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(["  SnK ", "", "Attack on Titan", "SnK", "   "]),
+            vec![String::from("Attack on Titan"), String::from("SnK")]
+        );
+    }
+
+    #[test]
+    fn test_seed() {
+        let titles = MediaTitle {
+            romaji: String::from("Yahari Ore no Seishun Love Comedy wa Machigatteiru."),
+            english: Some(String::from("My Teen Romantic Comedy SNAFU")),
+            native: None,
+        };
+        let candidates = vec![
+            String::from("Oregairu"),
+            String::from("my teen romantic comedy snafu"),
+            "x".repeat(MAX_ALIAS_LENGTH + 1),
+            String::from("OreGairu "),
+        ];
+        assert_eq!(
+            seed(&titles, candidates),
+            vec![String::from("OreGairu"), String::from("Oregairu")]
+        );
+    }
+}
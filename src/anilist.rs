@@ -12,11 +12,13 @@ query ($id: Int, $page: Int, $perPage: Int, $search: String) {
   Page (page: $page, perPage: $perPage) {
     media (id: $id, search: $search, type: ANIME) {
       id
+      idMal
       title {
         romaji
         english
         native
       }
+      synonyms
       isAdult
       format
     }
@@ -24,6 +26,33 @@ query ($id: Int, $page: Int, $perPage: Int, $search: String) {
 }
 "#;
 
+const SEARCH_BY_IDS_QUERY: &str = r#"
+query ($ids: [Int], $page: Int, $perPage: Int) {
+  Page (page: $page, perPage: $perPage) {
+    media (id_in: $ids, type: ANIME) {
+      id
+      idMal
+      title {
+        romaji
+        english
+        native
+      }
+      synonyms
+      isAdult
+      format
+    }
+  }
+}
+"#;
+
+/// The maximum number of media that AniList returns per page.
+const MAX_PER_PAGE: usize = 50;
+
+/// The time to wait between requests that are sent back to back.
+///
+/// AniList allows 30 requests per minute, and other requests might be going on at the same time.
+const BATCH_REQUEST_INTERVAL: Duration = Duration::from_secs(4);
+
 #[allow(clippy::declare_interior_mutable_const)]
 const APPLICATION_JSON: HeaderValue = HeaderValue::from_static("application/json");
 
@@ -158,7 +187,11 @@ impl MediaTitle {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Media {
     pub id: u32,
+    #[serde(rename = "idMal", default)]
+    pub mal_id: Option<u32>,
     pub title: MediaTitle,
+    #[serde(default)]
+    pub synonyms: Vec<String>,
     #[serde(rename = "isAdult")]
     pub adult: bool,
     pub format: MediaFormat,
@@ -234,6 +267,14 @@ struct SearchQueryVariables {
     per_page: u8,
 }
 
+#[derive(Debug, Serialize)]
+struct SearchByIdsQueryVariables<'a> {
+    ids: &'a [u32],
+    page: u8,
+    #[serde(rename = "perPage")]
+    per_page: u8,
+}
+
 #[derive(Debug, Serialize)]
 struct SearchByIdQueryVariables {
     id: u32,
@@ -275,3 +316,38 @@ pub async fn search_by_id(client: &reqwest::Client, id: u32) -> anyhow::Result<O
     .into_iter()
     .next())
 }
+
+/// Searches the AniList API for the media that match any of the given IDs
+///
+/// IDs that AniList does not know about are skipped, so there might be fewer media than IDs.
+///
+/// The IDs are requested a page at a time, with [`BATCH_REQUEST_INTERVAL`] between requests.
+/// A page that fails is logged and skipped rather than discarding the others. The IDs of
+/// those pages are returned along with the media so they can be tried again later.
+pub async fn search_by_ids(client: &reqwest::Client, ids: &[u32]) -> (Vec<Media>, Vec<u32>) {
+    let mut result = Vec::with_capacity(ids.len());
+    let mut failed = Vec::new();
+    for (index, chunk) in ids.chunks(MAX_PER_PAGE).enumerate() {
+        if index != 0 {
+            tokio::time::sleep(BATCH_REQUEST_INTERVAL).await;
+        }
+        let page = send_request::<PageResult>(
+            client,
+            SEARCH_BY_IDS_QUERY,
+            SearchByIdsQueryVariables {
+                ids: chunk,
+                page: 1,
+                per_page: MAX_PER_PAGE as u8,
+            },
+        )
+        .await;
+        match page {
+            Ok(page) => result.extend(page.page.media),
+            Err(e) => {
+                warn!(error=%e, count = chunk.len(), "could not search anilist for a page of IDs");
+                failed.extend_from_slice(chunk);
+            }
+        }
+    }
+    (result, failed)
+}
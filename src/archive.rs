@@ -0,0 +1,799 @@
+//! Reading the members of ZIP and 7z archives that are stored in an entry,
+//! and writing ZIP archives of an entry's files.
+//!
+//! Everything in here is blocking and should be called from a blocking context.
+
+use std::{
+    borrow::Cow,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use flate2::{write::DeflateEncoder, Compression};
+use time::OffsetDateTime;
+
+/// The size of the chunks that archive members are read in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The maximum uncompressed size of a single file extracted from an archive.
+pub const MAX_MEMBER_SIZE: u64 = crate::MAX_UPLOAD_SIZE;
+
+/// The maximum total uncompressed size of every file extracted from an archive.
+pub const MAX_EXTRACT_SIZE: u64 = 128 * 1024 * 1024;
+
+/// The maximum number of files that can be extracted from an archive.
+pub const MAX_EXTRACT_ENTRIES: usize = 1000;
+
+/// A supported archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    /// Returns the archive kind of the given path based off of its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension().and_then(|s| s.to_str())?;
+        if ext.eq_ignore_ascii_case("zip") {
+            Some(Self::Zip)
+        } else if ext.eq_ignore_ascii_case("7z") {
+            Some(Self::SevenZip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file stored within an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// The name of the file within the archive, with `/` as the separator.
+    pub name: String,
+    /// The uncompressed size of the file in bytes.
+    pub size: u64,
+}
+
+/// Decodes a ZIP member name.
+///
+/// Older archives, especially Japanese ones, tend to store the name in the
+/// system's encoding rather than UTF-8 so the encoding has to be detected.
+fn zip_member_name(raw: &[u8]) -> Cow<'_, str> {
+    crate::encoding::decode(raw)
+}
+
+/// Returns the total length of the reader, leaving it at the start.
+fn reader_length<R: Seek>(reader: &mut R) -> std::io::Result<u64> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    Ok(length)
+}
+
+/// Lists the files within the archive read from the given reader.
+///
+/// The kind of archive is determined by the given filename. Directories are not included.
+pub fn list<R: Read + Seek>(filename: &Path, mut reader: R) -> anyhow::Result<Vec<Member>> {
+    let kind = ArchiveKind::from_path(filename).context("file is not an archive")?;
+    let mut members = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(reader)?;
+            for index in 0..zip.len() {
+                let file = zip.by_index_raw(index)?;
+                if file.is_dir() {
+                    continue;
+                }
+                members.push(Member {
+                    name: zip_member_name(file.name_raw()).into_owned(),
+                    size: file.size(),
+                });
+            }
+        }
+        ArchiveKind::SevenZip => {
+            let length = reader_length(&mut reader)?;
+            let archive = sevenz_rust::Archive::read(&mut reader, length, &[])?;
+            for file in archive.files.iter() {
+                if file.is_directory() || file.is_anti_item() {
+                    continue;
+                }
+                members.push(Member {
+                    name: file.name().replace('\\', "/"),
+                    size: file.size(),
+                });
+            }
+        }
+    }
+    Ok(members)
+}
+
+/// Reads a single member out of the archive read from the given reader.
+///
+/// The kind of archive is determined by the given filename. The contents are given to the callback in chunks. If the callback returns
+/// `false` then reading stops early.
+///
+/// Returns `false` if the member was not found in the archive.
+pub fn read_member<R, F>(filename: &Path, mut reader: R, name: &str, mut callback: F) -> anyhow::Result<bool>
+where
+    R: Read + Seek,
+    F: FnMut(&[u8]) -> bool,
+{
+    let kind = ArchiveKind::from_path(filename).context("file is not an archive")?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(reader)?;
+            let mut found = None;
+            for index in 0..zip.len() {
+                let file = zip.by_index_raw(index)?;
+                if !file.is_dir() && zip_member_name(file.name_raw()) == name {
+                    found = Some(index);
+                    break;
+                }
+            }
+
+            let Some(index) = found else {
+                return Ok(false);
+            };
+
+            let mut file = zip.by_index(index)?;
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 || !callback(&buffer[..read]) {
+                    break;
+                }
+            }
+            Ok(true)
+        }
+        ArchiveKind::SevenZip => {
+            let length = reader_length(&mut reader)?;
+            let mut reader = sevenz_rust::SevenZReader::new(reader, length, sevenz_rust::Password::empty())?;
+            let mut found = false;
+            reader.for_each_entries(|entry, reader| {
+                if entry.is_directory() || entry.name().replace('\\', "/") != name {
+                    // Entries within a solid block have to be read in order
+                    std::io::copy(reader, &mut std::io::sink())?;
+                    return Ok(true);
+                }
+
+                found = true;
+                loop {
+                    let read = reader.read(&mut buffer)?;
+                    if read == 0 || !callback(&buffer[..read]) {
+                        break;
+                    }
+                }
+                Ok(false)
+            })?;
+            Ok(found)
+        }
+    }
+}
+
+/// Keeps track of how much has been extracted from an archive so far.
+#[derive(Default)]
+struct Extractor {
+    size: u64,
+    entries: usize,
+}
+
+impl Extractor {
+    /// Reads a single member, failing if it would go over any of the extraction limits.
+    ///
+    /// The sizes stored in the archive headers can lie, so the limits are checked
+    /// against the decompressed contents.
+    fn read(&mut self, name: &str, reader: &mut dyn Read) -> anyhow::Result<Vec<u8>> {
+        self.entries += 1;
+        if self.entries > MAX_EXTRACT_ENTRIES {
+            anyhow::bail!("archive has more than {MAX_EXTRACT_ENTRIES} files");
+        }
+
+        let mut buffer = Vec::new();
+        reader.take(MAX_MEMBER_SIZE + 1).read_to_end(&mut buffer)?;
+        if buffer.len() as u64 > MAX_MEMBER_SIZE {
+            anyhow::bail!("{name} is too large when uncompressed");
+        }
+        self.size += buffer.len() as u64;
+        if self.size > MAX_EXTRACT_SIZE {
+            anyhow::bail!("archive is too large when uncompressed");
+        }
+        Ok(buffer)
+    }
+}
+
+/// Extracts every file within an in-memory archive.
+///
+/// The kind of archive is determined by the given filename. Directories are not included.
+///
+/// Archives with a file over [`MAX_MEMBER_SIZE`], more than [`MAX_EXTRACT_ENTRIES`] files,
+/// or more than [`MAX_EXTRACT_SIZE`] bytes in total are rejected with an error.
+pub fn extract(filename: &Path, bytes: &[u8]) -> anyhow::Result<Vec<(Member, Vec<u8>)>> {
+    let kind = ArchiveKind::from_path(filename).context("file is not an archive")?;
+    let mut extractor = Extractor::default();
+    let mut files = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(Cursor::new(bytes))?;
+            if zip.len() > MAX_EXTRACT_ENTRIES {
+                anyhow::bail!("archive has more than {MAX_EXTRACT_ENTRIES} files");
+            }
+            for index in 0..zip.len() {
+                let mut file = zip.by_index(index)?;
+                if file.is_dir() {
+                    continue;
+                }
+                let name = zip_member_name(file.name_raw()).into_owned();
+                let buffer = extractor.read(&name, &mut file)?;
+                let member = Member {
+                    name,
+                    size: buffer.len() as u64,
+                };
+                files.push((member, buffer));
+            }
+        }
+        ArchiveKind::SevenZip => {
+            let mut reader =
+                sevenz_rust::SevenZReader::new(Cursor::new(bytes), bytes.len() as u64, sevenz_rust::Password::empty())?;
+            // The callback can only return I/O errors so the actual error is stored here
+            let mut error = None;
+            reader.for_each_entries(|entry, reader| {
+                if entry.is_directory() || entry.is_anti_item() {
+                    std::io::copy(reader, &mut std::io::sink())?;
+                    return Ok(true);
+                }
+                let name = entry.name().replace('\\', "/");
+                match extractor.read(&name, reader) {
+                    Ok(buffer) => {
+                        let member = Member {
+                            name,
+                            size: buffer.len() as u64,
+                        };
+                        files.push((member, buffer));
+                        Ok(true)
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        Ok(false)
+                    }
+                }
+            })?;
+            if let Some(error) = error {
+                return Err(error);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The limits that an uploaded archive has to be within to be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum total uncompressed size of every file, including nested archives.
+    pub max_size: u64,
+    /// The maximum number of files, including the ones in nested archives.
+    pub max_entries: usize,
+    /// The maximum number of archives that can be nested within each other.
+    ///
+    /// A value of 0 means archives cannot contain other archives.
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_EXTRACT_SIZE,
+            max_entries: MAX_EXTRACT_ENTRIES,
+            max_depth: 1,
+        }
+    }
+}
+
+/// The reason an archive was rejected by [`inspect`].
+#[derive(Debug)]
+pub enum InspectError {
+    /// The archive could not be read.
+    Invalid(anyhow::Error),
+    /// The total uncompressed size is over the limit.
+    TooLarge,
+    /// The archive has too many files.
+    TooManyEntries,
+    /// A member name is absolute or traverses outside of the archive.
+    UnsafePath(String),
+    /// A nested archive is past the depth limit.
+    TooDeep(String),
+}
+
+impl std::fmt::Display for InspectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "could not read archive: {e}"),
+            Self::TooLarge => f.write_str("archive is too large when uncompressed"),
+            Self::TooManyEntries => f.write_str("archive has too many files"),
+            Self::UnsafePath(name) => write!(f, "archive has an unsafe file path: {name}"),
+            Self::TooDeep(name) => write!(f, "archive has too many nested archives: {name}"),
+        }
+    }
+}
+
+impl std::error::Error for InspectError {}
+
+impl From<anyhow::Error> for InspectError {
+    fn from(value: anyhow::Error) -> Self {
+        Self::Invalid(value)
+    }
+}
+
+impl From<std::io::Error> for InspectError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Invalid(value.into())
+    }
+}
+
+impl From<zip::result::ZipError> for InspectError {
+    fn from(value: zip::result::ZipError) -> Self {
+        Self::Invalid(value.into())
+    }
+}
+
+impl From<sevenz_rust::Error> for InspectError {
+    fn from(value: sevenz_rust::Error) -> Self {
+        Self::Invalid(value.into())
+    }
+}
+
+/// Returns whether a member name is relative and stays within the archive.
+pub fn is_safe_name(name: &str) -> bool {
+    if name.is_empty() || name.starts_with(['/', '\\']) {
+        return false;
+    }
+
+    // Windows drive letters, e.g. C:
+    if name.as_bytes().get(1) == Some(&b':') {
+        return false;
+    }
+
+    name.split(['/', '\\']).all(|component| component != "..")
+}
+
+struct Inspector {
+    limits: Limits,
+    size: u64,
+    entries: usize,
+}
+
+impl Inspector {
+    /// Accounts for a single member, returning its contents if it's a nested archive.
+    fn visit(&mut self, name: &str, reader: &mut dyn Read, depth: usize) -> Result<Option<Vec<u8>>, InspectError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(InspectError::TooManyEntries);
+        }
+        if !is_safe_name(name) {
+            return Err(InspectError::UnsafePath(name.to_owned()));
+        }
+
+        let is_archive = ArchiveKind::from_path(Path::new(name)).is_some();
+        if is_archive && depth >= self.limits.max_depth {
+            return Err(InspectError::TooDeep(name.to_owned()));
+        }
+
+        // The sizes stored in the archive headers can lie, so the size is
+        // checked by actually decompressing the contents
+        let remaining = self.limits.max_size - self.size;
+        let mut limited = reader.take(remaining + 1);
+        let (read, contents) = if is_archive {
+            let mut buffer = Vec::new();
+            limited.read_to_end(&mut buffer)?;
+            (buffer.len() as u64, Some(buffer))
+        } else {
+            (std::io::copy(&mut limited, &mut std::io::sink())?, None)
+        };
+
+        if read > remaining {
+            return Err(InspectError::TooLarge);
+        }
+        self.size += read;
+        Ok(contents)
+    }
+
+    fn inspect(&mut self, filename: &Path, bytes: &[u8], depth: usize) -> Result<(), InspectError> {
+        let kind = ArchiveKind::from_path(filename).context("file is not an archive")?;
+        let mut nested = Vec::new();
+        match kind {
+            ArchiveKind::Zip => {
+                let mut zip = zip::ZipArchive::new(Cursor::new(bytes))?;
+                for index in 0..zip.len() {
+                    let mut file = zip.by_index(index)?;
+                    let name = zip_member_name(file.name_raw()).into_owned();
+                    if file.is_dir() {
+                        if !is_safe_name(&name) {
+                            return Err(InspectError::UnsafePath(name));
+                        }
+                        continue;
+                    }
+                    if let Some(contents) = self.visit(&name, &mut file, depth)? {
+                        nested.push((name, contents));
+                    }
+                }
+            }
+            ArchiveKind::SevenZip => {
+                let mut reader = sevenz_rust::SevenZReader::new(
+                    Cursor::new(bytes),
+                    bytes.len() as u64,
+                    sevenz_rust::Password::empty(),
+                )?;
+                // The callback can only return I/O errors so the actual error is stored here
+                let mut error = None;
+                reader.for_each_entries(|entry, reader| {
+                    let name = entry.name().replace('\\', "/");
+                    let result = if entry.is_directory() || entry.is_anti_item() {
+                        if is_safe_name(&name) {
+                            Ok(None)
+                        } else {
+                            Err(InspectError::UnsafePath(name.clone()))
+                        }
+                    } else {
+                        self.visit(&name, reader, depth)
+                    };
+                    match result {
+                        Ok(Some(contents)) => nested.push((name, contents)),
+                        Ok(None) => {}
+                        Err(e) => {
+                            error = Some(e);
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                })?;
+                if let Some(error) = error {
+                    return Err(error);
+                }
+            }
+        }
+
+        for (name, contents) in nested {
+            self.inspect(Path::new(&name), &contents, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that an in-memory archive is safe to store and extract.
+///
+/// Every member, including the ones in nested archives, is decompressed to verify
+/// that the archive is within the given limits and that no member names are absolute
+/// or contain `..` components.
+///
+/// The kind of archive is determined by the given filename.
+pub fn inspect(filename: &Path, bytes: &[u8], limits: Limits) -> Result<(), InspectError> {
+    let mut inspector = Inspector {
+        limits,
+        size: 0,
+        entries: 0,
+    };
+    inspector.inspect(filename, bytes, 0)
+}
+
+/// A writer that keeps track of how many bytes were written to it.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A file that was written by a [`StreamingZipWriter`], kept for the central directory.
+struct WrittenFile {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// The general purpose flags of every file: the sizes come after the data and the name is UTF-8.
+const ZIP_FLAGS: u16 = (1 << 3) | (1 << 11);
+/// The deflate compression method.
+const ZIP_DEFLATE: u16 = 8;
+/// The version needed to extract, 2.0 is needed for deflate.
+const ZIP_VERSION: u16 = 20;
+
+fn zip_too_large() -> std::io::Error {
+    std::io::Error::other("archive is too large, ZIP64 is not supported")
+}
+
+/// Converts a date to the MS-DOS `(time, date)` format used by ZIP files.
+fn dos_date_time(datetime: OffsetDateTime) -> (u16, u16) {
+    // MS-DOS dates can only represent the years 1980 to 2107
+    let year = datetime.year().clamp(1980, 2107);
+    let time = ((datetime.hour() as u16) << 11) | ((datetime.minute() as u16) << 5) | (datetime.second() as u16 / 2);
+    let date = (((year - 1980) as u16) << 9) | ((datetime.month() as u16) << 5) | datetime.day() as u16;
+    (time, date)
+}
+
+/// A ZIP archive writer that never seeks, so the archive can be sent while it's being written.
+///
+/// Since the size and CRC-32 of a file are only known after it's compressed,
+/// they're written in a data descriptor after the file's data rather than in
+/// its header. ZIP64 is not supported, so the archive has to be under 4 GiB.
+pub struct StreamingZipWriter<W: Write> {
+    inner: CountingWriter<W>,
+    files: Vec<WrittenFile>,
+}
+
+impl<W: Write> StreamingZipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: CountingWriter { inner, count: 0 },
+            files: Vec::new(),
+        }
+    }
+
+    /// Compresses everything from the reader into a new file in the archive.
+    pub fn add_file(&mut self, name: &str, modified: OffsetDateTime, mut reader: impl Read) -> std::io::Result<()> {
+        let offset = u32::try_from(self.inner.count).map_err(|_| zip_too_large())?;
+        let name_length = u16::try_from(name.len()).map_err(|_| std::io::Error::other("file name is too long"))?;
+        let (time, date) = dos_date_time(modified);
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        header.extend_from_slice(&ZIP_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        // The CRC-32, compressed size, and uncompressed size are in the data descriptor
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&name_length.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.inner.write_all(&header)?;
+
+        let start = self.inner.count;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0u64;
+        let mut encoder = DeflateEncoder::new(&mut self.inner, Compression::default());
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+            encoder.write_all(&buffer[..read])?;
+        }
+        encoder.finish()?;
+
+        let file = WrittenFile {
+            name: name.to_owned(),
+            time,
+            date,
+            crc: hasher.finalize(),
+            compressed_size: u32::try_from(self.inner.count - start).map_err(|_| zip_too_large())?,
+            size: u32::try_from(size).map_err(|_| zip_too_large())?,
+            offset,
+        };
+
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&file.crc.to_le_bytes());
+        descriptor.extend_from_slice(&file.compressed_size.to_le_bytes());
+        descriptor.extend_from_slice(&file.size.to_le_bytes());
+        self.inner.write_all(&descriptor)?;
+        self.files.push(file);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let start = u32::try_from(self.inner.count).map_err(|_| zip_too_large())?;
+        let total = u16::try_from(self.files.len()).map_err(|_| zip_too_large())?;
+        for file in &self.files {
+            let mut header = Vec::with_capacity(46 + file.name.len());
+            header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            // Version made by, the upper byte of 3 means the attributes are Unix ones
+            header.extend_from_slice(&((3 << 8) | ZIP_VERSION).to_le_bytes());
+            header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+            header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+            header.extend_from_slice(&ZIP_DEFLATE.to_le_bytes());
+            header.extend_from_slice(&file.time.to_le_bytes());
+            header.extend_from_slice(&file.date.to_le_bytes());
+            header.extend_from_slice(&file.crc.to_le_bytes());
+            header.extend_from_slice(&file.compressed_size.to_le_bytes());
+            header.extend_from_slice(&file.size.to_le_bytes());
+            header.extend_from_slice(&(file.name.len() as u16).to_le_bytes());
+            // Extra field length, comment length, disk number, and internal attributes
+            header.extend_from_slice(&[0; 8]);
+            // External attributes, a regular file with 0644 permissions
+            header.extend_from_slice(&(0o100644u32 << 16).to_le_bytes());
+            header.extend_from_slice(&file.offset.to_le_bytes());
+            header.extend_from_slice(file.name.as_bytes());
+            self.inner.write_all(&header)?;
+        }
+        let size = u32::try_from(self.inner.count - start as u64).map_err(|_| zip_too_large())?;
+
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        // Disk numbers
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&total.to_le_bytes());
+        end.extend_from_slice(&total.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        // Comment length
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.inner.write_all(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_zip_members() {
+        let path = std::env::temp_dir().join(format!("jimaku-archive-test-{}.zip", std::process::id()));
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+            let options = zip::write::FileOptions::default();
+            zip.add_directory("season 1/", options).unwrap();
+            zip.start_file("season 1/01.srt", options).unwrap();
+            zip.write_all(b"first").unwrap();
+            zip.start_file("02.srt", options).unwrap();
+            zip.write_all(b"second").unwrap();
+            zip.finish().unwrap();
+        }
+
+        let open = || std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        let members = list(&path, open()).unwrap();
+        assert_eq!(
+            members,
+            [
+                Member {
+                    name: String::from("season 1/01.srt"),
+                    size: 5
+                },
+                Member {
+                    name: String::from("02.srt"),
+                    size: 6
+                },
+            ]
+        );
+
+        let mut contents = Vec::new();
+        assert!(read_member(&path, open(), "02.srt", |chunk| {
+            contents.extend_from_slice(chunk);
+            true
+        })
+        .unwrap());
+        assert_eq!(contents, b"second");
+        assert!(!read_member(&path, open(), "03.srt", |_| true).unwrap());
+
+        let bytes = std::fs::read(&path).unwrap();
+        let extracted = extract(&path, &bytes).unwrap();
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted[0].1, b"first");
+        let _ = std::fs::remove_file(path);
+    }
+
+    fn zip_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        for (name, contents) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_streaming_zip() {
+        let modified = time::macros::datetime!(2024-03-05 12:34:56 UTC);
+        let mut writer = StreamingZipWriter::new(Vec::new());
+        writer.add_file("01.srt", modified, &b"first"[..]).unwrap();
+        writer.add_file("二.srt", modified, &[b'a'; 200_000][..]).unwrap();
+        writer.add_file("empty.srt", modified, &b""[..]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut contents = String::new();
+        let mut file = archive.by_name("01.srt").unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first");
+        let last_modified = file.last_modified();
+        assert_eq!(
+            (last_modified.year(), last_modified.month(), last_modified.day()),
+            (2024, 3, 5)
+        );
+        assert_eq!(
+            (last_modified.hour(), last_modified.minute(), last_modified.second()),
+            (12, 34, 56)
+        );
+        drop(file);
+
+        let mut contents = Vec::new();
+        let mut file = archive.by_name("二.srt").unwrap();
+        assert!(file.compressed_size() < file.size());
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![b'a'; 200_000]);
+        drop(file);
+        assert_eq!(archive.by_name("empty.srt").unwrap().size(), 0);
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        let path = Path::new("upload.zip");
+        let oversized = vec![0u8; MAX_MEMBER_SIZE as usize + 1];
+        let bytes = zip_bytes(&[("01.srt", b"first"), ("bomb.srt", &oversized)]);
+        assert!(extract(path, &bytes).is_err());
+
+        let names = (0..=MAX_EXTRACT_ENTRIES)
+            .map(|i| format!("{i}.srt"))
+            .collect::<Vec<_>>();
+        let files = names.iter().map(|name| (name.as_str(), &b"a"[..])).collect::<Vec<_>>();
+        assert!(extract(path, &zip_bytes(&files)).is_err());
+        assert_eq!(
+            extract(path, &zip_bytes(&files[1..])).unwrap().len(),
+            MAX_EXTRACT_ENTRIES
+        );
+    }
+
+    #[test]
+    fn test_inspect() {
+        let path = Path::new("upload.zip");
+        let limits = Limits::default();
+        let simple = zip_bytes(&[("01.srt", b"first"), ("sub/02.srt", b"second")]);
+        assert!(inspect(path, &simple, limits).is_ok());
+
+        for name in ["../01.srt", "/etc/passwd", "a/../../01.srt", "C:\\01.srt", "..\\01.srt"] {
+            let bytes = zip_bytes(&[(name, b"evil")]);
+            assert!(
+                matches!(inspect(path, &bytes, limits), Err(InspectError::UnsafePath(_))),
+                "{name}"
+            );
+        }
+
+        let large = vec![b'a'; 1024];
+        let bytes = zip_bytes(&[("01.srt", &large), ("02.srt", &large)]);
+        let small = Limits {
+            max_size: 1500,
+            ..limits
+        };
+        assert!(matches!(inspect(path, &bytes, small), Err(InspectError::TooLarge)));
+        let few = Limits {
+            max_entries: 1,
+            ..limits
+        };
+        assert!(matches!(inspect(path, &bytes, few), Err(InspectError::TooManyEntries)));
+
+        let nested = zip_bytes(&[("inner.zip", &simple)]);
+        assert!(inspect(path, &nested, limits).is_ok());
+        let twice = zip_bytes(&[("outer.zip", &nested)]);
+        assert!(matches!(inspect(path, &twice, limits), Err(InspectError::TooDeep(_))));
+
+        let nested_unsafe = zip_bytes(&[("inner.zip", &zip_bytes(&[("../01.srt", b"evil")]))]);
+        assert!(matches!(
+            inspect(path, &nested_unsafe, limits),
+            Err(InspectError::UnsafePath(_))
+        ));
+    }
+}
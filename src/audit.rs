@@ -1,10 +1,12 @@
 //! Implements an audit log trail for editor actions
 
+use std::collections::BTreeMap;
+
 use rusqlite::{types::FromSql, ToSql};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::{database::Table, models::EntryFlags, tmdb};
+use crate::{database::Table, models::EntryFlags, tmdb, Database};
 
 /*
     It's important to note that the data in here should be backwards compatible.
@@ -72,9 +74,9 @@ impl TryFrom<u8> for ScrapeSource {
     }
 }
 
-impl Into<u8> for ScrapeSource {
-    fn into(self) -> u8 {
-        self as u8
+impl From<ScrapeSource> for u8 {
+    fn from(value: ScrapeSource) -> Self {
+        value as u8
     }
 }
 
@@ -188,6 +190,21 @@ impl RenameFiles {
 pub struct Upload {
     pub files: Vec<FileOperation>,
     pub api: bool,
+    /// The detected source encoding of the text files that were not UTF-8.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub encodings: BTreeMap<String, String>,
+    /// Whether the files were stored in their original encoding instead of being converted to UTF-8.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub original_encoding: bool,
+    /// The name of the uploaded archive that each extracted file came from.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub archives: BTreeMap<String, String>,
+    /// The entry ID holding an existing copy of each file that was knowingly uploaded as a duplicate.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub duplicates: BTreeMap<String, i64>,
+    /// The files that were held for review in the trash instead of being stored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub held: Vec<String>,
 }
 
 impl Upload {
@@ -196,6 +213,29 @@ impl Upload {
     }
 }
 
+/// Audit log data for saving a retimed copy of a file
+///
+/// For this data, `entry_id` and `account_id` are only null if the data is deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetimeFile {
+    /// The name of the file that was retimed
+    pub source: String,
+    /// The name of the retimed copy
+    pub name: String,
+    /// The number of milliseconds every timestamp was shifted by
+    pub shift: i64,
+    /// The framerate the file was timed for, if it was converted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_fps: Option<f64>,
+    /// The framerate the file was converted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_fps: Option<f64>,
+    pub api: bool,
+}
+
+// The framerates are validated to be finite before they're stored, so they're never NaN.
+impl Eq for RetimeFile {}
+
 /// Audit log data for a file delete operation
 ///
 /// For this data, `entry_id` and `account_id` are only null if the data is deleted.
@@ -264,6 +304,8 @@ pub struct EntrySnapshot {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(with = "crate::models::expand_flags::option")]
     pub flags: Option<EntryFlags>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
 }
 
 /// Audit log data for an entry edit operation
@@ -295,6 +337,43 @@ impl TrashAction {
     }
 }
 
+/// A kind of repair made by the `fsck` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckAction {
+    /// The missing directory of an entry was created again
+    CreateDirectory,
+    /// A directory without an entry was adopted as an unverified entry
+    AdoptDirectory,
+    /// The `last_updated_at` of an entry was recomputed from its files
+    UpdateLastUpdated,
+    /// A trash record without its trashed file was removed
+    PruneTrashRecord,
+}
+
+/// Audit log data for a repair made by the `fsck` subcommand
+///
+/// For this data, `account_id` is always null since the repair is made by the system.
+/// `entry_id` is null if the repair is not related to an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsckRepair {
+    /// The repair that was made
+    pub action: FsckAction,
+    /// The path that was repaired
+    pub path: String,
+}
+
+/// Audit log data for files changed in an entry's directory outside of the site
+///
+/// For this data, `account_id` is always null since the change is detected by the system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalFiles {
+    /// The names of the files that were added
+    pub added: Vec<String>,
+    /// The names of the files that were removed
+    pub removed: Vec<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -310,6 +389,27 @@ pub enum AuditLogData {
     EditEntry(EditEntry),
     ReportFiles(ReportFiles),
     ReportEntry(ReportEntry),
+    RetimeFile(RetimeFile),
+    FsckRepair(FsckRepair),
+    ExternalFiles(ExternalFiles),
+}
+
+impl From<ExternalFiles> for AuditLogData {
+    fn from(v: ExternalFiles) -> Self {
+        Self::ExternalFiles(v)
+    }
+}
+
+impl From<FsckRepair> for AuditLogData {
+    fn from(v: FsckRepair) -> Self {
+        Self::FsckRepair(v)
+    }
+}
+
+impl From<RetimeFile> for AuditLogData {
+    fn from(v: RetimeFile) -> Self {
+        Self::RetimeFile(v)
+    }
 }
 
 impl From<ReportEntry> for AuditLogData {
@@ -441,6 +541,22 @@ impl AuditLogEntry {
         self
     }
 
+    pub fn with_entry(mut self, entry_id: i64) -> Self {
+        self.entry_id = Some(entry_id);
+        self
+    }
+
+    /// Inserts this entry into the audit log.
+    pub async fn insert(self, database: &Database) -> rusqlite::Result<()> {
+        database
+            .execute(
+                "INSERT INTO audit_log(id, entry_id, account_id, data) VALUES (?, ?, ?, ?)",
+                (self.id, self.entry_id, self.account_id, self.data),
+            )
+            .await
+            .map(|_| ())
+    }
+
     pub fn created_at(&self) -> OffsetDateTime {
         OffsetDateTime::from_unix_timestamp_nanos(self.id as i128 * 1_000_000).unwrap_or(OffsetDateTime::UNIX_EPOCH)
     }
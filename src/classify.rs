@@ -0,0 +1,259 @@
+//! Classification of text subtitle files by their language and caption style.
+//!
+//! This is a heuristic based off of the plain text of every cue. A cue is
+//! considered Japanese if it has any kana or kanji in it, and English if it
+//! only has latin letters. Cues that have neither, e.g. ones that are only
+//! `♪` or numbers, are ignored.
+//!
+//! Closed captions are told apart from dialogue-only subtitles by how many of
+//! their cues describe sounds or label the speaker, e.g. `（拍手）` or `(田中)`.
+
+use std::collections::HashMap;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{japanese::is_japanese_char, lines::Line, Database};
+
+/// The language a subtitle file is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Language {
+    /// The language could not be determined, e.g. because the file has no text.
+    Unknown = 0,
+    /// The file is mostly in Japanese.
+    Japanese = 1,
+    /// The file is mostly in English, or another language written with latin letters.
+    English = 2,
+    /// The file has a mix of Japanese and English, e.g. a dual subtitle file.
+    Mixed = 3,
+}
+
+impl FromSql for Language {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_i64()? {
+            0 => Ok(Self::Unknown),
+            1 => Ok(Self::Japanese),
+            2 => Ok(Self::English),
+            3 => Ok(Self::Mixed),
+            n => Err(FromSqlError::OutOfRange(n)),
+        }
+    }
+}
+
+impl ToSql for Language {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as u8))
+    }
+}
+
+/// The classification of a text subtitle file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Classification {
+    pub language: Language,
+    /// Whether the file is a closed caption (CC) track rather than dialogue-only.
+    pub closed_captions: bool,
+}
+
+/// The minimum ratio of Japanese cues for a file to be considered Japanese.
+const JAPANESE_RATIO: f64 = 0.8;
+/// The maximum ratio of Japanese cues for a file to be considered English.
+const ENGLISH_RATIO: f64 = 0.2;
+/// The minimum ratio of cues without Japanese for an upload to be held for review.
+const REVIEW_RATIO: f64 = 0.5;
+/// The minimum ratio of caption cues for a file to be considered closed captions.
+const CAPTION_RATIO: f64 = 0.05;
+/// The minimum number of caption cues for a file to be considered closed captions.
+///
+/// This prevents a single `（笑）` in a short file from counting.
+const CAPTION_MINIMUM: usize = 3;
+/// The maximum number of characters in a speaker label, e.g. `田中：`.
+const SPEAKER_LENGTH: usize = 10;
+
+const BRACKETS: [(char, char); 6] = [
+    ('（', '）'),
+    ('(', ')'),
+    ('［', '］'),
+    ('[', ']'),
+    ('〔', '〕'),
+    ('【', '】'),
+];
+
+/// Returns whether the line describes a sound or labels who is speaking.
+fn is_caption_line(line: &str) -> bool {
+    let line = line.trim();
+    let mut chars = line.chars();
+    if let Some(first) = chars.next() {
+        if let Some((_, close)) = BRACKETS.iter().find(|(open, _)| *open == first) {
+            return chars.as_str().contains(*close);
+        }
+    }
+
+    match line.split_once(['：', ':']) {
+        Some((speaker, rest)) => {
+            let length = speaker.chars().count();
+            (1..=SPEAKER_LENGTH).contains(&length)
+                && !rest.trim().is_empty()
+                && !speaker.chars().any(char::is_whitespace)
+                && !speaker.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Classifies a text subtitle file from its lines.
+pub fn classify(lines: &[Line]) -> Classification {
+    let mut japanese = 0usize;
+    let mut english = 0usize;
+    let mut captions = 0usize;
+    for line in lines {
+        if line.text.chars().any(is_japanese_char) {
+            japanese += 1;
+        } else if line.text.chars().any(|c| c.is_ascii_alphabetic()) {
+            english += 1;
+        }
+        if line.text.lines().any(is_caption_line) {
+            captions += 1;
+        }
+    }
+
+    let counted = japanese + english;
+    let language = if counted == 0 {
+        Language::Unknown
+    } else {
+        let ratio = japanese as f64 / counted as f64;
+        if ratio >= JAPANESE_RATIO {
+            Language::Japanese
+        } else if ratio <= ENGLISH_RATIO {
+            Language::English
+        } else {
+            Language::Mixed
+        }
+    };
+
+    Classification {
+        language,
+        closed_captions: captions >= CAPTION_MINIMUM && captions as f64 >= lines.len() as f64 * CAPTION_RATIO,
+    }
+}
+
+/// Returns `true` if at least half of the cues with text in them have no Japanese.
+///
+/// This is used to hold uploads for review. Unlike the classified language, files
+/// that are a mix of languages are included when they're mostly not Japanese.
+pub fn is_mostly_non_japanese(lines: &[Line]) -> bool {
+    let mut total = 0usize;
+    let mut japanese = 0usize;
+    for line in lines.iter().filter(|l| !l.text.trim().is_empty()) {
+        total += 1;
+        if line.text.chars().any(is_japanese_char) {
+            japanese += 1;
+        }
+    }
+    total != 0 && (total - japanese) as f64 / total as f64 >= REVIEW_RATIO
+}
+
+/// Replaces the stored classification of a file.
+pub fn store(conn: &rusqlite::Connection, file_id: i64, classification: &Classification) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO file_classification(file_id, language, closed_captions) VALUES (?, ?, ?)",
+    )?
+    .execute((file_id, classification.language, classification.closed_captions))?;
+    Ok(())
+}
+
+/// Returns the classification of every classified file in the entry, keyed by file ID.
+pub async fn for_entry(database: &Database, entry_id: i64) -> rusqlite::Result<HashMap<i64, Classification>> {
+    database
+        .call(move |conn| -> rusqlite::Result<HashMap<i64, Classification>> {
+            let query = r#"
+                SELECT file_classification.file_id, file_classification.language, file_classification.closed_captions
+                FROM file_classification INNER JOIN file ON file.id = file_classification.file_id
+                WHERE file.entry_id = ?
+            "#;
+            let mut stmt = conn.prepare_cached(query)?;
+            let rows = stmt.query_map([entry_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    Classification {
+                        language: row.get(1)?,
+                        closed_captions: row.get(2)?,
+                    },
+                ))
+            })?;
+            rows.collect()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(texts: &[&str]) -> Vec<Line> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| Line {
+                start: i as u64 * 1000,
+                end: i as u64 * 1000 + 500,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_caption_lines() {
+        assert!(is_caption_line("（拍手）"));
+        assert!(is_caption_line("(田中)そうだね"));
+        assert!(is_caption_line("田中：そうだね"));
+        assert!(is_caption_line("[door opens]"));
+        assert!(!is_caption_line("そうだね"));
+        assert!(!is_caption_line("（まだ"));
+        assert!(!is_caption_line("10:30に会おう"));
+        assert!(!is_caption_line("It's 10:30 now"));
+    }
+
+    #[test]
+    fn test_classify() {
+        let japanese = classify(&lines(&["こんにちは", "OK", "元気？", "はい", "♪", "行こう"]));
+        assert_eq!(japanese.language, Language::Japanese);
+        assert!(!japanese.closed_captions);
+
+        let english = classify(&lines(&["Hello", "How are you?", "Fine", "Tanaka-san"]));
+        assert_eq!(english.language, Language::English);
+
+        let mixed = classify(&lines(&["こんにちは\nHello", "Hello", "元気？", "How are you?"]));
+        assert_eq!(mixed.language, Language::Mixed);
+
+        assert_eq!(classify(&lines(&["♪", "123"])).language, Language::Unknown);
+        assert_eq!(classify(&[]).language, Language::Unknown);
+
+        let captions = classify(&lines(&[
+            "（拍手）",
+            "田中：そうだね",
+            "はい",
+            "（ドアの音）",
+            "行こう",
+        ]));
+        assert_eq!(captions.language, Language::Japanese);
+        assert!(captions.closed_captions);
+    }
+
+    #[test]
+    fn test_mostly_non_japanese() {
+        let mostly_english = lines(&["こんにちは", "Hello", "How are you?", "元気？", "Fine"]);
+        assert_eq!(classify(&mostly_english).language, Language::Mixed);
+        assert!(is_mostly_non_japanese(&mostly_english));
+
+        let mostly_japanese = lines(&["こんにちは", "Hello", "元気？", "はい", "Fine"]);
+        assert_eq!(classify(&mostly_japanese).language, Language::Mixed);
+        assert!(!is_mostly_non_japanese(&mostly_japanese));
+
+        assert!(is_mostly_non_japanese(&lines(&["Hello", "Fine"])));
+        assert!(!is_mostly_non_japanese(&lines(&["こんにちは", "", " "])));
+        assert!(!is_mostly_non_japanese(&[]));
+    }
+}
@@ -18,6 +18,7 @@ pub enum Command {
     Scrape { path: Option<PathBuf> },
     Fixtures { path: PathBuf },
     Move { path: PathBuf },
+    Fsck { fix: bool },
 }
 
 macro_rules! quick_exit {
@@ -35,6 +36,7 @@ commands:
   scrape   [path]   Scrapes and creates a fixture file from kitsunekko
   fixtures <path>   Loads a fixture from the given path
   move     [path]   Move directory entry paths to a new location
+  fsck     [--fix]  Checks the database against the subtitle directory
 
 options:
   -h, --help   Prints this help output
@@ -122,6 +124,13 @@ impl Command {
 
                     Self::Move { path }
                 }
+                "fsck" => match args.next().and_then(|s| s.into_string().ok()).as_deref() {
+                    None => Self::Fsck { fix: false },
+                    Some("--fix") => Self::Fsck { fix: true },
+                    Some(other) => {
+                        quick_exit!("unknown fsck option: {other}");
+                    }
+                },
                 "-h" | "--help" | "help" => {
                     println!("{}", HELP_OUTPUT);
                     std::process::exit(0);
@@ -45,6 +45,17 @@ pub struct Config {
     /// The server IP and port configuration
     #[serde(default)]
     pub server: ServerConfig,
+    /// Whether to convert scraped and fixture subtitle files to UTF-8.
+    ///
+    /// Uploads control this on a per-request basis instead.
+    #[serde(default = "crate::utils::default_true")]
+    pub normalize_encoding: bool,
+    /// Whether to watch the subtitle directory for files that are added, removed,
+    /// or renamed outside of the site and record them.
+    ///
+    /// This is only supported on Linux.
+    #[serde(default)]
+    pub watch_subtitle_path: bool,
     /// The secret key used for all crypto related functionality in the server.
     ///
     /// Microbenching makes it evident that cloning this without an Arc is around ~4x faster.
@@ -62,6 +73,8 @@ impl Config {
             tmdb_api_key: String::new(),
             webhook: None,
             server: ServerConfig::default(),
+            normalize_encoding: true,
+            watch_subtitle_path: false,
             secret_key: SecretKey::random()?,
         })
     }
@@ -535,6 +535,20 @@ pub fn is_unique_constraint_violation(e: &rusqlite::Error) -> bool {
     }
 }
 
+/// Opens an in-memory database with the schema from `main.sql` and the given seed data.
+#[cfg(test)]
+pub async fn test_database(seed: &'static str) -> Database {
+    Database::file(":memory:")
+        .connections(1)
+        .with_init(move |con| {
+            con.execute_batch(include_str!("../main.sql"))?;
+            con.execute_batch(seed)
+        })
+        .open()
+        .await
+        .expect("could not connect DB")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
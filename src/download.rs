@@ -1,6 +1,12 @@
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header::CONTENT_TYPE, StatusCode};
 use axum::response::{IntoResponse, Response};
 use std::path::{Component, Path, PathBuf};
+use tower::ServiceExt as _;
+use tower_http::services::ServeFile;
+
+use crate::storage::Storage;
 
 /// Ensures the path is valid and does not traverse outside of the base path.
 pub fn validate_path(base: &Path, requested: &str) -> Option<PathBuf> {
@@ -38,3 +44,24 @@ impl IntoResponse for DownloadResponse {
         }
     }
 }
+
+/// Responds with a stored file.
+///
+/// Files on the local filesystem are served directly, which supports range and
+/// conditional requests. Anything else is read and sent as a whole.
+pub async fn serve(storage: &dyn Storage, path: &Path, req: Request) -> DownloadResponse {
+    if let Some(local) = storage.local_path(path) {
+        return match ServeFile::new(local).oneshot(req).await {
+            Ok(res) => DownloadResponse::File(res.map(Body::new)),
+            Err(_) => DownloadResponse::NotFound,
+        };
+    }
+
+    let Ok(bytes) = storage.read(path).await else {
+        return DownloadResponse::NotFound;
+    };
+    let content_type = crate::subtitle::Format::from_path(path)
+        .map(|f| f.content_type())
+        .unwrap_or("application/octet-stream");
+    DownloadResponse::File(([(CONTENT_TYPE, content_type)], bytes).into_response())
+}
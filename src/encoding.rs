@@ -0,0 +1,135 @@
+//! Detection of the text encoding used by subtitle files.
+//!
+//! A lot of older Japanese subtitles are not encoded in UTF-8 but rather in
+//! Shift-JIS, EUC-JP, or UTF-16. These need to be detected and converted to
+//! UTF-8 otherwise they show up as mojibake.
+
+use std::{borrow::Cow, path::Path};
+
+use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_8};
+
+use crate::storage::Storage;
+
+/// Returns how many kana characters are in the string.
+///
+/// Text decoded with the wrong encoding tends to produce random kanji rather
+/// than kana, so this is used to decide between encodings that both decode
+/// without errors.
+fn kana_count(s: &str) -> usize {
+    s.chars().filter(|c| ('\u{3040}'..='\u{30ff}').contains(c)).count()
+}
+
+/// Detects the text encoding of the given bytes.
+///
+/// A byte order mark always takes priority. Otherwise UTF-8 is preferred
+/// followed by the legacy Japanese encodings. If the bytes are not valid
+/// in any of them then `None` is returned.
+pub fn detect(bytes: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        return Some(UTF_8);
+    }
+
+    // In case of a tie Shift-JIS wins since it's more common
+    [EUC_JP, SHIFT_JIS]
+        .into_iter()
+        .filter_map(|encoding| {
+            let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+            Some((encoding, kana_count(&text)))
+        })
+        .max_by_key(|(_, count)| *count)
+        .map(|(encoding, _)| encoding)
+}
+
+/// Decodes the bytes into a string using the detected encoding.
+///
+/// If no encoding could be detected then it's decoded as UTF-8 with replacement characters.
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    let encoding = detect(bytes).unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text
+}
+
+/// Converts the bytes to UTF-8 if they're in a different encoding.
+///
+/// Returns the detected source encoding along with the converted bytes.
+/// If the bytes are already UTF-8, or no encoding could be detected, then
+/// the bytes are returned unchanged.
+pub fn to_utf8(bytes: &[u8]) -> (Option<&'static Encoding>, Cow<'_, [u8]>) {
+    match detect(bytes) {
+        Some(encoding) if encoding != UTF_8 => {
+            let (text, _, _) = encoding.decode(bytes);
+            (Some(encoding), Cow::Owned(text.into_owned().into_bytes()))
+        }
+        encoding => (encoding, Cow::Borrowed(bytes)),
+    }
+}
+
+/// Returns whether a file at this path should have its encoding detected.
+///
+/// Only text based subtitle files are checked, since binary formats would
+/// be corrupted by this.
+pub fn is_text_file(path: &Path) -> bool {
+    crate::subtitle::Format::from_path(path).is_some()
+}
+
+/// Returns whether scraped and fixture files should be converted to UTF-8.
+///
+/// This is controlled by the `normalize_encoding` configuration option.
+pub fn is_normalization_enabled() -> bool {
+    crate::CONFIG.get().map(|c| c.normalize_encoding).unwrap_or(true)
+}
+
+/// Returns whether a scraped or fixture file at this path should be converted to UTF-8.
+pub fn should_normalize(path: &Path) -> bool {
+    is_normalization_enabled() && is_text_file(path)
+}
+
+/// Converts every text subtitle file in the directory to UTF-8 in place.
+///
+/// Returns the number of files that were converted.
+pub async fn normalize_directory(storage: &dyn Storage, path: &Path) -> std::io::Result<usize> {
+    let mut converted = 0;
+    for file in storage.list(path).await? {
+        let path = path.join(&file.name);
+        if !is_text_file(&path) {
+            continue;
+        }
+
+        let bytes = storage.read(&path).await?;
+        if let (Some(encoding), Cow::Owned(bytes)) = to_utf8(&bytes) {
+            tracing::debug!(path = %path.display(), encoding = encoding.name(), "Converted file to UTF-8");
+            storage.write(&path, &bytes, true).await?;
+            converted += 1;
+        }
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "1\n00:00:01,000 --> 00:00:02,000\nこんにちは、世界。ひらがなとカタカナ\n";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(TEXT.as_bytes()), Some(UTF_8));
+
+        let (sjis, _, _) = SHIFT_JIS.encode(TEXT);
+        assert_eq!(detect(&sjis), Some(SHIFT_JIS));
+        assert_eq!(to_utf8(&sjis).1.as_ref(), TEXT.as_bytes());
+
+        let (eucjp, _, _) = EUC_JP.encode(TEXT);
+        assert_eq!(detect(&eucjp), Some(EUC_JP));
+        assert_eq!(decode(&eucjp), TEXT);
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend(TEXT.encode_utf16().flat_map(|c| c.to_le_bytes()));
+        assert_eq!(detect(&utf16), Some(encoding_rs::UTF_16LE));
+        assert_eq!(to_utf8(&utf16).1.as_ref(), TEXT.as_bytes());
+    }
+}
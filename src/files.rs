@@ -0,0 +1,615 @@
+//! Persistent metadata for the files that belong to a directory entry.
+//!
+//! The filesystem is still the source of truth for the actual file contents,
+//! the `file` table just stores the information that the filesystem cannot
+//! tell us, e.g. who uploaded it and what its hash is.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{database::Table, relations::Range, storage::Storage, Database};
+
+/// A row in the `file` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// The ID of the file. This is 0 if the file has not been inserted yet.
+    pub id: i64,
+    /// The directory entry that this file belongs to.
+    pub entry_id: i64,
+    /// The file's name within the directory entry.
+    pub name: String,
+    /// The file's size in bytes.
+    pub size: u64,
+    /// The SHA-256 hash of the file's contents, as a lowercase hexadecimal string.
+    pub sha256: String,
+    /// The account that uploaded this file, if known.
+    pub uploader_id: Option<i64>,
+    /// When the file was first recorded.
+    pub created_at: OffsetDateTime,
+    /// When the file was last modified in storage as of the time it was hashed.
+    pub modified_at: Option<OffsetDateTime>,
+    /// The episode range this file is for, based off of the filename.
+    pub episode: Option<Range>,
+}
+
+impl Table for StoredFile {
+    const NAME: &'static str = "file";
+
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "entry_id",
+        "name",
+        "size",
+        "sha256",
+        "uploader_id",
+        "created_at",
+        "modified_at",
+        "episode_begin",
+        "episode_end",
+    ];
+
+    type Id = i64;
+
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let begin: Option<u16> = row.get("episode_begin")?;
+        let end: Option<u16> = row.get("episode_end")?;
+        Ok(Self {
+            id: row.get("id")?,
+            entry_id: row.get("entry_id")?,
+            name: row.get("name")?,
+            size: row.get("size")?,
+            sha256: row.get("sha256")?,
+            uploader_id: row.get("uploader_id")?,
+            created_at: row.get("created_at")?,
+            modified_at: row.get("modified_at")?,
+            episode: begin.map(|begin| match end {
+                Some(end) => Range::Inclusive { begin, end },
+                None => Range::Number { value: begin },
+            }),
+        })
+    }
+}
+
+/// Returns the SHA-256 hash of the given bytes as a lowercase hexadecimal string.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Returns the episode range of a file based off of its filename.
+///
+/// This is a best-effort guess.
+pub fn episode_range(filename: &str) -> Option<Range> {
+    let parsed = anitomy::parse(filename);
+    let mut episodes = parsed.iter().filter(|p| p.kind() == anitomy::ElementKind::Episode);
+    let begin = episodes.next().map(|e| e.value()).and_then(|s| s.parse::<u16>().ok())?;
+    let end = episodes.next().map(|e| e.value()).and_then(|s| s.parse::<u16>().ok());
+    match end {
+        Some(end) => Some(Range::Inclusive { begin, end }),
+        None => Some(Range::Number { value: begin }),
+    }
+}
+
+/// Returns the series title of a release based off of its filename.
+///
+/// This is a best-effort guess.
+pub fn release_title(filename: &str) -> Option<String> {
+    let parsed = anitomy::parse(filename);
+    let title = parsed.iter().find(|p| p.kind() == anitomy::ElementKind::Title)?;
+    let title = title.value().trim();
+    (!title.is_empty()).then(|| title.to_owned())
+}
+
+fn episode_columns(episode: Option<Range>) -> (Option<u16>, Option<u16>) {
+    match episode {
+        Some(Range::Inclusive { begin, end }) => (Some(begin), Some(end)),
+        Some(range) => (Some(range.begin()), None),
+        None => (None, None),
+    }
+}
+
+impl StoredFile {
+    /// Creates a pending file record from the file's contents.
+    pub fn new(entry_id: i64, name: String, bytes: &[u8], uploader_id: Option<i64>) -> Self {
+        Self::with_hash(entry_id, name, bytes.len() as u64, sha256_hex(bytes), uploader_id)
+    }
+
+    /// Creates a pending file record from an already computed hash.
+    pub fn with_hash(entry_id: i64, name: String, size: u64, sha256: String, uploader_id: Option<i64>) -> Self {
+        Self {
+            id: 0,
+            entry_id,
+            episode: episode_range(&name),
+            name,
+            size,
+            sha256,
+            uploader_id,
+            created_at: OffsetDateTime::now_utc(),
+            modified_at: None,
+        }
+    }
+
+    /// Inserts or replaces the record for this file.
+    ///
+    /// Since files are unique by name within an entry, uploading a file with
+    /// the same name replaces the older record.
+    pub fn upsert(&self, conn: &rusqlite::Connection) -> rusqlite::Result<i64> {
+        let query = r#"
+            INSERT INTO file(entry_id, name, size, sha256, uploader_id, created_at, modified_at, episode_begin, episode_end)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(entry_id, name) DO UPDATE
+            SET size = EXCLUDED.size, sha256 = EXCLUDED.sha256, uploader_id = EXCLUDED.uploader_id,
+                created_at = EXCLUDED.created_at, modified_at = EXCLUDED.modified_at,
+                episode_begin = EXCLUDED.episode_begin, episode_end = EXCLUDED.episode_end
+            RETURNING id
+        "#;
+        let (begin, end) = episode_columns(self.episode);
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(
+            (
+                self.entry_id,
+                &self.name,
+                self.size,
+                &self.sha256,
+                self.uploader_id,
+                self.created_at,
+                self.modified_at,
+                begin,
+                end,
+            ),
+            |row| row.get(0),
+        )
+    }
+}
+
+/// Records the given files in the database.
+pub async fn record(database: &Database, files: Vec<StoredFile>) -> rusqlite::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    database
+        .call(move |conn| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            for file in &files {
+                file.upsert(&tx)?;
+            }
+            tx.commit()
+        })
+        .await
+}
+
+/// Returns the ID of the directory entry whose files are stored in the given directory.
+pub async fn entry_id_by_path(database: &Database, path: &Path) -> rusqlite::Result<Option<i64>> {
+    let Some(path) = path.to_str().map(str::to_owned) else {
+        return Ok(None);
+    };
+    database
+        .get_row("SELECT id FROM directory_entry WHERE path = ?", [path], |row| {
+            row.get(0)
+        })
+        .await
+        .optional()
+}
+
+/// Records a file that was just written into an entry's directory outside of an upload, e.g. by a scraper.
+///
+/// The file is hashed and its lines are indexed right away rather than on the next
+/// [`sync_directory`]. Nothing is recorded if the directory does not belong to an entry
+/// yet, since the entry's files are synced once it's created.
+pub async fn record_written(
+    database: &Database,
+    storage: &dyn Storage,
+    path: &Path,
+    bytes: &[u8],
+) -> anyhow::Result<()> {
+    let (Some(directory), Some(name)) = (path.parent(), path.file_name().and_then(|s| s.to_str())) else {
+        return Ok(());
+    };
+    let Some(entry_id) = entry_id_by_path(database, directory).await? else {
+        return Ok(());
+    };
+
+    let mut record = StoredFile::new(entry_id, name.to_owned(), bytes, None);
+    record.modified_at = storage.stat(path).await?.map(|m| m.modified);
+    let lines = crate::lines::extract(name, bytes);
+    database
+        .call(move |conn| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            let file_id = record.upsert(&tx)?;
+            if let Some(lines) = lines {
+                crate::lines::replace(&tx, file_id, &lines)?;
+            }
+            tx.commit()
+        })
+        .await?;
+    Ok(())
+}
+
+/// Updates the record of a renamed file.
+pub async fn rename(database: &Database, entry_id: i64, from: String, to: String) -> rusqlite::Result<()> {
+    let (begin, end) = episode_columns(episode_range(&to));
+    database
+        .execute(
+            "UPDATE file SET name = ?, episode_begin = ?, episode_end = ? WHERE entry_id = ? AND name = ?",
+            (to, begin, end, entry_id, from),
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Updates the record of a file that was moved to another entry.
+pub async fn relocate(database: &Database, from_entry_id: i64, to_entry_id: i64, name: String) -> rusqlite::Result<()> {
+    database
+        .execute(
+            "UPDATE file SET entry_id = ? WHERE entry_id = ? AND name = ?",
+            (to_entry_id, from_entry_id, name),
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Removes the record of a file that no longer belongs to the entry.
+///
+/// The removed record is returned, if any.
+pub async fn remove(database: &Database, entry_id: i64, name: String) -> rusqlite::Result<Option<StoredFile>> {
+    database
+        .get(
+            "DELETE FROM file WHERE entry_id = ? AND name = ? RETURNING *",
+            (entry_id, name),
+        )
+        .await
+}
+
+/// A file that is already stored somewhere in the library.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DuplicateFile {
+    /// The ID of the entry that holds the file.
+    pub entry_id: i64,
+    /// The name of the entry that holds the file.
+    pub entry_name: String,
+    /// The file's name within the entry.
+    pub name: String,
+}
+
+impl std::fmt::Display for DuplicateFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in {} (/entry/{})", self.name, self.entry_name, self.entry_id)
+    }
+}
+
+impl DuplicateFile {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            entry_id: row.get("entry_id")?,
+            entry_name: row.get("entry_name")?,
+            name: row.get("name")?,
+        })
+    }
+}
+
+/// Finds the files in the library that have any of the given hashes.
+///
+/// The returned map is keyed by hash. If multiple files share a hash then
+/// the oldest one is returned.
+pub async fn find_by_hashes(
+    database: &Database,
+    hashes: Vec<String>,
+) -> rusqlite::Result<HashMap<String, DuplicateFile>> {
+    if hashes.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = String::from(
+        r#"
+        SELECT file.sha256, file.entry_id, file.name, directory_entry.name AS entry_name
+        FROM file INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+        WHERE file.sha256 IN ("#,
+    );
+    for index in 0..hashes.len() {
+        if index != 0 {
+            query.push(',');
+        }
+        query.push('?');
+    }
+    query.push_str(") ORDER BY file.created_at DESC, file.id DESC");
+
+    database
+        .call(move |conn| -> rusqlite::Result<HashMap<String, DuplicateFile>> {
+            let mut stmt = conn.prepare(&query)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(hashes))?;
+            let mut result = HashMap::new();
+            while let Some(row) = rows.next()? {
+                // Later rows are older so they overwrite the newer ones
+                result.insert(row.get("sha256")?, DuplicateFile::from_row(row)?);
+            }
+            Ok(result)
+        })
+        .await
+}
+
+/// A set of files in the library that have the exact same contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateGroup {
+    /// The SHA-256 hash shared by every file.
+    pub sha256: String,
+    /// The size of each file in bytes.
+    pub size: u64,
+    /// The files with this hash, oldest first.
+    pub files: Vec<DuplicateFile>,
+}
+
+/// Returns every set of duplicate files in the library, largest files first.
+pub async fn duplicate_groups(database: &Database) -> rusqlite::Result<Vec<DuplicateGroup>> {
+    let query = r#"
+        SELECT file.sha256, file.size, file.entry_id, file.name, directory_entry.name AS entry_name
+        FROM file INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+        WHERE file.sha256 IN (SELECT sha256 FROM file GROUP BY sha256 HAVING COUNT(*) > 1)
+        ORDER BY file.size DESC, file.sha256, file.created_at, file.id
+    "#;
+    database
+        .call(move |conn| -> rusqlite::Result<Vec<DuplicateGroup>> {
+            let mut stmt = conn.prepare(query)?;
+            let mut rows = stmt.query([])?;
+            let mut groups: Vec<DuplicateGroup> = Vec::new();
+            while let Some(row) = rows.next()? {
+                let sha256: String = row.get("sha256")?;
+                let file = DuplicateFile::from_row(row)?;
+                match groups.last_mut() {
+                    Some(group) if group.sha256 == sha256 => group.files.push(file),
+                    _ => groups.push(DuplicateGroup {
+                        sha256,
+                        size: row.get("size")?,
+                        files: vec![file],
+                    }),
+                }
+            }
+            Ok(groups)
+        })
+        .await
+}
+
+/// A file found in an entry's directory along with its stored metadata.
+#[derive(Debug, Clone)]
+pub struct ListedFile {
+    /// The stored record for this file.
+    pub record: StoredFile,
+    /// The date the file was last modified on disk.
+    pub last_modified: OffsetDateTime,
+}
+
+/// Lists the files in the entry's directory and reconciles them with the `file` table.
+///
+/// Files that exist on disk but have no record, e.g. because they were scraped or
+/// predate the table, are hashed and recorded without an uploader. Files whose size
+/// or modification date no longer matches their record, e.g. because they were
+/// overwritten outside of the site, are hashed again. The lines of every newly hashed
+/// text subtitle file are indexed for search. Files that can't be read keep their
+/// previous record, or are left out if they have none. Records that no longer have a
+/// file on disk are removed.
+pub async fn sync_directory(
+    database: &Database,
+    storage: &Arc<dyn Storage>,
+    entry_id: i64,
+    path: &Path,
+) -> anyhow::Result<Vec<ListedFile>> {
+    let stored: Vec<StoredFile> = database
+        .all("SELECT * FROM file WHERE entry_id = ?", [entry_id])
+        .await?;
+    let mut stored: HashMap<String, StoredFile> = stored.into_iter().map(|f| (f.name.clone(), f)).collect();
+
+    let mut listed = Vec::new();
+    let mut changed = Vec::new();
+    let mut seen = HashSet::new();
+    for file in storage.list(path).await? {
+        seen.insert(file.name.clone());
+        match stored.remove(&file.name) {
+            Some(record) if record.size == file.size && record.modified_at == Some(file.modified) => {
+                listed.push(ListedFile {
+                    record,
+                    last_modified: file.modified,
+                })
+            }
+            previous => changed.push((file, previous)),
+        }
+    }
+    let stale = stored
+        .into_keys()
+        .filter(|name| !seen.contains(name))
+        .collect::<Vec<_>>();
+
+    // Files are read one at a time so only a single file is ever held in memory
+    let storage = Arc::clone(storage);
+    let directory = path.to_path_buf();
+    let runtime = tokio::runtime::Handle::current();
+    let (pending, unreadable) = tokio::task::spawn_blocking(move || {
+        let mut pending = Vec::with_capacity(changed.len());
+        let mut unreadable = Vec::new();
+        for (file, previous) in changed {
+            let bytes = match runtime.block_on(storage.read(&directory.join(&file.name))) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    tracing::warn!(error=%e, entry_id, name=%file.name, "Could not read file to hash it");
+                    // The stale record is still listed so that the listing agrees with the database
+                    if let Some(record) = previous {
+                        unreadable.push(ListedFile {
+                            record,
+                            last_modified: file.modified,
+                        });
+                    }
+                    continue;
+                }
+            };
+            let uploader_id = previous.as_ref().and_then(|r| r.uploader_id);
+            let lines = crate::lines::extract(&file.name, &bytes);
+            let mut record = StoredFile::new(entry_id, file.name, &bytes, uploader_id);
+            record.created_at = previous.map(|r| r.created_at).unwrap_or(file.modified);
+            record.modified_at = Some(file.modified);
+            let last_modified = file.modified;
+            pending.push((ListedFile { record, last_modified }, lines));
+        }
+        (pending, unreadable)
+    })
+    .await?;
+    listed.extend(unreadable);
+
+    if pending.is_empty() && stale.is_empty() {
+        return Ok(listed);
+    }
+
+    let pending = database
+        .call(move |conn| -> rusqlite::Result<Vec<ListedFile>> {
+            let tx = conn.transaction()?;
+            let mut result = Vec::with_capacity(pending.len());
+            for (mut file, lines) in pending {
+                file.record.id = file.record.upsert(&tx)?;
+                if let Some(lines) = lines {
+                    crate::lines::replace(&tx, file.record.id, &lines)?;
+                }
+                result.push(file);
+            }
+            {
+                let mut stmt = tx.prepare_cached("DELETE FROM file WHERE entry_id = ? AND name = ?")?;
+                for name in stale {
+                    stmt.execute((entry_id, name))?;
+                }
+            }
+            tx.commit()?;
+            Ok(result)
+        })
+        .await?;
+
+    listed.extend(pending);
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+
+    async fn database() -> Database {
+        test_database(
+            "INSERT INTO account(id, name, password) VALUES (1, 'user', '');
+             INSERT INTO directory_entry(id, path, name) VALUES (1, 'a', 'a'), (2, 'b', 'b');",
+        )
+        .await
+    }
+
+    async fn names(database: &Database, entry_id: i64) -> Vec<String> {
+        let files: Vec<StoredFile> = database
+            .all("SELECT * FROM file WHERE entry_id = ? ORDER BY name", [entry_id])
+            .await
+            .unwrap();
+        files.into_iter().map(|f| f.name).collect()
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_records() {
+        let db = database().await;
+        let files = vec![
+            StoredFile::new(1, "foo.srt".into(), b"foo", Some(1)),
+            StoredFile::new(1, "bar.srt".into(), b"bar", None),
+        ];
+        record(&db, files).await.unwrap();
+        assert_eq!(names(&db, 1).await, ["bar.srt", "foo.srt"]);
+
+        // re-recording the same name replaces the record
+        record(&db, vec![StoredFile::new(1, "foo.srt".into(), b"foo2", None)])
+            .await
+            .unwrap();
+        let foo: StoredFile = db
+            .get("SELECT * FROM file WHERE name = 'foo.srt'", [])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(foo.sha256, sha256_hex(b"foo2"));
+        assert_eq!(foo.size, 4);
+        assert_eq!(foo.uploader_id, None);
+
+        rename(&db, 1, "bar.srt".into(), "baz.srt".into()).await.unwrap();
+        assert_eq!(names(&db, 1).await, ["baz.srt", "foo.srt"]);
+
+        relocate(&db, 1, 2, "baz.srt".into()).await.unwrap();
+        assert_eq!(names(&db, 1).await, ["foo.srt"]);
+        assert_eq!(names(&db, 2).await, ["baz.srt"]);
+
+        record(&db, vec![StoredFile::new(1, "qux.srt".into(), b"baz", None)])
+            .await
+            .unwrap();
+        let found = find_by_hashes(&db, vec![sha256_hex(b"bar"), sha256_hex(b"missing")])
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[&sha256_hex(b"bar")].entry_id, 2);
+        assert_eq!(found[&sha256_hex(b"bar")].name, "baz.srt");
+
+        record(&db, vec![StoredFile::new(1, "copy.srt".into(), b"bar", None)])
+            .await
+            .unwrap();
+        let groups = duplicate_groups(&db).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].sha256, sha256_hex(b"bar"));
+        assert_eq!(groups[0].files.len(), 2);
+        assert_eq!(groups[0].files[0].name, "baz.srt");
+        remove(&db, 1, "copy.srt".into()).await.unwrap();
+        remove(&db, 1, "qux.srt".into()).await.unwrap();
+
+        let removed = remove(&db, 1, "foo.srt".into()).await.unwrap();
+        assert_eq!(removed.map(|f| f.name).as_deref(), Some("foo.srt"));
+        assert!(names(&db, 1).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_directory() {
+        let db = database().await;
+        let storage: Arc<dyn Storage> = Arc::new(crate::storage::Memory::default());
+        let directory = Path::new("a");
+        storage.create_directory(directory).await.unwrap();
+
+        // files written outside of an upload are recorded right away
+        let path = directory.join("foo.srt");
+        storage.write(&path, b"foo", false).await.unwrap();
+        record_written(&db, storage.as_ref(), &path, b"foo").await.unwrap();
+        let foo: StoredFile = db.get("SELECT * FROM file", []).await.unwrap().unwrap();
+        assert_eq!(foo.sha256, sha256_hex(b"foo"));
+        assert!(foo.modified_at.is_some());
+
+        let listed = sync_directory(&db, &storage, 1, directory).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].record, foo);
+
+        // overwriting with a file of the same size is still noticed
+        storage.write(&path, b"bar", true).await.unwrap();
+        let listed = sync_directory(&db, &storage, 1, directory).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].record.sha256, sha256_hex(b"bar"));
+        assert_eq!(listed[0].record.created_at, foo.created_at);
+
+        // directories that don't belong to an entry are not recorded
+        let other = Path::new("c");
+        storage.create_directory(other).await.unwrap();
+        storage.write(&other.join("baz.srt"), b"baz", false).await.unwrap();
+        record_written(&db, storage.as_ref(), &other.join("baz.srt"), b"baz")
+            .await
+            .unwrap();
+        assert_eq!(names(&db, 1).await, ["foo.srt"]);
+        assert!(names(&db, 2).await.is_empty());
+    }
+}
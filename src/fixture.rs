@@ -22,20 +22,22 @@ pub struct Fixture {
 }
 
 pub async fn commit_fixtures(state: &AppState, fixtures: Vec<Fixture>) -> anyhow::Result<()> {
-    state
+    let entries = state
         .database()
-        .call(move |conn| -> rusqlite::Result<()> {
+        .call(move |conn| -> rusqlite::Result<Vec<(i64, PathBuf)>> {
             let sql = r#"
                 INSERT INTO directory_entry(path, last_updated_at, flags, anilist_id, tmdb_id, english_name, japanese_name, name)
                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)
                 ON CONFLICT DO UPDATE
                 SET last_updated_at = MAX(last_updated_at, EXCLUDED.last_updated_at)
+                RETURNING id
             "#;
             let tx = conn.transaction()?;
+            let mut entries = Vec::with_capacity(fixtures.len());
             {
                 let mut stmt = tx.prepare(sql)?;
                 for fixture in fixtures {
-                    stmt.execute((
+                    let id = stmt.query_row((
                         fixture.path.to_string_lossy(),
                         fixture.last_updated_at,
                         fixture.flags,
@@ -44,13 +46,29 @@ pub async fn commit_fixtures(state: &AppState, fixtures: Vec<Fixture>) -> anyhow
                         fixture.title.english,
                         fixture.title.native,
                         fixture.title.romaji,
-                    ))?;
+                    ), |row| row.get::<_, i64>(0))?;
+                    entries.push((id, fixture.path));
                 }
             }
             tx.commit()?;
-            Ok(())
+            Ok(entries)
         })
         .await?;
     state.cached_directories().invalidate().await;
+
+    let normalize = crate::encoding::is_normalization_enabled();
+    let storage = state.storage().as_ref();
+    for (entry_id, path) in entries {
+        if normalize {
+            match crate::encoding::normalize_directory(storage, &path).await {
+                Ok(0) => {}
+                Ok(converted) => tracing::info!(entry_id, converted, "Converted fixture files to UTF-8"),
+                Err(e) => tracing::warn!(error=%e, entry_id, "Could not convert fixture files to UTF-8"),
+            }
+        }
+        if let Err(e) = crate::files::sync_directory(state.database(), state.storage(), entry_id, &path).await {
+            tracing::warn!(error=%e, entry_id, "Could not record fixture files");
+        }
+    }
     Ok(())
 }
@@ -129,21 +129,16 @@ impl<'a> std::fmt::Display for FlashMessageHtml<'a> {
 }
 
 /// The level for the flash message.
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
 #[repr(u8)]
 pub enum FlashLevel {
+    #[default]
     Info,
     Success,
     Warning,
     Error,
 }
 
-impl Default for FlashLevel {
-    fn default() -> Self {
-        Self::Info
-    }
-}
-
 impl std::fmt::Display for FlashLevel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -244,7 +239,7 @@ where
             .extensions
             .get::<Flasher>()
             .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
-            .map(|x| x.clone())
+            .cloned()
     }
 }
 
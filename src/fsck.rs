@@ -0,0 +1,292 @@
+//! Reconciling the database with what's actually in the subtitle directory.
+//!
+//! This backs the `fsck` subcommand. Checking only reports the problems that
+//! were found, repairing them is opt-in and every repair is recorded in the
+//! audit log.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    audit::{AuditLogEntry, FsckAction, FsckRepair},
+    models::{DirectoryEntry, EntryFlags},
+    storage::Storage,
+    trash::Trash,
+    Database,
+};
+
+/// How far ahead of `last_updated_at` a file can be modified before it's considered stale.
+///
+/// Uploads update the entry right after the files are written so there's always some leeway.
+const STALE_THRESHOLD: Duration = Duration::minutes(1);
+
+/// An inconsistency between the database and the subtitle directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// An entry whose directory no longer exists.
+    MissingDirectory { entry_id: i64, name: String, path: PathBuf },
+    /// A directory within the subtitle directory that has no entry.
+    OrphanDirectory { path: PathBuf },
+    /// An entry with a file that was modified after its `last_updated_at`.
+    StaleLastUpdated {
+        entry_id: i64,
+        name: String,
+        recorded: OffsetDateTime,
+        newest: OffsetDateTime,
+    },
+    /// A trash record whose trashed file no longer exists.
+    BrokenTrashRecord { filename: PathBuf },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingDirectory { entry_id, name, path } => {
+                write!(
+                    f,
+                    "entry {entry_id} ({name}) is missing its directory {}",
+                    path.display()
+                )
+            }
+            Self::OrphanDirectory { path } => write!(f, "directory {} has no entry", path.display()),
+            Self::StaleLastUpdated {
+                entry_id,
+                name,
+                recorded,
+                newest,
+            } => write!(
+                f,
+                "entry {entry_id} ({name}) was last updated at {recorded} but has a file modified at {newest}"
+            ),
+            Self::BrokenTrashRecord { filename } => {
+                write!(f, "trash record {} has no trashed file", filename.display())
+            }
+        }
+    }
+}
+
+/// Returns the modification date of the newest file in the directory.
+async fn newest_file(storage: &dyn Storage, path: &Path) -> std::io::Result<Option<OffsetDateTime>> {
+    Ok(storage.list(path).await?.into_iter().map(|f| f.modified).max())
+}
+
+/// Checks the database against the subtitle directory at `root` and the trash.
+pub async fn check(database: &Database, storage: &Arc<dyn Storage>, root: &Path) -> anyhow::Result<Vec<Problem>> {
+    let entries: Vec<DirectoryEntry> = database.all("SELECT * FROM directory_entry", []).await?;
+    let mut problems = Vec::new();
+    for entry in entries.iter() {
+        let is_directory = storage.stat(&entry.path).await?.is_some_and(|m| m.is_directory);
+        if !is_directory {
+            problems.push(Problem::MissingDirectory {
+                entry_id: entry.id,
+                name: entry.name.clone(),
+                path: entry.path.clone(),
+            });
+            continue;
+        }
+
+        if let Some(newest) = newest_file(storage.as_ref(), &entry.path).await? {
+            if newest - entry.last_updated_at > STALE_THRESHOLD {
+                problems.push(Problem::StaleLastUpdated {
+                    entry_id: entry.id,
+                    name: entry.name.clone(),
+                    recorded: entry.last_updated_at,
+                    newest,
+                });
+            }
+        }
+    }
+
+    let known = entries.iter().map(|e| e.path.as_path()).collect::<HashSet<_>>();
+    let mut orphans = storage
+        .list_directories(root)
+        .await?
+        .into_iter()
+        .filter(|name| !name.starts_with('.'))
+        .map(|name| root.join(name))
+        .filter(|path| !known.contains(path.as_path()))
+        .collect::<Vec<_>>();
+    orphans.sort();
+    problems.extend(orphans.into_iter().map(|path| Problem::OrphanDirectory { path }));
+
+    let trash = Trash::new(storage.clone()).await?;
+    let mut broken = trash.broken_records().await?;
+    broken.sort();
+    problems.extend(
+        broken
+            .into_iter()
+            .map(|filename| Problem::BrokenTrashRecord { filename }),
+    );
+    Ok(problems)
+}
+
+/// Returns the name and flags of an entry adopted from the given directory name.
+///
+/// Directories of non-anime entries are prefixed, both when created on the site and when scraped.
+fn adopted_entry(directory_name: &str) -> (String, EntryFlags) {
+    let mut flags = EntryFlags::new();
+    flags.set_unverified(true);
+    let name = match directory_name
+        .strip_prefix("[drama] ")
+        .or_else(|| directory_name.strip_prefix("jpsubbers_"))
+    {
+        Some(name) => {
+            flags.set_anime(false);
+            name
+        }
+        None => directory_name,
+    };
+    (name.to_owned(), flags)
+}
+
+/// Repairs a single problem, returning the audit log entry that records it.
+async fn repair(database: &Database, storage: &Arc<dyn Storage>, problem: &Problem) -> anyhow::Result<AuditLogEntry> {
+    let entry = match problem {
+        Problem::MissingDirectory { entry_id, path, .. } => {
+            storage.create_directory(path).await?;
+            let data = FsckRepair {
+                action: FsckAction::CreateDirectory,
+                path: path.display().to_string(),
+            };
+            AuditLogEntry::new(data).with_entry(*entry_id)
+        }
+        Problem::OrphanDirectory { path } => {
+            let directory_name = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow::anyhow!("directory name is not UTF-8"))?;
+            let (name, flags) = adopted_entry(directory_name);
+            let last_updated_at = newest_file(storage.as_ref(), path)
+                .await?
+                .unwrap_or_else(OffsetDateTime::now_utc);
+            let entry_id: i64 = database
+                .get_row(
+                    "INSERT INTO directory_entry(path, last_updated_at, flags, name) VALUES (?, ?, ?, ?) RETURNING id",
+                    (path.display().to_string(), last_updated_at, flags, name),
+                    |row| row.get(0),
+                )
+                .await?;
+            crate::files::sync_directory(database, storage, entry_id, path).await?;
+            let data = FsckRepair {
+                action: FsckAction::AdoptDirectory,
+                path: path.display().to_string(),
+            };
+            AuditLogEntry::new(data).with_entry(entry_id)
+        }
+        Problem::StaleLastUpdated { entry_id, newest, .. } => {
+            let path: String = database
+                .get_row(
+                    "UPDATE directory_entry SET last_updated_at = ? WHERE id = ? RETURNING path",
+                    (*newest, *entry_id),
+                    |row| row.get(0),
+                )
+                .await?;
+            let data = FsckRepair {
+                action: FsckAction::UpdateLastUpdated,
+                path,
+            };
+            AuditLogEntry::new(data).with_entry(*entry_id)
+        }
+        Problem::BrokenTrashRecord { filename } => {
+            let trash = Trash::new(storage.clone()).await?;
+            trash.remove_record(filename).await?;
+            let data = FsckRepair {
+                action: FsckAction::PruneTrashRecord,
+                path: trash.info_path().join(filename).display().to_string(),
+            };
+            AuditLogEntry::new(data)
+        }
+    };
+    Ok(entry)
+}
+
+/// Repairs every given problem and records each repair in the audit log.
+///
+/// Problems that could not be repaired are logged and skipped. Returns the number of repairs made.
+pub async fn repair_all(database: &Database, storage: &Arc<dyn Storage>, problems: &[Problem]) -> usize {
+    let mut repaired = 0;
+    let mut last_id = 0;
+    for problem in problems {
+        match repair(database, storage, problem).await {
+            Ok(mut entry) => {
+                // Audit log IDs are millisecond timestamps so repairs made in quick succession would clash
+                entry.id = entry.id.max(last_id + 1);
+                last_id = entry.id;
+                if let Err(e) = entry.insert(database).await {
+                    tracing::error!(error=%e, "Could not insert audit log entry");
+                }
+                repaired += 1;
+            }
+            Err(e) => tracing::warn!(error=%e, %problem, "Could not repair problem"),
+        }
+    }
+    repaired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+    use crate::storage::Memory;
+
+    async fn database() -> Database {
+        test_database(
+            "INSERT INTO directory_entry(id, path, last_updated_at, name)
+             VALUES (1, '/library/present', '2020-01-01 00:00:00', 'present'),
+                    (2, '/library/missing', '2020-01-01 00:00:00', 'missing');",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_check_and_repair() {
+        let db = database().await;
+        let storage: Arc<dyn Storage> = Arc::new(Memory::default());
+        let root = Path::new("/library");
+        storage.create_directory(&root.join("present")).await.unwrap();
+        storage.create_directory(&root.join("[drama] orphan")).await.unwrap();
+        storage
+            .write(&root.join("present/01.srt"), b"hello", false)
+            .await
+            .unwrap();
+        let trash = Trash::new(storage.clone()).await.unwrap();
+        storage
+            .write(&trash.info_path().join("1_gone.srt"), b"{}", false)
+            .await
+            .unwrap();
+
+        let problems = check(&db, &storage, root).await.unwrap();
+        assert_eq!(problems.len(), 4);
+        assert!(matches!(&problems[0], Problem::StaleLastUpdated { entry_id: 1, .. }));
+        assert!(matches!(&problems[1], Problem::MissingDirectory { entry_id: 2, .. }));
+        assert_eq!(
+            problems[2],
+            Problem::OrphanDirectory {
+                path: root.join("[drama] orphan")
+            }
+        );
+        assert!(matches!(&problems[3], Problem::BrokenTrashRecord { .. }));
+
+        assert_eq!(repair_all(&db, &storage, &problems).await, 4);
+        assert!(check(&db, &storage, root).await.unwrap().is_empty());
+
+        let adopted: DirectoryEntry = db
+            .get("SELECT * FROM directory_entry WHERE name = 'orphan'", [])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(adopted.flags.is_unverified());
+        assert!(!adopted.flags.is_anime());
+
+        let repairs: i64 = db
+            .get_row("SELECT COUNT(*) FROM audit_log", [], |row| row.get(0))
+            .await
+            .unwrap();
+        assert_eq!(repairs, 4);
+    }
+}
@@ -24,7 +24,7 @@ There are a few caveats:
 3) There is no last_modified information so each scrape loop will force a full crawl either way.
 */
 
-use std::{collections::HashMap, io::Write, path::PathBuf, sync::OnceLock};
+use std::{collections::HashMap, path::PathBuf, sync::OnceLock};
 
 use anyhow::{bail, Context};
 use regex::Regex;
@@ -87,13 +87,14 @@ impl From<File> for Directory {
 }
 
 impl File {
-    async fn download(self, client: reqwest::Client, directory: PathBuf) -> anyhow::Result<bool> {
+    async fn download(self, state: AppState, directory: PathBuf) -> anyhow::Result<bool> {
+        let storage = state.storage().as_ref();
         let path = directory.join(&self.name);
-        if path.exists() {
+        if crate::storage::exists(storage, &path).await {
             return Ok(false);
         }
 
-        let resp = client.get(&self.url).send().await?;
+        let resp = state.client.get(&self.url).send().await?;
         if let Some(bytes) = resp.content_length() {
             if bytes >= crate::MAX_UPLOAD_SIZE {
                 bail!(
@@ -105,9 +106,18 @@ impl File {
         }
 
         let bytes = resp.bytes().await?;
-        let mut file =
-            std::fs::File::create(&path).with_context(|| format!("Could not create file at {}", path.display()))?;
-        file.write_all(&bytes)?;
+        let bytes = if crate::encoding::should_normalize(&path) {
+            crate::encoding::to_utf8(&bytes).1
+        } else {
+            std::borrow::Cow::Borrowed(bytes.as_ref())
+        };
+        storage
+            .write(&path, &bytes, false)
+            .await
+            .with_context(|| format!("Could not create file at {}", path.display()))?;
+        crate::files::record_written(state.database(), storage, &path, &bytes)
+            .await
+            .with_context(|| format!("Could not record file at {}", path.display()))?;
         Ok(true)
     }
 
@@ -129,10 +139,10 @@ impl Directory {
     }
 
     /// Concurrently downloads every file in this directory
-    pub async fn download_files(self, client: &reqwest::Client, directory: PathBuf) -> anyhow::Result<usize> {
+    pub async fn download_files(self, state: &AppState, directory: PathBuf) -> anyhow::Result<usize> {
         let mut set = JoinSet::new();
         for file in self.files {
-            set.spawn(file.download(client.clone(), directory.clone()));
+            set.spawn(file.download(state.clone(), directory.clone()));
         }
         let mut downloaded = 0;
         while let Some(result) = set.join_next().await {
@@ -299,16 +309,14 @@ pub async fn scrape(state: &AppState) -> anyhow::Result<Vec<Fixture>> {
             }
         };
 
-        if !directory.exists() {
-            if let Err(e) = std::fs::create_dir_all(&directory) {
-                if e.kind() != std::io::ErrorKind::AlreadyExists {
-                    return Err(e).with_context(|| format!("Could not create directory {}", directory.display()));
-                }
-            }
-        }
+        state
+            .storage()
+            .create_directory(&directory)
+            .await
+            .with_context(|| format!("Could not create directory {}", directory.display()))?;
 
         let name = entry.name.clone();
-        let download_count = entry.download_files(&state.client, directory).await?;
+        let download_count = entry.download_files(state, directory).await?;
         if download_count == 0 {
             info!(
                 "[{}/{}] skipping {:?} due to having no new files",
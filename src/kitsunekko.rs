@@ -21,7 +21,7 @@ For an item's file listing:
 
 use anyhow::{bail, Context};
 use regex::Regex;
-use std::{collections::HashMap, io::Write, path::PathBuf, sync::OnceLock};
+use std::{collections::HashMap, path::PathBuf, sync::OnceLock};
 use time::{
     format_description::FormatItem,
     macros::{format_description, offset},
@@ -83,13 +83,14 @@ impl From<File> for Directory {
 }
 
 impl File {
-    async fn download(self, client: reqwest::Client, directory: PathBuf) -> anyhow::Result<()> {
+    async fn download(self, state: AppState, directory: PathBuf) -> anyhow::Result<()> {
+        let storage = state.storage().as_ref();
         let path = directory.join(&self.name);
-        if path.exists() {
+        if crate::storage::exists(storage, &path).await {
             return Ok(());
         }
 
-        let resp = client.get(&self.url).send().await?;
+        let resp = state.client.get(&self.url).send().await?;
         if let Some(bytes) = resp.content_length() {
             if bytes >= crate::MAX_UPLOAD_SIZE {
                 bail!(
@@ -101,9 +102,18 @@ impl File {
         }
 
         let bytes = resp.bytes().await?;
-        let mut file =
-            std::fs::File::create(&path).with_context(|| format!("Could not create file at {}", path.display()))?;
-        file.write_all(&bytes)?;
+        let bytes = if crate::encoding::should_normalize(&path) {
+            crate::encoding::to_utf8(&bytes).1
+        } else {
+            std::borrow::Cow::Borrowed(bytes.as_ref())
+        };
+        storage
+            .write(&path, &bytes, false)
+            .await
+            .with_context(|| format!("Could not create file at {}", path.display()))?;
+        crate::files::record_written(state.database(), storage, &path, &bytes)
+            .await
+            .with_context(|| format!("Could not record file at {}", path.display()))?;
         Ok(())
     }
 
@@ -122,10 +132,10 @@ impl Directory {
     }
 
     /// Concurrently downloads every file in this directory
-    pub async fn download_files(self, client: &reqwest::Client, directory: PathBuf) -> anyhow::Result<()> {
+    pub async fn download_files(self, state: &AppState, directory: PathBuf) -> anyhow::Result<()> {
         let mut set = JoinSet::new();
         for file in self.files {
-            set.spawn(file.download(client.clone(), directory.clone()));
+            set.spawn(file.download(state.clone(), directory.clone()));
         }
         while let Some(result) = set.join_next().await {
             if let Ok(Err(e)) = result {
@@ -358,16 +368,14 @@ pub async fn scrape(state: &AppState, date: OffsetDateTime) -> anyhow::Result<Ve
             });
         }
 
-        if !directory.exists() {
-            if let Err(e) = std::fs::create_dir_all(&directory) {
-                if e.kind() != std::io::ErrorKind::AlreadyExists {
-                    return Err(e).with_context(|| format!("Could not create directory {}", directory.display()));
-                }
-            }
-        }
+        state
+            .storage()
+            .create_directory(&directory)
+            .await
+            .with_context(|| format!("Could not create directory {}", directory.display()))?;
 
         let name = entry.name.clone();
-        entry.download_files(&state.client, directory).await?;
+        entry.download_files(state, directory).await?;
         info!("[{}/{}] finished downloading {:?}", index + 1, total, name);
     }
 
@@ -1,32 +1,45 @@
+pub mod aliases;
 pub mod anilist;
+pub mod archive;
 pub mod audit;
 pub mod auth;
 pub mod borrowed;
 pub mod cached;
+pub mod classify;
 pub mod cli;
 mod config;
 pub mod database;
 pub mod discord;
 pub mod download;
+pub mod encoding;
 pub mod error;
+pub mod files;
 pub mod filters;
 pub mod fixture;
 pub mod flash;
+pub mod fsck;
 pub mod headers;
 pub mod japanese;
 pub mod jpsubbers;
 pub mod key;
 pub mod kitsunekko;
+pub mod lines;
 pub mod logging;
 pub mod models;
 pub mod ratelimit;
 pub mod relations;
 pub mod routes;
+pub mod search;
 mod state;
+pub mod stats;
+pub mod storage;
+pub mod subtitle;
 pub mod tmdb;
 pub mod token;
 pub mod trash;
+pub mod upload;
 pub mod utils;
+pub mod watcher;
 
 pub use cli::{Command, PROGRAM_NAME};
 pub use config::{Config, CONFIG};
@@ -0,0 +1,373 @@
+//! Full-text search over the lines of text subtitle files.
+//!
+//! The lines are stored in the `subtitle_line` table and indexed by the
+//! `subtitle_line_fts` FTS5 table.
+//!
+//! The FTS5 `unicode61` tokenizer splits text on whitespace and punctuation,
+//! which does not work for Japanese since it isn't written with spaces. To work
+//! around this, runs of CJK characters are split into overlapping bigrams before
+//! being indexed, e.g. `こんにちは` becomes `こん んに にち ちは は`. The last
+//! character of each run is also indexed on its own so single character
+//! queries and phrases that cross runs still match. Queries are split the same
+//! way and searched for as a phrase.
+
+use std::path::Path;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{classify, storage::Storage, subtitle, Database};
+
+/// A single line of a subtitle file with its formatting removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The start time in milliseconds.
+    pub start: u64,
+    /// The end time in milliseconds.
+    pub end: u64,
+    /// The text of the line. Multiple lines are separated by `\n`.
+    pub text: String,
+}
+
+/// Extracts the lines out of a text subtitle file.
+///
+/// Returns `None` if the file is not a text subtitle file or if it could not be parsed.
+pub fn extract(name: &str, bytes: &[u8]) -> Option<Vec<Line>> {
+    let format = subtitle::Format::from_path(Path::new(name))?;
+    let text = subtitle::decode(bytes).ok()?;
+    let parsed = subtitle::parse(format, &text).ok()?;
+    Some(
+        parsed
+            .plain_cues()
+            .into_iter()
+            .map(|(start, end, text)| Line {
+                start: start.as_millis(),
+                end: end.as_millis(),
+                text,
+            })
+            .collect(),
+    )
+}
+
+/// Returns whether the character is written without spaces between words.
+fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{3005}' // 々
+        | '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}' // CJK Unified Ideographs Extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK Unified Ideographs
+        | '\u{ac00}'..='\u{d7af}' // Hangul Syllables
+        | '\u{f900}'..='\u{faff}' // CJK Compatibility Ideographs
+        | '\u{ff66}'..='\u{ff9f}' // Halfwidth Katakana
+    )
+}
+
+/// A piece of text that is tokenized as a unit.
+enum Segment<'a> {
+    /// A run of CJK characters.
+    Cjk(Vec<char>),
+    /// Anything else, which is left to the FTS5 tokenizer.
+    Other(&'a str),
+}
+
+fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut result = Vec::new();
+    let mut run = Vec::new();
+    // The start of the current non-CJK segment
+    let mut start = 0;
+    for (index, ch) in text.char_indices() {
+        if is_cjk(ch) {
+            if run.is_empty() && start < index {
+                result.push(Segment::Other(&text[start..index]));
+            }
+            run.push(ch);
+            start = index + ch.len_utf8();
+        } else if !run.is_empty() {
+            result.push(Segment::Cjk(std::mem::take(&mut run)));
+        }
+    }
+    if !run.is_empty() {
+        result.push(Segment::Cjk(run));
+    } else if start < text.len() {
+        result.push(Segment::Other(&text[start..]));
+    }
+    result
+}
+
+fn push_bigrams(run: &[char], output: &mut String) {
+    for pair in run.windows(2) {
+        output.push(' ');
+        output.push(pair[0]);
+        output.push(pair[1]);
+    }
+}
+
+/// Converts text into the form that is stored in the FTS5 index.
+pub fn tokenize(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() * 3);
+    for segment in segments(text) {
+        match segment {
+            Segment::Other(s) => output.push_str(s),
+            Segment::Cjk(run) => {
+                push_bigrams(&run, &mut output);
+                output.push(' ');
+                output.push(*run.last().unwrap());
+                output.push(' ');
+            }
+        }
+    }
+    output
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Converts a search query into an FTS5 query that matches it as a phrase.
+///
+/// Returns `None` if the query has nothing that can be searched for.
+pub fn to_fts_query(query: &str) -> Option<String> {
+    // Segments without any letters or numbers produce no tokens and are dropped
+    let segments = segments(query)
+        .into_iter()
+        .filter(|s| match s {
+            Segment::Cjk(_) => true,
+            Segment::Other(s) => s.chars().any(char::is_alphanumeric),
+        })
+        .collect::<Vec<_>>();
+
+    let mut parts = Vec::new();
+    let total = segments.len();
+    for (index, segment) in segments.into_iter().enumerate() {
+        let is_last = index + 1 == total;
+        match segment {
+            Segment::Other(s) => parts.push(quote(s)),
+            Segment::Cjk(run) if run.len() == 1 && is_last => {
+                // A lone character can be the start of any bigram
+                parts.push(format!("{} *", quote(&run[0].to_string())));
+            }
+            Segment::Cjk(run) => {
+                let mut tokens = String::new();
+                push_bigrams(&run, &mut tokens);
+                // The last character is only needed to connect the phrase to what comes next
+                if !is_last {
+                    tokens.push(' ');
+                    tokens.push(*run.last().unwrap());
+                }
+                parts.push(quote(tokens.trim_start()));
+            }
+        }
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" + "))
+    }
+}
+
+/// Replaces the indexed lines of a file.
+///
+/// The file is also classified again from its new lines.
+pub fn replace(conn: &rusqlite::Connection, file_id: i64, lines: &[Line]) -> rusqlite::Result<()> {
+    conn.prepare_cached("DELETE FROM subtitle_line WHERE file_id = ?")?
+        .execute([file_id])?;
+    let mut insert =
+        conn.prepare_cached("INSERT INTO subtitle_line(file_id, start_ms, end_ms, text) VALUES (?, ?, ?, ?)")?;
+    let mut insert_fts = conn.prepare_cached("INSERT INTO subtitle_line_fts(rowid, tokens) VALUES (?, ?)")?;
+    for line in lines {
+        insert.execute((file_id, line.start, line.end, &line.text))?;
+        insert_fts.execute((conn.last_insert_rowid(), tokenize(&line.text)))?;
+    }
+    classify::store(conn, file_id, &classify::classify(lines))
+}
+
+/// The lines of a file that are pending to be indexed.
+#[derive(Debug, Clone)]
+pub struct PendingLines {
+    /// The directory entry that the file belongs to.
+    pub entry_id: i64,
+    /// The file's name within the directory entry.
+    pub name: String,
+    /// The lines of the file.
+    pub lines: Vec<Line>,
+}
+
+impl PendingLines {
+    /// Extracts the lines of a file to be indexed.
+    ///
+    /// Returns `None` if the file is not a text subtitle file or if it could not be parsed.
+    pub fn new(entry_id: i64, name: String, bytes: &[u8]) -> Option<Self> {
+        let lines = extract(&name, bytes)?;
+        Some(Self { entry_id, name, lines })
+    }
+}
+
+/// Indexes the lines of the given files, replacing any lines that were indexed before.
+///
+/// The files must already be recorded in the `file` table.
+pub async fn index(database: &Database, files: Vec<PendingLines>) -> rusqlite::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    database
+        .call(move |conn| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            for file in &files {
+                let id = tx
+                    .prepare_cached("SELECT id FROM file WHERE entry_id = ? AND name = ?")?
+                    .query_row((file.entry_id, &file.name), |row| row.get(0));
+                match id {
+                    Ok(id) => replace(&tx, id, &file.lines)?,
+                    Err(rusqlite::Error::QueryReturnedNoRows) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            tx.commit()
+        })
+        .await
+}
+
+/// Indexes every text subtitle file in the entry's directory.
+///
+/// Returns the number of files that were indexed.
+pub async fn index_directory(
+    database: &Database,
+    storage: &dyn Storage,
+    entry_id: i64,
+    path: &Path,
+) -> anyhow::Result<usize> {
+    let mut files = Vec::new();
+    for file in storage.list(path).await? {
+        if subtitle::Format::from_path(Path::new(&file.name)).is_none() {
+            continue;
+        }
+        let Ok(bytes) = storage.read(&path.join(&file.name)).await else {
+            continue;
+        };
+        files.push((file.name, bytes));
+    }
+    let files = tokio::task::spawn_blocking(move || {
+        files
+            .into_iter()
+            .flat_map(|(name, bytes)| PendingLines::new(entry_id, name, &bytes))
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    let total = files.len();
+    index(database, files).await?;
+    Ok(total)
+}
+
+/// A line that matched a search.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LineMatch {
+    /// The ID of the entry that the file belongs to.
+    pub entry_id: i64,
+    /// The name of the entry that the file belongs to.
+    pub entry_name: String,
+    /// The name of the file that the line is in.
+    pub file_name: String,
+    /// The start time of the line in milliseconds.
+    pub start: u64,
+    /// The end time of the line in milliseconds.
+    pub end: u64,
+    /// The text of the line. Multiple lines are separated by `\n`.
+    pub text: String,
+}
+
+/// Searches for lines that contain the given query, best matches first.
+pub async fn search(
+    database: &Database,
+    query: &str,
+    entry_id: Option<i64>,
+    limit: usize,
+) -> rusqlite::Result<Vec<LineMatch>> {
+    let Some(query) = to_fts_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let sql = r#"
+        SELECT subtitle_line.start_ms, subtitle_line.end_ms, subtitle_line.text,
+               file.entry_id, file.name AS file_name, directory_entry.name AS entry_name
+        FROM subtitle_line_fts
+        INNER JOIN subtitle_line ON subtitle_line.id = subtitle_line_fts.rowid
+        INNER JOIN file ON file.id = subtitle_line.file_id
+        INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+        WHERE subtitle_line_fts MATCH ?1 AND (?2 IS NULL OR file.entry_id = ?2)
+        ORDER BY rank
+        LIMIT ?3
+    "#;
+    database
+        .call(move |conn| -> rusqlite::Result<Vec<LineMatch>> {
+            let mut stmt = conn.prepare_cached(sql)?;
+            let rows = stmt.query_map((query, entry_id, limit), |row| {
+                Ok(LineMatch {
+                    entry_id: row.get("entry_id")?,
+                    entry_name: row.get("entry_name")?,
+                    file_name: row.get("file_name")?,
+                    start: row.get("start_ms")?,
+                    end: row.get("end_ms")?,
+                    text: row.get("text")?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+    use crate::files::{self, StoredFile};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("こんにちは"), " こん んに にち ちは は ");
+        assert_eq!(tokenize("猫"), " 猫 ");
+        assert_eq!(tokenize("hello 世界!"), "hello  世界 界 !");
+        assert_eq!(to_fts_query("こんにちは").as_deref(), Some("\"こん んに にち ちは\""));
+        assert_eq!(to_fts_query("猫").as_deref(), Some("\"猫\" *"));
+        assert_eq!(to_fts_query("世界 hello").as_deref(), Some("\"世界 界\" + \" hello\""));
+        assert_eq!(to_fts_query("say \"hi\"").as_deref(), Some("\"say \"\"hi\"\"\""));
+        assert_eq!(to_fts_query("、。!"), None);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let db = test_database("INSERT INTO directory_entry(id, path, name) VALUES (1, 'a', 'Show');").await;
+
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\n私は黒猫です。\n\n2\n00:00:03,000 --> 00:00:04,500\n{\\an8}Hello world\n";
+        files::record(&db, vec![StoredFile::new(1, "01.srt".into(), srt.as_bytes(), None)])
+            .await
+            .unwrap();
+        let pending = PendingLines::new(1, "01.srt".into(), srt.as_bytes()).unwrap();
+        assert_eq!(pending.lines.len(), 2);
+        index(&db, vec![pending.clone()]).await.unwrap();
+        // indexing again replaces the old lines
+        index(&db, vec![pending]).await.unwrap();
+
+        let found = search(&db, "黒猫", None, 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file_name, "01.srt");
+        assert_eq!(found[0].entry_name, "Show");
+        assert_eq!(found[0].start, 1000);
+        assert_eq!(found[0].text, "私は黒猫です。");
+
+        assert_eq!(search(&db, "猫", None, 10).await.unwrap().len(), 1);
+        assert_eq!(search(&db, "す", None, 10).await.unwrap().len(), 1);
+        assert_eq!(search(&db, "猫です", Some(1), 10).await.unwrap().len(), 1);
+        assert!(search(&db, "猫です", Some(2), 10).await.unwrap().is_empty());
+        assert!(search(&db, "白猫", None, 10).await.unwrap().is_empty());
+
+        let found = search(&db, "hello", None, 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].text, "Hello world");
+        assert_eq!(found[0].end, 4500);
+
+        files::remove(&db, 1, "01.srt".into()).await.unwrap();
+        assert!(search(&db, "hello", None, 10).await.unwrap().is_empty());
+    }
+}
@@ -118,6 +118,12 @@ async fn run_server(state: jimaku::AppState) -> anyhow::Result<()> {
 
     tokio::spawn(jimaku::kitsunekko::auto_scrape_loop(state.clone()));
     tokio::spawn(jimaku::jpsubbers::auto_scrape_loop(state.clone()));
+    tokio::spawn(jimaku::upload::expire_sessions_loop(state.clone()));
+    tokio::spawn(jimaku::watcher::watch_loop(state.clone()));
+    {
+        let state = state.clone();
+        tokio::spawn(async move { state.fill_external_ids().await });
+    }
 
     // Middleware order for request processing is bottom to top
     // and for response processing it's top to bottom
@@ -331,6 +337,21 @@ async fn run(command: jimaku::Command) -> anyhow::Result<()> {
             );
             Ok(())
         }
+        jimaku::Command::Fsck { fix } => {
+            let root = state.config().subtitle_path.as_path();
+            let problems = jimaku::fsck::check(state.database(), state.storage(), root).await?;
+            for problem in problems.iter() {
+                println!("{problem}");
+            }
+            println!("found {} problems", problems.len());
+            if fix && !problems.is_empty() {
+                let repaired = jimaku::fsck::repair_all(state.database(), state.storage(), &problems).await;
+                state.cached_directories().invalidate().await;
+                println!("repaired {repaired} problems");
+                info!(total = problems.len(), repaired, "repaired library problems");
+            }
+            Ok(())
+        }
     }
 }
 
@@ -238,6 +238,14 @@ pub struct DirectoryEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(pattern = r#"(tv|movie):(\d+)"#, value_type = Option<String>, example = "tv:12345")]
     pub tmdb_id: Option<tmdb::Id>,
+    /// The MyAnimeList ID of this entry.
+    #[schema(example = 52991)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mal_id: Option<u32>,
+    /// The Kitsu ID of this entry.
+    #[schema(example = 46474)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kitsu_id: Option<u32>,
     /// Extra notes that the entry might have.
     ///
     /// Supports a limited set of markdown. Can only be set by editors.
@@ -251,6 +259,20 @@ pub struct DirectoryEntry {
     #[schema(example = "葬送のフリーレン")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub japanese_name: Option<String>,
+    /// Alternative titles of the entry, e.g. abbreviations and synonyms.
+    #[schema(example = json!(["Frieren"]))]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+/// Gets the value of a column that is only selected by some queries.
+///
+/// Returns `None` if the query did not select the column.
+fn optional_column<T: FromSql>(row: &rusqlite::Row<'_>, name: &str) -> rusqlite::Result<Option<T>> {
+    match row.as_ref().column_index(name) {
+        Ok(index) => row.get(index),
+        Err(_) => Ok(None),
+    }
 }
 
 impl Table for DirectoryEntry {
@@ -283,9 +305,24 @@ impl Table for DirectoryEntry {
             creator_id: row.get("creator_id")?,
             anilist_id: row.get("anilist_id")?,
             tmdb_id: row.get("tmdb_id")?,
+            // External IDs and aliases are only loaded by queries that ask for them, see DirectoryEntry::SELECT_FULL
+            mal_id: optional_column(row, "mal_id")?,
+            kitsu_id: optional_column(row, "kitsu_id")?,
             notes: row.get("notes")?,
             english_name: row.get("english_name")?,
             japanese_name: row.get("japanese_name")?,
+            aliases: match row.as_ref().column_index("aliases") {
+                Ok(index) => {
+                    let json: Option<String> = row.get(index)?;
+                    match json {
+                        Some(json) => serde_json::from_str(&json).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+                        })?,
+                        None => Vec::new(),
+                    }
+                }
+                Err(_) => Vec::new(),
+            },
         })
     }
 }
@@ -326,12 +363,37 @@ impl DirectoryEntry {
             creator_id: Default::default(),
             anilist_id: Default::default(),
             tmdb_id: Default::default(),
+            mal_id: Default::default(),
+            kitsu_id: Default::default(),
             notes: Default::default(),
             english_name: Default::default(),
             japanese_name: Default::default(),
+            aliases: Default::default(),
         }
     }
 
+    /// A query that selects every entry along with its external IDs and aliases.
+    ///
+    /// Regular `SELECT * FROM directory_entry` queries do not load these.
+    pub const SELECT_FULL: &'static str = r#"
+        SELECT directory_entry.*,
+               entry_external_id.mal_id,
+               entry_external_id.kitsu_id,
+               (SELECT json_group_array(name) FROM (
+                   SELECT name FROM entry_alias WHERE entry_id = directory_entry.id ORDER BY name
+               )) AS aliases
+        FROM directory_entry
+        LEFT JOIN entry_external_id ON entry_external_id.entry_id = directory_entry.id
+    "#;
+
+    /// Returns every title of the entry, starting with its name and ending with its aliases.
+    pub fn titles(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str())
+            .chain(self.english_name.as_deref())
+            .chain(self.japanese_name.as_deref())
+            .chain(self.aliases.iter().map(String::as_str))
+    }
+
     /// Returns data safe for embedding into the frontend
     pub fn data(&self) -> DirectoryEntryData<'_> {
         DirectoryEntryData {
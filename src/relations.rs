@@ -23,27 +23,57 @@
 //!     redirected to itself.
 //! ```
 //!
-//! The only IDs we're interested in are the 3rd ones, the AniList ID.
+//! Relations are keyed by the AniList ID but can be looked up by any of the three IDs.
+//! The file is also used to find the MyAnimeList and Kitsu IDs of an AniList ID.
 
 use std::{collections::HashMap, str::FromStr};
 
 use serde::Serialize;
 use time::format_description::well_known::Iso8601;
+use utoipa::ToSchema;
+
+use crate::{models::DirectoryEntry, Database};
 
 pub const RELATIONS_URL: &str = "https://raw.githubusercontent.com/erengy/anime-relations/master/anime-relations.txt";
 
+/// The external services that relations can be looked up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    MyAnimeList,
+    Kitsu,
+    AniList,
+}
+
+impl Service {
+    /// Every service, in the order they appear in a rule.
+    pub const ALL: [Service; 3] = [Service::MyAnimeList, Service::Kitsu, Service::AniList];
+}
+
 /// The relation ID that you can look up relations by.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum RelationId {
-    /// An external AniList ID
-    AniList(u32),
+    /// An external ID of one of the services
+    Known(u32),
     /// An unknown ID, denoted by ?
     Unknown,
     /// A repeated ID, denoted by ~
     Repeated,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+impl FromStr for RelationId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "?" => Ok(RelationId::Unknown),
+            "~" => Ok(RelationId::Repeated),
+            x => x.parse().map(RelationId::Known),
+        }
+    }
+}
+
+/// A range of episodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, ToSchema)]
 #[serde(rename_all = "lowercase", tag = "type")]
 pub enum Range {
     Number { value: u16 },
@@ -80,15 +110,65 @@ impl Range {
     pub const fn is_number(&self) -> bool {
         matches!(self, Self::Number { .. })
     }
+
+    /// Returns `true` if any episode is in both ranges.
+    pub const fn overlaps(&self, other: &Range) -> bool {
+        self.begin() <= other.end() && other.begin() <= self.end()
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl FromStr for Range {
+    type Err = std::num::ParseIntError;
+
+    /// Parses a range of episodes, e.g. `12`, `12-24`, or `12-?` for every episode from 12 onwards.
+    ///
+    /// The `?` is optional, i.e. `12-` is the same as `12-?`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((left, right)) => {
+                let value = left.parse()?;
+                if right.is_empty() || right == "?" {
+                    Ok(Range::From { value })
+                } else {
+                    Ok(Range::Inclusive {
+                        begin: value,
+                        end: right.parse()?,
+                    })
+                }
+            }
+            None => Ok(Range::Number { value: s.parse()? }),
+        }
+    }
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct RuleComponent {
-    id: RelationId,
+    /// The IDs of each service, in the same order as [`Service::ALL`].
+    ids: [RelationId; 3],
     range: Range,
 }
 
+impl RuleComponent {
+    fn id(&self, service: Service) -> RelationId {
+        match service {
+            Service::MyAnimeList => self.ids[0],
+            Service::Kitsu => self.ids[1],
+            Service::AniList => self.ids[2],
+        }
+    }
+
+    /// Returns the ID of the service if it's known.
+    ///
+    /// Repeated IDs are resolved using the source component, if given.
+    fn known_id(&self, service: Service, source: Option<&RuleComponent>) -> Option<u32> {
+        match self.id(service) {
+            RelationId::Known(id) => Some(id),
+            RelationId::Repeated => source.and_then(|s| s.known_id(service, None)),
+            RelationId::Unknown => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct ParseRuleComponentError;
 
@@ -110,40 +190,29 @@ impl FromStr for RuleComponent {
     type Err = ParseRuleComponentError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (_, s) = s.rsplit_once('|').ok_or(ParseRuleComponentError)?;
-        let (id, episodes) = s.split_once(':').ok_or(ParseRuleComponentError)?;
-        let id = match id {
-            "?" => RelationId::Unknown,
-            "~" => RelationId::Repeated,
-            x => x.parse().map(RelationId::AniList)?,
+        let (ids, episodes) = s.split_once(':').ok_or(ParseRuleComponentError)?;
+        let mut parts = ids.split('|');
+        let mut next = || -> Result<RelationId, ParseRuleComponentError> {
+            Ok(parts.next().ok_or(ParseRuleComponentError)?.parse()?)
         };
+        let ids = [next()?, next()?, next()?];
+        if parts.next().is_some() {
+            return Err(ParseRuleComponentError);
+        }
 
         // Episodes are essentially \d+(?:-(?:\d+|\?))?
-        let range = match episodes.split_once('-') {
-            Some((left, right)) => {
-                let value = left.parse()?;
-                if right == "?" {
-                    Range::From { value }
-                } else {
-                    Range::Inclusive {
-                        begin: value,
-                        end: right.parse()?,
-                    }
-                }
-            }
-            None => Range::Number {
-                value: episodes.parse()?,
-            },
-        };
+        let range = episodes.parse()?;
 
-        Ok(Self { id, range })
+        Ok(Self { ids, range })
     }
 }
 
 /// The actual relation rule that maps from one source range to another destination range.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub struct Rule {
-    /// The ID that this rule applies to
+    /// The destination ID that this rule applies to
+    ///
+    /// This is in the same service as the ID the rule was looked up by.
     #[serde(rename = "anilist_id")]
     pub id: u32,
     /// The source range of episodes that is being applied to
@@ -171,19 +240,130 @@ fn find_destination(rules: &[Rule], episode: u16) -> Option<(u32, u16)> {
     None
 }
 
+/// The MyAnimeList and Kitsu IDs of an entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExternalIds {
+    pub mal_id: Option<u32>,
+    pub kitsu_id: Option<u32>,
+}
+
+impl ExternalIds {
+    /// Fills in the IDs that are missing from the other IDs.
+    pub fn or(self, other: ExternalIds) -> Self {
+        Self {
+            mal_id: self.mal_id.or(other.mal_id),
+            kitsu_id: self.kitsu_id.or(other.kitsu_id),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mal_id.is_none() && self.kitsu_id.is_none()
+    }
+}
+
+/// Stores the external IDs of the entry, keeping the ones it already has.
+pub fn save_external_ids(conn: &rusqlite::Connection, entry_id: i64, ids: ExternalIds) -> rusqlite::Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let query = r#"
+        INSERT INTO entry_external_id(entry_id, mal_id, kitsu_id) VALUES (?, ?, ?)
+        ON CONFLICT(entry_id) DO UPDATE
+        SET mal_id = COALESCE(mal_id, excluded.mal_id), kitsu_id = COALESCE(kitsu_id, excluded.kitsu_id)
+    "#;
+    conn.prepare_cached(query)?
+        .execute((entry_id, ids.mal_id, ids.kitsu_id))
+        .map(|_| ())
+}
+
+/// Records that AniList was asked for the MyAnimeList ID of the entry.
+pub fn mark_anilist_checked(conn: &rusqlite::Connection, entry_id: i64) -> rusqlite::Result<()> {
+    let query = r#"
+        INSERT INTO entry_external_id(entry_id, anilist_checked_at) VALUES (?, CURRENT_TIMESTAMP)
+        ON CONFLICT(entry_id) DO UPDATE SET anilist_checked_at = excluded.anilist_checked_at
+    "#;
+    conn.prepare_cached(query)?.execute([entry_id]).map(|_| ())
+}
+
+/// Returns the entries that have any of the given IDs of the service.
+///
+/// MyAnimeList and Kitsu IDs are looked up through the IDs stored for each entry.
+pub async fn find_entries(
+    database: &Database,
+    service: Service,
+    ids: Vec<u32>,
+) -> rusqlite::Result<Vec<DirectoryEntry>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let column = match service {
+        Service::MyAnimeList => "entry_external_id.mal_id",
+        Service::Kitsu => "entry_external_id.kitsu_id",
+        Service::AniList => "directory_entry.anilist_id",
+    };
+    let mut query = format!("{} WHERE {column} IN (", DirectoryEntry::SELECT_FULL);
+    for index in 0..ids.len() {
+        if index != 0 {
+            query.push(',');
+        }
+        query.push('?');
+    }
+    query.push_str(") ORDER BY directory_entry.id");
+    database.all(query, rusqlite::params_from_iter(ids)).await
+}
+
 /// A mapping of an ID to a relation
 #[derive(Debug, Clone, Serialize)]
 pub struct Relations {
     pub last_modified: time::Date,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: time::OffsetDateTime,
+    /// The relations keyed by AniList ID
     #[serde(rename = "relations")]
     data: HashMap<u32, Relation>,
+    /// The relations keyed by MyAnimeList ID
+    #[serde(skip)]
+    mal: HashMap<u32, Relation>,
+    /// The relations keyed by Kitsu ID
+    #[serde(skip)]
+    kitsu: HashMap<u32, Relation>,
+    /// The external IDs of every AniList ID mentioned in a rule
+    #[serde(skip)]
+    external_ids: HashMap<u32, ExternalIds>,
 }
 
 impl Relations {
+    fn rules(&self, service: Service) -> &HashMap<u32, Relation> {
+        match service {
+            Service::MyAnimeList => &self.mal,
+            Service::Kitsu => &self.kitsu,
+            Service::AniList => &self.data,
+        }
+    }
+
+    fn rules_mut(&mut self, service: Service) -> &mut HashMap<u32, Relation> {
+        match service {
+            Service::MyAnimeList => &mut self.mal,
+            Service::Kitsu => &mut self.kitsu,
+            Service::AniList => &mut self.data,
+        }
+    }
+
+    /// Records the external IDs of the component if its AniList ID is known.
+    fn add_external_ids(&mut self, component: &RuleComponent, source: Option<&RuleComponent>) {
+        let Some(anilist_id) = component.known_id(Service::AniList, source) else {
+            return;
+        };
+        let found = ExternalIds {
+            mal_id: component.known_id(Service::MyAnimeList, source),
+            kitsu_id: component.known_id(Service::Kitsu, source),
+        };
+        let ids = self.external_ids.entry(anilist_id).or_default();
+        *ids = ids.or(found);
+    }
+
     pub fn new(s: &str) -> anyhow::Result<Self> {
-        let mut data: HashMap<u32, Vec<Rule>> = HashMap::new();
+        let mut relations = Self::default();
         let mut last_modified = time::Date::MIN;
 
         for line in s.lines() {
@@ -204,34 +384,38 @@ impl Relations {
                 };
 
                 let left = left.parse::<RuleComponent>()?;
-                let RelationId::AniList(source_id) = left.id else {
-                    continue;
-                };
-
                 let right = right.parse::<RuleComponent>()?;
-                let destination_id = match right.id {
-                    RelationId::AniList(id) => id,
-                    _ => source_id,
-                };
-
-                let rule = Rule {
-                    id: destination_id,
-                    source: left.range,
-                    destination: right.range,
-                };
-
-                data.entry(source_id).or_default().push(rule);
-                if is_redirected {
-                    data.entry(destination_id).or_default().push(rule);
+                relations.add_external_ids(&left, None);
+                relations.add_external_ids(&right, Some(&left));
+
+                for service in Service::ALL {
+                    let RelationId::Known(source_id) = left.id(service) else {
+                        continue;
+                    };
+
+                    let destination_id = match right.id(service) {
+                        RelationId::Known(id) => id,
+                        _ => source_id,
+                    };
+
+                    let rule = Rule {
+                        id: destination_id,
+                        source: left.range,
+                        destination: right.range,
+                    };
+
+                    let data = relations.rules_mut(service);
+                    data.entry(source_id).or_default().push(rule);
+                    if is_redirected {
+                        data.entry(destination_id).or_default().push(rule);
+                    }
                 }
             }
         }
 
-        Ok(Self {
-            last_modified,
-            data,
-            created_at: time::OffsetDateTime::now_utc(),
-        })
+        relations.last_modified = last_modified;
+        relations.created_at = time::OffsetDateTime::now_utc();
+        Ok(relations)
     }
 
     /// Loads the relation data from the GitHub URL
@@ -245,9 +429,21 @@ impl Relations {
     /// The first element of the tuple is the destination AniList ID
     /// and the second element of the tuple is the resulting episode.
     pub fn find(&self, anilist_id: u32, episode: u16) -> Option<(u32, u16)> {
-        let rules = self.data.get(&anilist_id)?;
+        self.find_in(Service::AniList, anilist_id, episode)
+    }
+
+    /// Finds the relation of a given ID of the service and episode
+    ///
+    /// The destination ID is of the same service as the given ID.
+    pub fn find_in(&self, service: Service, id: u32, episode: u16) -> Option<(u32, u16)> {
+        let rules = self.rules(service).get(&id)?;
         find_destination(rules, episode)
     }
+
+    /// Returns the MyAnimeList and Kitsu IDs of the given AniList ID, as far as they're known.
+    pub fn external_ids(&self, anilist_id: u32) -> ExternalIds {
+        self.external_ids.get(&anilist_id).copied().unwrap_or_default()
+    }
 }
 
 impl Default for Relations {
@@ -256,6 +452,9 @@ impl Default for Relations {
             last_modified: time::OffsetDateTime::UNIX_EPOCH.date(),
             created_at: time::OffsetDateTime::UNIX_EPOCH,
             data: HashMap::new(),
+            mal: HashMap::new(),
+            kitsu: HashMap::new(),
+            external_ids: HashMap::new(),
         }
     }
 }
@@ -264,6 +463,113 @@ impl Default for Relations {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_range_parse() {
+        assert_eq!("12".parse(), Ok(Range::Number { value: 12 }));
+        assert_eq!("12-24".parse(), Ok(Range::Inclusive { begin: 12, end: 24 }));
+        assert_eq!("12-?".parse(), Ok(Range::From { value: 12 }));
+        assert_eq!("12-".parse(), Ok(Range::From { value: 12 }));
+        assert!("a-12".parse::<Range>().is_err());
+        assert!(Range::Inclusive { begin: 12, end: 24 }.overlaps(&Range::Number { value: 24 }));
+        assert!(!Range::Inclusive { begin: 12, end: 24 }.overlaps(&Range::From { value: 25 }));
+    }
+
+    #[test]
+    fn test_rule_component_parse() {
+        let component = "10001|~|?:14-26".parse::<RuleComponent>().unwrap();
+        assert_eq!(
+            component.ids,
+            [RelationId::Known(10001), RelationId::Repeated, RelationId::Unknown]
+        );
+        assert_eq!(component.range, Range::Inclusive { begin: 14, end: 26 });
+        assert!("10001|10002:1".parse::<RuleComponent>().is_err());
+        assert!("1|2|3|4:1".parse::<RuleComponent>().is_err());
+    }
+
+    #[test]
+    fn test_relations_services() {
+        let relations = Relations::new(
+            "- last_modified: 2024-01-01\n\
+             - 40028|43247|114308:14-25 -> 48661|46102|139630:1-12!\n\
+             - 999|?|?:2 -> ~|?|?:1\n",
+        )
+        .unwrap();
+        assert_eq!(relations.find(114308, 14), Some((139630, 1)));
+        assert_eq!(relations.find(139630, 14), Some((139630, 1)));
+        assert_eq!(relations.find_in(Service::MyAnimeList, 40028, 25), Some((48661, 12)));
+        assert_eq!(relations.find_in(Service::Kitsu, 43247, 20), Some((46102, 7)));
+        assert_eq!(relations.find_in(Service::MyAnimeList, 999, 2), Some((999, 1)));
+        assert_eq!(relations.find(999, 2), None);
+        assert_eq!(
+            relations.external_ids(139630),
+            ExternalIds {
+                mal_id: Some(48661),
+                kitsu_id: Some(46102)
+            }
+        );
+        assert!(relations.external_ids(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_entries() {
+        let db = crate::database::test_database(
+            "INSERT INTO directory_entry(id, path, name, anilist_id) VALUES (1, 'a', 'a', 139630), (2, 'b', 'b', 1);
+             INSERT INTO entry_external_id(entry_id, mal_id, kitsu_id) VALUES (1, 48661, 46102), (2, 1, NULL);",
+        )
+        .await;
+        let ids = |entries: Vec<DirectoryEntry>| entries.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        let found = find_entries(&db, Service::AniList, vec![139630]).await.unwrap();
+        assert_eq!(found[0].mal_id, Some(48661));
+        assert_eq!(ids(found), [1]);
+        let found = find_entries(&db, Service::MyAnimeList, vec![48661, 1, 5])
+            .await
+            .unwrap();
+        assert_eq!(ids(found), [1, 2]);
+        let found = find_entries(&db, Service::Kitsu, vec![46102]).await.unwrap();
+        assert_eq!(ids(found), [1]);
+        assert!(find_entries(&db, Service::Kitsu, vec![1]).await.unwrap().is_empty());
+        assert!(find_entries(&db, Service::MyAnimeList, Vec::new())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mark_anilist_checked() {
+        let db = crate::database::test_database(
+            "INSERT INTO directory_entry(id, path, name, anilist_id) VALUES (1, 'a', 'a', 139630), (2, 'b', 'b', 1);
+             INSERT INTO entry_external_id(entry_id, mal_id, kitsu_id) VALUES (1, NULL, 46102);",
+        )
+        .await;
+        db.call(|conn| -> rusqlite::Result<()> {
+            mark_anilist_checked(conn, 1)?;
+            mark_anilist_checked(conn, 2)?;
+            save_external_ids(
+                conn,
+                2,
+                ExternalIds {
+                    mal_id: Some(1),
+                    kitsu_id: None,
+                },
+            )
+        })
+        .await
+        .unwrap();
+
+        let rows = db
+            .call(|conn| -> rusqlite::Result<Vec<(Option<u32>, bool)>> {
+                let mut stmt = conn.prepare(
+                    "SELECT mal_id, anilist_checked_at IS NOT NULL FROM entry_external_id ORDER BY entry_id",
+                )?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows, [(None, true), (Some(1), true)]);
+    }
+
     #[tokio::test]
     async fn test_relations_lookup() {
         let client = reqwest::Client::new();
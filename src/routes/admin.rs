@@ -2,27 +2,29 @@ use std::path::PathBuf;
 
 use crate::{
     audit,
-    download::{validate_path, DownloadResponse},
+    download::{self, validate_path, DownloadResponse},
     filters,
 };
 use askama::Template;
 use axum::{
     extract::{Path, Query, Request, State},
     http::StatusCode,
-    response::Redirect,
-    routing::get,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
     Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use tower::ServiceExt as _;
-use tower_http::services::ServeFile;
 
 use crate::{
     cached::BodyCache,
     error::ApiError,
-    models::Account,
-    trash::{Trash, TrashListing},
+    files::{self, DuplicateGroup, StoredFile},
+    flash::{FlashMessage, Flasher, Flashes},
+    lines::{self, PendingLines},
+    models::{Account, DirectoryEntry},
+    stats::{self, DownloadedEntry, DownloadedFile},
+    trash::{Trash, TrashInfo, TrashListing},
     utils::logs_directory,
     AppState,
 };
@@ -149,12 +151,12 @@ struct AdminTrashTemplate {
     trash: Trash,
 }
 
-async fn show_trash(account: Account) -> Result<AdminTrashTemplate, Redirect> {
+async fn show_trash(State(state): State<AppState>, account: Account) -> Result<AdminTrashTemplate, Redirect> {
     if !account.flags.is_admin() {
         return Err(Redirect::to("/"));
     }
 
-    let Ok(trash) = Trash::new() else {
+    let Ok(trash) = state.trash().await else {
         return Err(Redirect::to("/"));
     };
 
@@ -186,6 +188,24 @@ struct TrashResponse {
     failed: usize,
 }
 
+/// Re-records a file that was restored from the trash.
+async fn restore_file_record(state: &AppState, info: TrashInfo) {
+    let Some(name) = info.path.file_name().and_then(|s| s.to_str()).map(String::from) else {
+        return;
+    };
+    let Ok(bytes) = state.storage().read(&info.path).await else {
+        return;
+    };
+    let lines = PendingLines::new(info.entry_id, name.clone(), &bytes);
+    let record = StoredFile::new(info.entry_id, name, &bytes, info.uploader_id);
+    if let Err(e) = files::record(state.database(), vec![record]).await {
+        tracing::error!(error=%e, "Could not record restored file");
+    }
+    if let Err(e) = lines::index(state.database(), lines.into_iter().collect()).await {
+        tracing::error!(error=%e, "Could not index restored file");
+    }
+}
+
 async fn trash_management(
     State(state): State<AppState>,
     account: Account,
@@ -195,7 +215,7 @@ async fn trash_management(
         return Err(ApiError::forbidden());
     }
 
-    let trash = Trash::new()?;
+    let trash = state.trash().await?;
     let mut response = TrashResponse::default();
     let mut data = audit::TrashAction {
         restore: payload.action == TrashRequestAction::Restore,
@@ -205,7 +225,13 @@ async fn trash_management(
         let filename = PathBuf::from(&name);
         let result = match payload.action {
             TrashRequestAction::Delete => trash.delete(filename).await,
-            TrashRequestAction::Restore => trash.restore(filename).await,
+            TrashRequestAction::Restore => match trash.restore(filename).await {
+                Ok(info) => {
+                    restore_file_record(&state, info).await;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
         };
         data.add_file(name, result.is_err());
         match result {
@@ -220,22 +246,137 @@ async fn trash_management(
     Ok(Json(response))
 }
 
-async fn download_trash(account: Account, Path(path): Path<String>, req: Request) -> DownloadResponse {
+async fn download_trash(
+    State(state): State<AppState>,
+    account: Account,
+    Path(path): Path<String>,
+    req: Request,
+) -> DownloadResponse {
     if !account.flags.is_admin() {
         return DownloadResponse::NotFound;
     }
 
-    let Ok(trash) = Trash::new() else {
+    let Ok(trash) = state.trash().await else {
         return DownloadResponse::NotFound;
     };
     let Some(path) = validate_path(trash.files_path(), path.as_str()) else {
         return DownloadResponse::NotFound;
     };
 
-    match ServeFile::new(path).oneshot(req).await {
-        Ok(res) => DownloadResponse::File(res.map(axum::body::Body::new)),
-        Err(_) => DownloadResponse::NotFound,
+    download::serve(state.storage().as_ref(), &path, req).await
+}
+
+#[derive(Template)]
+#[template(path = "admin_duplicates.html")]
+struct AdminDuplicatesTemplate {
+    account: Option<Account>,
+    flashes: Flashes,
+    groups: Vec<DuplicateGroup>,
+    /// The total size taken up by every copy past the first.
+    wasted: u64,
+}
+
+async fn show_duplicates(
+    State(state): State<AppState>,
+    account: Account,
+    flashes: Flashes,
+) -> Result<AdminDuplicatesTemplate, Redirect> {
+    if !account.flags.is_admin() {
+        return Err(Redirect::to("/"));
+    }
+
+    let groups = files::duplicate_groups(state.database()).await.unwrap_or_default();
+    let wasted = groups
+        .iter()
+        .map(|g| g.size * (g.files.len() as u64).saturating_sub(1))
+        .sum();
+    Ok(AdminDuplicatesTemplate {
+        account: Some(account),
+        flashes,
+        groups,
+        wasted,
+    })
+}
+
+/// The number of rows shown in each table of the download report.
+const DOWNLOAD_REPORT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+struct DownloadsQuery {
+    /// Only count the downloads of the last given days, or every download if not given.
+    #[serde(default, deserialize_with = "crate::utils::generic_empty_string_is_none")]
+    days: Option<u16>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_downloads.html")]
+struct AdminDownloadsTemplate {
+    account: Option<Account>,
+    days: Option<u16>,
+    entries: Vec<DownloadedEntry>,
+    files: Vec<DownloadedFile>,
+    never_downloaded: Vec<DownloadedFile>,
+}
+
+async fn show_downloads(
+    State(state): State<AppState>,
+    account: Account,
+    Query(query): Query<DownloadsQuery>,
+) -> Result<AdminDownloadsTemplate, Redirect> {
+    if !account.flags.is_admin() {
+        return Err(Redirect::to("/"));
     }
+
+    let database = state.database();
+    let days = query.days.filter(|&d| d != 0);
+    let entries = stats::most_downloaded_entries(database, days, DOWNLOAD_REPORT_LIMIT).await;
+    let files = stats::most_downloaded_files(database, days, DOWNLOAD_REPORT_LIMIT).await;
+    let never_downloaded = stats::never_downloaded_files(database, DOWNLOAD_REPORT_LIMIT).await;
+    Ok(AdminDownloadsTemplate {
+        account: Some(account),
+        days,
+        entries: entries.unwrap_or_default(),
+        files: files.unwrap_or_default(),
+        never_downloaded: never_downloaded.unwrap_or_default(),
+    })
+}
+
+/// Syncs the file records and search index of every entry with what's on disk.
+///
+/// This takes a while so it's done in the background.
+async fn rescan_library(State(state): State<AppState>, account: Account, flasher: Flasher) -> Response {
+    if !account.flags.is_admin() {
+        return Redirect::to("/").into_response();
+    }
+
+    let entries: Vec<DirectoryEntry> = match state.database().all("SELECT * FROM directory_entry", []).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            return flasher
+                .add(FlashMessage::error(format!("Could not load entries: {e}")))
+                .bail("/admin/duplicates")
+        }
+    };
+
+    tokio::spawn(async move {
+        let total = entries.len();
+        for entry in entries {
+            let storage = state.storage().as_ref();
+            if let Err(e) = files::sync_directory(state.database(), state.storage(), entry.id, &entry.path).await {
+                tracing::warn!(error=%e, entry_id = entry.id, "Could not sync entry files");
+            }
+            if let Err(e) = lines::index_directory(state.database(), storage, entry.id, &entry.path).await {
+                tracing::warn!(error=%e, entry_id = entry.id, "Could not index entry files");
+            }
+        }
+        tracing::info!(total, "Finished rescanning library files");
+    });
+
+    flasher
+        .add(FlashMessage::info(
+            "Rescan started, refresh this page in a few minutes.",
+        ))
+        .bail("/admin/duplicates")
 }
 
 pub fn routes() -> Router<AppState> {
@@ -247,4 +388,7 @@ pub fn routes() -> Router<AppState> {
         .route("/admin/trash", get(show_trash).post(trash_management))
         .route("/admin/trash/download/*path", get(download_trash))
         .route("/admin/cache/invalidate", get(invalidate_caches))
+        .route("/admin/duplicates", get(show_duplicates))
+        .route("/admin/duplicates/rescan", post(rescan_library))
+        .route("/admin/downloads", get(show_downloads))
 }
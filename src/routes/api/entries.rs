@@ -1,16 +1,26 @@
-use axum::extract::{Multipart, State};
+use axum::{
+    extract::{Multipart, State},
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
 use rusqlite::OptionalExtension;
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{
+    aliases,
     anilist::MediaTitle,
+    classify::Language,
     error::{ApiError, ApiErrorCode},
     models::{DirectoryEntry, EntryFlags},
-    relations::{Range as RelationRange, Relations},
+    relations::{Range as RelationRange, Relations, Service},
     routes::entry::{
-        get_file_entries, raw_create_directory_entry, raw_upload_file, FileEntry, PendingDirectoryEntry, UploadResult,
+        expand_archives, fill_downloads, get_file_entries, preview_file, raw_create_directory_entry,
+        raw_edit_directory_entry, raw_retime_file, raw_upload_file, stream_zip, EditDirectoryEntry, FileEntry,
+        PendingDirectoryEntry, RetimeRequest, RetimeResult, UploadQuery, UploadResult, DEFAULT_PREVIEW_CUES,
     },
+    search::{self, NormalizedTitle, SearchIndex},
+    subtitle::{self, Conversion, PreviewCue},
     tmdb, AppState,
 };
 
@@ -60,50 +70,113 @@ pub struct FilesQuery {
     /// are movies.
     #[serde(default)]
     episode: Option<u16>,
-}
-
-fn get_episode_range(filename: &str) -> Option<RelationRange> {
-    let parsed = anitomy::parse(filename);
-    let mut episodes = parsed.iter().filter(|p| p.kind() == anitomy::ElementKind::Episode);
-    let begin = episodes.next().map(|e| e.value()).and_then(|s| s.parse::<u16>().ok())?;
-    let end = episodes.next().map(|e| e.value()).and_then(|s| s.parse::<u16>().ok());
-    match end {
-        Some(end) => Some(RelationRange::Inclusive { begin, end }),
-        None => Some(RelationRange::Number { value: begin }),
-    }
+    /// Also provide a URL to download subtitle files converted to the given format.
+    ///
+    /// The converted URL is given in the `converted_url` field of each file.
+    /// Files that are not SRT, ASS, or SSA files cannot be converted. ASS
+    /// styling and override tags are removed when converting.
+    #[serde(default)]
+    #[param(inline)]
+    format: Option<Conversion>,
+    /// Expand archive files (ZIP and 7z) into the files within them.
+    ///
+    /// The files within an archive are given in the `members` field and can
+    /// be downloaded individually. When combined with `episode`, the members are
+    /// filtered as well.
+    #[serde(default)]
+    expand_archives: bool,
+    /// Return files that are written in the given language.
+    ///
+    /// This is a best-effort guess, based off of the text of the file.
+    /// Files that are not SRT, ASS, or SSA files are not returned.
+    #[serde(default)]
+    language: Option<Language>,
+    /// Return files that are closed caption (CC) tracks if `true`, or dialogue-only if `false`.
+    ///
+    /// This is a best-effort guess, based off of the text of the file.
+    /// Files that are not SRT, ASS, or SSA files are not returned.
+    #[serde(default)]
+    closed_captions: Option<bool>,
+    /// Also provide the number of times each file was downloaded.
+    ///
+    /// The count is given in the `downloads` field of each file. This is
+    /// only available to editors and is ignored for everyone else.
+    #[serde(default)]
+    downloads: bool,
 }
 
 fn get_equivalent_episodes(
     relation: &Relations,
-    anilist_id: Option<u32>,
+    entry: &DirectoryEntry,
     episodes: RelationRange,
 ) -> Option<RelationRange> {
-    let id = anilist_id?;
+    // Entries without an AniList ID might still have a relation under one of their other IDs
+    let ids = [
+        (Service::AniList, entry.anilist_id),
+        (Service::MyAnimeList, entry.mal_id),
+        (Service::Kitsu, entry.kitsu_id),
+    ];
+    let find = |episode: u16| {
+        ids.iter()
+            .find_map(|(service, id)| relation.find_in(*service, (*id)?, episode))
+            .map(|(_, episode)| episode)
+    };
     match episodes {
         RelationRange::Number { value } => {
-            let (_, value) = relation.find(id, value)?;
+            let value = find(value)?;
             Some(RelationRange::Number { value })
         }
         RelationRange::Inclusive { begin, end } => {
-            let (_, begin) = relation.find(id, begin)?;
-            let (_, end) = relation.find(id, end)?;
+            let begin = find(begin)?;
+            let end = find(end)?;
             Some(RelationRange::Inclusive { begin, end })
         }
         _ => None,
     }
 }
 
+/// Keeps the files that match the given episode number, taking the entry's relations into account.
+///
+/// Episode numbers are meaningless for movies so nothing is removed for them.
+async fn retain_episode(files: &mut Vec<FileEntry>, episode: u16, entry: &DirectoryEntry, state: &AppState) {
+    if entry.flags.is_movie() {
+        return;
+    }
+    let guard = state.anime_relations().await;
+    let matches = |range: RelationRange| {
+        range.contains(episode) || get_equivalent_episodes(&guard, entry, range).is_some_and(|eq| eq.contains(episode))
+    };
+    files.retain_mut(|f| {
+        if let Some(members) = f.members.as_mut() {
+            members.retain(|m| m.episode.is_some_and(matches));
+            if !members.is_empty() {
+                return true;
+            }
+        }
+        f.episode.is_some_and(matches)
+    });
+}
+
+/// Turns the relative download URLs of the files and their archive members into absolute ones.
+fn prefix_urls(files: &mut [FileEntry], url: &str) {
+    for file in files.iter_mut() {
+        file.url = format!("{url}{}", file.url);
+        for member in file.members.iter_mut().flatten() {
+            member.url = format!("{url}{}", member.url);
+        }
+    }
+}
+
 impl FilesQuery {
     async fn filter(&self, files: &mut Vec<FileEntry>, entry: &DirectoryEntry, state: &AppState) {
         if let Some(episode) = self.episode {
-            let guard = state.anime_relations().await;
-            files.retain(|f| {
-                let Some(range) = get_episode_range(&f.name) else {
-                    return false;
-                };
-                range.contains(episode)
-                    || get_equivalent_episodes(&guard, entry.anilist_id, range).is_some_and(|eq| eq.contains(episode))
-            });
+            retain_episode(files, episode, entry, state).await;
+        }
+        if let Some(language) = self.language {
+            files.retain(|f| f.language == Some(language));
+        }
+        if let Some(closed_captions) = self.closed_captions {
+            files.retain(|f| f.closed_captions == Some(closed_captions));
         }
     }
 }
@@ -134,17 +207,30 @@ pub async fn get_entry_files(
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Query(query): Query<FilesQuery>,
-    _auth: ApiToken,
+    auth: ApiToken,
 ) -> Result<Json<Vec<FileEntry>>, ApiError> {
     match state.get_directory_entry(id).await {
         Some(entry) => {
-            let mut files = get_file_entries(id, &entry.path)?;
-            if !entry.flags.is_movie() {
-                query.filter(&mut files, &entry, &state).await;
+            let mut files = get_file_entries(&state, id, &entry.path).await?;
+            if query.downloads
+                && state
+                    .get_account(auth.id)
+                    .await
+                    .is_some_and(|acc| acc.flags.is_editor())
+            {
+                fill_downloads(&state, id, &mut files).await?;
+            }
+            if query.expand_archives {
+                expand_archives(state.storage().as_ref(), &entry.path, &mut files).await;
             }
-            let url = state.config().canonical_url();
+            query.filter(&mut files, &entry, &state).await;
+            prefix_urls(&mut files, &state.config().canonical_url());
             for file in files.iter_mut() {
-                file.url = url.clone() + file.url.as_str();
+                if let Some(to) = query.format {
+                    if subtitle::Format::from_path(std::path::Path::new(&file.name)).is_some() {
+                        file.converted_url = Some(format!("{}?format={}", file.url, to.extension()));
+                    }
+                }
             }
             Ok(Json(files))
         }
@@ -152,12 +238,232 @@ pub async fn get_entry_files(
     }
 }
 
+#[derive(Default, IntoParams)]
+pub struct ArchiveQuery {
+    /// Only include files that match the given episode number.
+    ///
+    /// This is a best-effort guess, based off of the filename.
+    /// This query parameter is also ignored for entries that
+    /// are movies.
+    episode: Option<u16>,
+    /// Only include the files with the given names.
+    ///
+    /// This can be given multiple times, e.g. `files[]=01.srt&files[]=02.srt`.
+    #[param(rename = "files[]")]
+    files: Vec<String>,
+    /// Convert subtitle files to the given format.
+    ///
+    /// Files that are not SRT, ASS, or SSA files, or that would be renamed to
+    /// the name of another file, are included as-is. ASS styling and override
+    /// tags are removed when converting.
+    #[param(inline)]
+    format: Option<Conversion>,
+}
+
+impl ArchiveQuery {
+    /// Parses the query from its key-value pairs, since `files[]` can be given multiple times.
+    fn from_pairs(pairs: Vec<(String, String)>) -> Result<Self, ApiError> {
+        let mut query = Self::default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "episode" => {
+                    let episode = value.parse().map_err(|_| ApiError::new("Invalid episode number"))?;
+                    query.episode = Some(episode);
+                }
+                "files[]" | "files" => query.files.push(value),
+                "format" => {
+                    let format = Conversion::from_extension(&value).ok_or(ApiError::new("Invalid format"))?;
+                    query.format = Some(format);
+                }
+                _ => {}
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Archive
+///
+/// Download the files of an entry as a ZIP archive.
+///
+/// Without any filters, every file in the entry is included. The archive is
+/// sent while it's being written so the size is not known in advance.
+///
+/// This has a stricter rate limit than the other endpoints.
+#[utoipa::path(
+    get,
+    path = "/api/entries/{id}/archive",
+    responses(
+        (status = 200, description = "The ZIP archive", content_type = "application/zip", body = inline(ArchiveBody)),
+        (status = 400, description = "Invalid query given", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Entry not found or no files matched", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = i64, Path, description = "The entry's ID"),
+        ArchiveQuery
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "entries"
+)]
+pub async fn download_archive(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(pairs): Query<Vec<(String, String)>>,
+    _auth: ApiToken,
+) -> Result<Response, ApiError> {
+    let query = ArchiveQuery::from_pairs(pairs)?;
+    let Some(entry) = state.get_directory_entry(id).await else {
+        return Err(ApiError::not_found("This entry could not be found"));
+    };
+
+    let mut files = get_file_entries(&state, id, &entry.path).await?;
+    if let Some(episode) = query.episode {
+        retain_episode(&mut files, episode, &entry, &state).await;
+    }
+    if !query.files.is_empty() {
+        files.retain(|f| query.files.contains(&f.name));
+    }
+    if files.is_empty() {
+        return Err(ApiError::not_found("No files matched"));
+    }
+
+    let paths = files.into_iter().map(|f| entry.path.join(f.name)).collect::<Vec<_>>();
+    let filename = sanitise_file_name::sanitise(&format!("{}.zip", &entry.name));
+    Ok(stream_zip(state, id, filename, paths, query.format))
+}
+
+#[derive(ToSchema)]
+struct ArchiveBody {
+    #[schema(format = Binary)]
+    #[allow(dead_code)]
+    archive: String,
+}
+
+fn default_preview_limit() -> usize {
+    DEFAULT_PREVIEW_CUES
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PreviewQuery {
+    /// The maximum number of cues to return, up to 100.
+    #[serde(default = "default_preview_limit")]
+    #[param(default = 10, maximum = 100)]
+    limit: usize,
+}
+
+/// Preview
+///
+/// Get the first cues of an SRT, ASS, or SSA file in an entry.
+///
+/// The cues are given in the order they're displayed with their formatting
+/// removed. This can be used to check the language and timing of a file
+/// before downloading it.
+#[utoipa::path(
+    get,
+    path = "/api/entries/{id}/files/{name}/preview",
+    responses(
+        (status = 200, description = "Successful response", body = [PreviewCue]),
+        (status = 400, description = "The file could not be previewed", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Entry or file not found", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = i64, Path, description = "The entry's ID"),
+        ("name" = String, Path, description = "The file's name"),
+        PreviewQuery
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "entries"
+)]
+pub async fn preview_entry_file(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(i64, String)>,
+    Query(query): Query<PreviewQuery>,
+    _auth: ApiToken,
+) -> Result<Json<Vec<PreviewCue>>, ApiError> {
+    let Some(path) = state.get_directory_entry_path(id).await else {
+        return Err(ApiError::not_found("This entry could not be found"));
+    };
+    let cues = preview_file(&state, &path, &name, query.limit).await?;
+    Ok(Json(cues))
+}
+
+/// A filter on whether an entry has a flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ToSchema)]
+pub enum FlagFilter {
+    /// Only return entries that have the flag.
+    #[serde(rename = "true")]
+    Only,
+    /// Only return entries that do not have the flag.
+    #[serde(rename = "false")]
+    Exclude,
+    /// Return entries regardless of the flag.
+    #[default]
+    #[serde(rename = "any")]
+    Any,
+}
+
+impl FlagFilter {
+    fn matches(self, value: bool) -> bool {
+        match self {
+            Self::Only => value,
+            Self::Exclude => !value,
+            Self::Any => true,
+        }
+    }
+
+    fn only() -> Self {
+        Self::Only
+    }
+}
+
+/// The order that search results are returned in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSort {
+    /// The best matches first. Entries that match equally are sorted by name.
+    #[default]
+    Score,
+    /// By name, alphabetically.
+    Name,
+    /// The most recently updated first.
+    LastModified,
+}
+
+/// The header that holds the cursor to the next page of search results.
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
 #[derive(Deserialize, IntoParams)]
 pub struct SearchQuery {
     /// Return entries that are anime.
-    #[serde(default = "crate::utils::default_true")]
-    #[param(default = true)]
-    anime: bool,
+    ///
+    /// Pass `any` to return entries regardless of whether they're anime.
+    #[serde(default = "FlagFilter::only")]
+    #[param(inline, default = "true")]
+    anime: FlagFilter,
+    /// Return entries that are, or are not, unverified.
+    #[serde(default)]
+    #[param(inline)]
+    unverified: FlagFilter,
+    /// Return entries that are, or are not, from an external source.
+    #[serde(default)]
+    #[param(inline)]
+    external: FlagFilter,
+    /// Return entries that are, or are not, movies.
+    #[serde(default)]
+    #[param(inline)]
+    movie: FlagFilter,
+    /// Return entries that are, or are not, adult content.
+    #[serde(default)]
+    #[param(inline)]
+    adult: FlagFilter,
     /// Return the entry that has the given AniList ID.
     #[serde(default)]
     anilist_id: Option<u32>,
@@ -168,10 +474,17 @@ pub struct SearchQuery {
     #[param(pattern = r#"(tv|movie):(\d+)"#, value_type = Option<String>, example = "tv:12345")]
     #[serde(default)]
     tmdb_id: Option<tmdb::Id>,
+    /// Return the entry that has the given MyAnimeList ID.
+    #[serde(default)]
+    mal_id: Option<u32>,
+    /// Return the entry that has the given Kitsu ID.
+    #[serde(default)]
+    kitsu_id: Option<u32>,
     /// Return entries that match the given string.
     ///
     /// Currently this search is done through a fuzzy
-    /// search.
+    /// search. Romaji, hiragana and katakana are matched
+    /// interchangeably, e.g. `shingeki` matches `しんげき`.
     #[serde(deserialize_with = "crate::utils::generic_empty_string_is_none")]
     #[serde(default)]
     query: Option<String>,
@@ -183,23 +496,40 @@ pub struct SearchQuery {
     /// Return entries that are before this UNIX timestamp (in seconds).
     #[serde(default)]
     before: Option<i64>,
+
+    /// The order to return entries in.
+    #[serde(default)]
+    #[param(inline)]
+    sort: SearchSort,
+
+    /// The maximum number of entries to return, up to 500.
+    ///
+    /// If not given then every matching entry is returned. When there are more
+    /// entries to return, the cursor to the next page is given in the
+    /// `x-next-cursor` response header.
+    #[serde(default)]
+    #[param(maximum = 500)]
+    limit: Option<usize>,
+
+    /// Return the page of entries that starts at this cursor.
+    ///
+    /// This is the value of the `x-next-cursor` header of the previous page. The
+    /// other parameters should be the same as the ones used for the previous page.
+    #[serde(default)]
+    cursor: Option<usize>,
 }
 
 impl SearchQuery {
-    fn get_best_fuzzy_score(&self, entry: &DirectoryEntry) -> Option<sublime_fuzzy::Match> {
-        let query = self.query.as_deref()?;
-        let mut max = sublime_fuzzy::best_match(query, &entry.name);
-        if let Some(target) = entry.english_name.as_deref() {
-            max = max.max(sublime_fuzzy::best_match(query, target));
-        }
-        if let Some(target) = entry.japanese_name.as_deref() {
-            max = max.max(sublime_fuzzy::best_match(query, target));
-        }
-        max
-    }
-
-    fn apply(&self, entry: &DirectoryEntry) -> Option<isize> {
-        if self.anime != entry.flags.is_anime() {
+    fn apply(&self, entry: &DirectoryEntry, index: &SearchIndex, query: Option<&NormalizedTitle>) -> Option<isize> {
+        let flags = entry.flags;
+        let filters = [
+            (self.anime, flags.is_anime()),
+            (self.unverified, flags.is_unverified()),
+            (self.external, flags.is_external()),
+            (self.movie, flags.is_movie()),
+            (self.adult, flags.is_adult()),
+        ];
+        if !filters.into_iter().all(|(filter, value)| filter.matches(value)) {
             return None;
         }
 
@@ -211,6 +541,14 @@ impl SearchQuery {
             return (self.tmdb_id == entry.tmdb_id).then_some(isize::MAX);
         }
 
+        if self.mal_id.is_some() {
+            return (self.mal_id == entry.mal_id).then_some(isize::MAX);
+        }
+
+        if self.kitsu_id.is_some() {
+            return (self.kitsu_id == entry.kitsu_id).then_some(isize::MAX);
+        }
+
         let ts = entry.last_updated_at.unix_timestamp();
         if let Some(after) = self.after {
             if ts < after {
@@ -224,22 +562,27 @@ impl SearchQuery {
             }
         }
 
-        if let Some(m) = self.get_best_fuzzy_score(entry) {
-            return (m.score() >= 100).then_some(m.score());
+        match query {
+            Some(query) => index.score(entry.id, query),
+            None => Some(search::MIN_SCORE),
         }
-
-        self.query.is_none().then_some(100)
     }
 }
 
 /// Search
 ///
 /// Returns all entries that meet a specific criteria.
+///
+/// Results can be paginated by passing `limit`. When there are more entries
+/// than fit in a page, the `x-next-cursor` response header holds the `cursor`
+/// to pass in order to get the next page.
 #[utoipa::path(
     get,
     path = "/api/entries/search",
     responses(
-        (status = 200, description = "Successful response", body = [Entry]),
+        (status = 200, description = "Successful response", body = [Entry], headers(
+            ("x-next-cursor" = usize, description = "The cursor to the next page, if there are more entries")
+        )),
         (status = 401, description = "User is unauthenticated", body = ApiError),
         (status = 429, response = RateLimitResponse),
     ),
@@ -253,14 +596,125 @@ pub async fn search_entries(
     State(state): State<AppState>,
     Query(query): Query<SearchQuery>,
     _auth: ApiToken,
-) -> Result<Json<Vec<DirectoryEntry>>, ApiError> {
-    let entries = state.directory_entries().await;
-    let mut entries = entries
+) -> Result<(HeaderMap, Json<Vec<DirectoryEntry>>), ApiError> {
+    let listing = state.directory_listing().await;
+    let normalized = query.query.as_deref().map(NormalizedTitle::new);
+    let mut entries = listing
+        .entries
         .iter()
-        .filter_map(|s| query.apply(s).zip(Some(s.clone())))
+        .filter_map(|s| query.apply(s, &listing.index, normalized.as_ref()).zip(Some(s)))
         .collect::<Vec<_>>();
-    entries.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
-    Ok(Json(entries.into_iter().map(|(_, entry)| entry).collect()))
+    match query.sort {
+        SearchSort::Score => entries.sort_by_key(|(score, _)| std::cmp::Reverse(*score)),
+        SearchSort::Name => entries.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name).then(a.id.cmp(&b.id))),
+        SearchSort::LastModified => {
+            entries.sort_by(|(_, a), (_, b)| b.last_updated_at.cmp(&a.last_updated_at).then(a.id.cmp(&b.id)))
+        }
+    }
+
+    let start = query.cursor.unwrap_or(0).min(entries.len());
+    let end = match query.limit {
+        Some(limit) => start.saturating_add(limit.clamp(1, 500)).min(entries.len()),
+        None => entries.len(),
+    };
+    let mut headers = HeaderMap::new();
+    if end < entries.len() {
+        headers.insert(NEXT_CURSOR_HEADER, HeaderValue::from(end));
+    }
+    let page = entries[start..end].iter().map(|(_, entry)| (*entry).clone()).collect();
+    Ok((headers, Json(page)))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct MatchQuery {
+    /// The filename of the video file, e.g. `[Group] Sousou no Frieren - 05 (1080p).mkv`.
+    filename: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MatchResult {
+    /// The entry that best matches the title in the filename.
+    entry: DirectoryEntry,
+    /// A relative fuzzy score of the match, from 0 to 1.
+    ///
+    /// This is the score of the parsed title against the entry's best matching title
+    /// divided by the score of the parsed title against itself. It is only useful for
+    /// comparing matches, since a value of 1 does not mean the titles are equal, e.g.
+    /// a parsed title that is a prefix of the entry's title also gets 1.
+    #[schema(example = 0.95)]
+    confidence: f32,
+    /// The title that was parsed from the filename.
+    #[schema(example = "Sousou no Frieren")]
+    title: String,
+    /// The episode number that was parsed from the filename, if any.
+    #[schema(example = 5)]
+    episode: Option<u16>,
+    /// The entry's files for the episode.
+    ///
+    /// If no episode number could be parsed or the entry is a movie then every file is returned.
+    files: Vec<FileEntry>,
+}
+
+/// Match
+///
+/// Finds the entry and subtitle files for a video file based off of its filename.
+///
+/// The filename is parsed to get the title and episode number of the release. The
+/// title is searched for the same way the `query` parameter of the search endpoint is.
+/// Episode numbers are remapped using anime relations, the same way the `episode`
+/// parameter of the files endpoint is.
+///
+/// This is a best-effort guess, so check the `confidence` of the result.
+#[utoipa::path(
+    get,
+    path = "/api/match",
+    responses(
+        (status = 200, description = "Successful response", body = inline(MatchResult)),
+        (status = 400, description = "No title could be parsed from the filename", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "No entry matched the title", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(MatchQuery),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "entries"
+)]
+pub async fn match_filename(
+    State(state): State<AppState>,
+    Query(query): Query<MatchQuery>,
+    _auth: ApiToken,
+) -> Result<Json<MatchResult>, ApiError> {
+    let Some(title) = crate::files::release_title(&query.filename) else {
+        return Err(ApiError::new("Could not find a title in the filename"));
+    };
+    let episode = crate::files::episode_range(&query.filename).map(|r| r.begin());
+    let normalized = NormalizedTitle::new(&title);
+    let (entry, score) = {
+        let listing = state.directory_listing().await;
+        let found = listing
+            .index
+            .best_match(&normalized)
+            .and_then(|(id, score)| Some((listing.entries.iter().find(|e| e.id == id)?.clone(), score)));
+        match found {
+            Some(found) => found,
+            None => return Err(ApiError::not_found("No entry matched the title in the filename")),
+        }
+    };
+
+    let mut files = get_file_entries(&state, entry.id, &entry.path).await?;
+    if let Some(episode) = episode {
+        retain_episode(&mut files, episode, &entry, &state).await;
+    }
+    prefix_urls(&mut files, &state.config().canonical_url());
+    Ok(Json(MatchResult {
+        confidence: normalized.confidence(score),
+        entry,
+        title,
+        episode,
+        files,
+    }))
 }
 
 #[derive(Deserialize, IntoParams)]
@@ -410,6 +864,135 @@ pub async fn create_entry(
     Ok(Json(CreateEntryResult { entry_id }))
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct EditPayload {
+    /// The new Romaji name of the entry.
+    #[serde(default)]
+    name: Option<String>,
+    /// The new Japanese name of the entry, or `null` to remove it.
+    #[serde(default, deserialize_with = "crate::utils::nullable")]
+    #[schema(value_type = Option<String>)]
+    japanese_name: Option<Option<String>>,
+    /// The new English name of the entry, or `null` to remove it.
+    #[serde(default, deserialize_with = "crate::utils::nullable")]
+    #[schema(value_type = Option<String>)]
+    english_name: Option<Option<String>>,
+    /// The new notes of the entry, or `null` to remove them.
+    ///
+    /// Supports a limited set of markdown.
+    #[serde(default, deserialize_with = "crate::utils::nullable")]
+    #[schema(value_type = Option<String>)]
+    notes: Option<Option<String>>,
+    /// The new AniList ID of the entry, or `null` to remove it.
+    #[serde(default, deserialize_with = "crate::utils::nullable")]
+    #[schema(value_type = Option<u32>)]
+    anilist_id: Option<Option<u32>>,
+    /// The new TMDB ID of the entry, or `null` to remove it.
+    ///
+    /// Check the documentation for the string TMDB ID encoding.
+    #[serde(default, deserialize_with = "crate::utils::nullable")]
+    #[schema(pattern = r#"(tv|movie):(\d+)"#, value_type = Option<String>, example = "tv:12345")]
+    tmdb_id: Option<Option<tmdb::Id>>,
+    /// The new flags of the entry.
+    ///
+    /// Flags that are not given are unset. The `external` flag cannot be changed.
+    #[serde(default, with = "crate::models::expand_flags::option")]
+    flags: Option<EntryFlags>,
+    /// The new aliases of the entry, replacing the current ones.
+    #[serde(default)]
+    aliases: Option<Vec<String>>,
+}
+
+impl EditPayload {
+    /// Returns the edit to make to the entry, keeping everything that was not given as-is.
+    fn into_edit(self, entry: &DirectoryEntry) -> EditDirectoryEntry {
+        let mut edit = EditDirectoryEntry::from(entry);
+        if let Some(name) = self.name {
+            edit.name = name;
+        }
+        if let Some(japanese_name) = self.japanese_name {
+            edit.japanese_name = japanese_name;
+        }
+        if let Some(english_name) = self.english_name {
+            edit.english_name = english_name;
+        }
+        if let Some(notes) = self.notes {
+            edit.notes = notes;
+        }
+        if let Some(anilist_id) = self.anilist_id {
+            edit.anilist_id = anilist_id;
+        }
+        if let Some(tmdb_id) = self.tmdb_id {
+            edit.tmdb_id = tmdb_id;
+        }
+        if let Some(flags) = self.flags {
+            edit.unverified = flags.is_unverified();
+            edit.adult = flags.is_adult();
+            edit.movie = flags.is_movie();
+            edit.anime = flags.is_anime();
+        }
+        edit.aliases = self.aliases.map(aliases::normalize);
+        edit
+    }
+}
+
+/// Edit
+///
+/// Edits the details of an entry.
+///
+/// Only the fields that are given are changed. Fields that can be removed
+/// are removed by giving `null`. The updated entry is returned.
+///
+/// This is only available for API keys bound to editor users.
+#[utoipa::path(
+    patch,
+    path = "/api/entries/{id}",
+    request_body = inline(EditPayload),
+    responses(
+        (status = 200, description = "Successfully edited entry", body = Entry),
+        (status = 400, description = "An error occurred", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 403, description = "The user does not have permission to do this", body = ApiError),
+        (status = 404, description = "Entry not found", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = i64, Path, description = "The entry's ID")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "entries"
+)]
+pub async fn edit_entry(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    auth: ApiToken,
+    Json(payload): Json<EditPayload>,
+) -> Result<Json<DirectoryEntry>, ApiError> {
+    let Some(account) = state.get_account(auth.id).await else {
+        return Err(ApiError::unauthorized());
+    };
+    if !account.flags.is_editor() {
+        return Err(ApiError::forbidden());
+    }
+    let Some(entry) = state.get_directory_entry(id).await else {
+        return Err(ApiError::not_found("This entry could not be found"));
+    };
+
+    let edit = payload.into_edit(&entry);
+    let validation_errors = edit.validate();
+    if !validation_errors.is_empty() {
+        return Err(ApiError::new(validation_errors.join("\n")));
+    }
+
+    raw_edit_directory_entry(&state, &account, entry, edit).await?;
+    match state.get_directory_entry(id).await {
+        Some(entry) => Ok(Json(entry)),
+        None => Err(ApiError::not_found("This entry could not be found")),
+    }
+}
+
 #[derive(ToSchema)]
 struct UploadedFiles {
     #[schema(format = Binary)]
@@ -424,6 +1007,23 @@ struct UploadedFiles {
 /// Multiple files can be uploaded at a time. The field name should be
 /// `file` and the `filename` should point to the subtitle filename.
 /// You can have multiple `file` fields.
+///
+/// SRT, ASS, and SSA files are checked to be well formed before being
+/// accepted. Files that fail any check are skipped and the reason is
+/// given in the `rejected` field of the response. Text files that are not
+/// encoded in UTF-8 are converted to UTF-8 unless `keep_encoding` is set.
+///
+/// ZIP and 7z archives are rejected if they are too large when uncompressed,
+/// have too many files, contain absolute or `..` paths, or have archives
+/// nested too deeply within them.
+///
+/// Files that are exact copies of a file already stored anywhere in the
+/// library are rejected and the existing file is given in `duplicate_of`,
+/// unless `allow_duplicates` is set.
+///
+/// Subtitle files that are mostly not in Japanese are held for review
+/// instead of being stored, unless the user is an editor. These files are
+/// given in the `held` field of the response.
 #[utoipa::path(
     post,
     path = "/api/entries/{id}/upload",
@@ -441,7 +1041,8 @@ struct UploadedFiles {
         (status = 429, response = RateLimitResponse),
     ),
     params(
-        ("id" = i64, Path, description = "The entry's ID")
+        ("id" = i64, Path, description = "The entry's ID"),
+        UploadQuery
     ),
     security(
         ("api_key" = [])
@@ -452,14 +1053,60 @@ pub async fn upload_files(
     State(state): State<AppState>,
     Path(entry_id): Path<i64>,
     auth: ApiToken,
+    Query(options): Query<UploadQuery>,
     multipart: Multipart,
 ) -> Result<Json<UploadResult>, ApiError> {
     let Some(account) = state.get_account(auth.id).await else {
         return Err(ApiError::unauthorized());
     };
-    let result = raw_upload_file(state, entry_id, account, multipart, true).await?;
+    let result = raw_upload_file(state, entry_id, account, multipart, options, true).await?;
     if result.is_error() {
         return Err(ApiError::new("Upload failed"));
     }
     Ok(Json(result))
 }
+
+/// Retime
+///
+/// Saves a retimed copy of an SRT, ASS, or SSA file to the same entry.
+///
+/// Every timestamp is shifted by `shift` milliseconds and, if both `from_fps`
+/// and `to_fps` are given, scaled to convert the timing between the two framerates.
+/// The framerate conversion is applied before the shift. Lines that end up
+/// entirely before the start of the video are removed.
+///
+/// If you only want to download a retimed file without saving it, use the
+/// `shift`, `from_fps`, and `to_fps` query parameters when downloading instead.
+#[utoipa::path(
+    post,
+    path = "/api/entries/{id}/retime",
+    request_body = RetimeRequest,
+    responses(
+        (status = 200, description = "The retimed copy was saved", body = RetimeResult),
+        (status = 400, description = "An error occurred", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 403, description = "The user does not have permission to do this", body = ApiError),
+        (status = 404, description = "Entry or file not found", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = i64, Path, description = "The entry's ID"),
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "entries"
+)]
+pub async fn retime_file(
+    State(state): State<AppState>,
+    Path(entry_id): Path<i64>,
+    auth: ApiToken,
+    Json(request): Json<RetimeRequest>,
+) -> Result<Json<RetimeResult>, ApiError> {
+    let Some(account) = state.get_account(auth.id).await else {
+        return Err(ApiError::unauthorized());
+    };
+    raw_retime_file(&state, entry_id, &account, request, true)
+        .await
+        .map(Json)
+}
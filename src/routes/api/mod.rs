@@ -1,5 +1,7 @@
 mod auth;
 mod entries;
+mod search;
+mod uploads;
 pub mod utils;
 
 use crate::{filters, models::Account, ratelimit::RateLimit, AppState};
@@ -8,9 +10,9 @@ use axum::{
     extract::State,
     http::{
         header::{AUTHORIZATION, USER_AGENT},
-        Method,
+        HeaderName, Method,
     },
-    routing::{get, post},
+    routing::{get, post, put},
     Json, Router,
 };
 use tower_http::cors::{AllowOrigin, CorsLayer};
@@ -32,9 +34,20 @@ pub use auth::{copy_api_token, ApiToken};
     paths(
         entries::get_entry_by_id,
         entries::get_entry_files,
+        entries::preview_entry_file,
+        entries::download_archive,
         entries::search_entries,
+        entries::match_filename,
         entries::create_entry,
+        entries::edit_entry,
         entries::upload_files,
+        entries::retime_file,
+        uploads::create_upload,
+        uploads::get_upload,
+        uploads::upload_chunk,
+        uploads::finalize_upload,
+        uploads::cancel_upload,
+        search::search_lines,
     ),
     components(
         schemas(
@@ -42,13 +55,28 @@ pub use auth::{copy_api_token, ApiToken};
             crate::models::EntryFlags,
             crate::models::DirectoryEntry,
             crate::routes::entry::FileEntry,
+            crate::routes::entry::ArchiveMember,
             crate::routes::entry::UploadResult,
+            crate::routes::entry::RejectedFile,
+            crate::routes::entry::DuplicateUpload,
+            crate::routes::entry::RetimeRequest,
+            crate::routes::entry::RetimeResult,
+            crate::files::DuplicateFile,
+            crate::relations::Range,
+            crate::classify::Language,
+            crate::subtitle::PreviewCue,
+            crate::lines::LineMatch,
+            crate::upload::UploadSession,
+            crate::upload::UploadProgress,
+            uploads::CreateUploadSession,
         ),
         responses(utils::RateLimitResponse),
     ),
     modifiers(&RequiredAuthentication),
     tags(
-        (name = "entries", description = "Working with entries on the site")
+        (name = "entries", description = "Working with entries on the site"),
+        (name = "uploads", description = "Uploading large files in chunks"),
+        (name = "search", description = "Searching the contents of subtitle files")
     )
 )]
 pub struct Schema;
@@ -89,17 +117,35 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/openapi.json", get(spec))
         .route("/docs", get(docs))
-        .route("/entries/:id", get(entries::get_entry_by_id))
+        .route("/entries/:id", get(entries::get_entry_by_id).patch(entries::edit_entry))
         .route("/entries/:id/files", get(entries::get_entry_files))
+        .route("/entries/:id/files/:name/preview", get(entries::preview_entry_file))
         .route("/entries/search", get(entries::search_entries))
+        .route("/match", get(entries::match_filename))
         .route("/entries", post(entries::create_entry))
         .route("/entries/:id/upload", post(entries::upload_files))
+        .route("/entries/:id/retime", post(entries::retime_file))
+        .route("/entries/:id/uploads", post(uploads::create_upload))
+        .route("/uploads/:id", get(uploads::get_upload).delete(uploads::cancel_upload))
+        .route("/uploads/:id/finalize", post(uploads::finalize_upload))
+        .route("/search/lines", get(search::search_lines))
         .route_layer(RateLimit::default().quota(25, 60.0).build())
+        // Chunks are added after the global rate limit since a single upload can need many of them
+        .route(
+            "/uploads/:id/chunks/:index",
+            put(uploads::upload_chunk).layer(RateLimit::default().quota(120, 60.0).build()),
+        )
+        // Archives have their own, stricter, rate limit instead of the global one
+        .route(
+            "/entries/:id/archive",
+            get(entries::download_archive).layer(RateLimit::default().quota(5, 60.0).build()),
+        )
         .route_layer(
             CorsLayer::new()
-                .allow_methods([Method::GET, Method::POST])
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
                 .allow_credentials(true)
                 .allow_origin(AllowOrigin::mirror_request())
-                .allow_headers([AUTHORIZATION, USER_AGENT]),
+                .allow_headers([AUTHORIZATION, USER_AGENT])
+                .expose_headers([HeaderName::from_static(entries::NEXT_CURSOR_HEADER)]),
         )
 }
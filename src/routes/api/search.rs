@@ -0,0 +1,68 @@
+use axum::extract::State;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{error::ApiError, lines::LineMatch, AppState};
+
+use super::{
+    auth::ApiToken,
+    utils::{ApiJson as Json, ApiQuery as Query, RateLimitResponse},
+};
+
+fn default_limit() -> usize {
+    25
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct LinesQuery {
+    /// The text to search for.
+    ///
+    /// Lines that contain the text as a phrase are returned. Japanese text
+    /// does not need to be split into words.
+    q: String,
+    /// Only return lines from files in the entry with the given ID.
+    #[serde(default)]
+    entry_id: Option<i64>,
+    /// The maximum number of lines to return, up to 100.
+    #[serde(default = "default_limit")]
+    #[param(default = 25, maximum = 100)]
+    limit: usize,
+}
+
+/// Search Lines
+///
+/// Searches the text of every SRT, ASS, and SSA file for a line.
+///
+/// Formatting such as ASS override tags is removed before searching.
+/// The best matches are returned first.
+#[utoipa::path(
+    get,
+    path = "/api/search/lines",
+    responses(
+        (status = 200, description = "Successful response", body = [LineMatch]),
+        (status = 400, description = "Invalid query given", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(LinesQuery),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "search"
+)]
+pub async fn search_lines(
+    State(state): State<AppState>,
+    Query(query): Query<LinesQuery>,
+    _auth: ApiToken,
+) -> Result<Json<Vec<LineMatch>>, ApiError> {
+    if query.q.trim().is_empty() {
+        return Err(ApiError::new("Query cannot be empty"));
+    }
+    if query.q.chars().count() > 256 {
+        return Err(ApiError::new("Query can only be up to 256 characters long"));
+    }
+
+    let limit = query.limit.clamp(1, 100);
+    let lines = crate::lines::search(state.database(), &query.q, query.entry_id, limit).await?;
+    Ok(Json(lines))
+}
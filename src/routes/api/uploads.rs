@@ -0,0 +1,283 @@
+use axum::{body::Bytes, extract::State, http::StatusCode};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    error::ApiError,
+    models::Account,
+    routes::entry::{raw_upload_assembled_file, verify_extension, UploadQuery, UploadResult},
+    storage,
+    upload::{NewUploadSession, UploadProgress, UploadSessions},
+    AppState,
+};
+
+use super::{
+    auth::ApiToken,
+    utils::{ApiJson as Json, ApiPath as Path, ApiQuery as Query, RateLimitResponse},
+};
+
+async fn get_account(state: &AppState, auth: ApiToken) -> Result<Account, ApiError> {
+    state.get_account(auth.id).await.ok_or_else(ApiError::unauthorized)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateUploadSession {
+    /// The file's name.
+    name: String,
+    /// The file's total size in bytes, up to 256 MiB.
+    size: u64,
+    /// The SHA-256 hash of the file's contents, as a hexadecimal string.
+    ///
+    /// This is checked once every chunk is uploaded.
+    sha256: String,
+    /// The size of every chunk in bytes, except the last one which holds the remainder.
+    ///
+    /// This must be between 256 KiB and 16 MiB. Defaults to 8 MiB.
+    #[serde(default)]
+    chunk_size: Option<u64>,
+}
+
+/// Create Upload
+///
+/// Starts an upload of a file that is too large to upload in a single request.
+///
+/// After the upload is created, the file is split into chunks of `chunk_size`
+/// bytes and each of them is uploaded separately. Once every chunk is uploaded
+/// the upload is finalized, at which point the file goes through the same
+/// checks as a regular upload.
+///
+/// Uploads that go 24 hours without any activity expire. Only 5 uploads can be
+/// in progress at a time.
+#[utoipa::path(
+    post,
+    path = "/api/entries/{id}/uploads",
+    request_body = CreateUploadSession,
+    responses(
+        (status = 200, description = "Upload created", body = UploadProgress),
+        (status = 400, description = "An error occurred", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Entry not found", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = i64, Path, description = "The entry's ID"),
+        UploadQuery
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "uploads"
+)]
+pub async fn create_upload(
+    State(state): State<AppState>,
+    Path(entry_id): Path<i64>,
+    auth: ApiToken,
+    Query(options): Query<UploadQuery>,
+    Json(payload): Json<CreateUploadSession>,
+) -> Result<Json<UploadProgress>, ApiError> {
+    let account = get_account(&state, auth).await?;
+    let Some(entry) = state.get_directory_entry(entry_id).await else {
+        return Err(ApiError::not_found("Entry not found"));
+    };
+
+    let name = sanitise_file_name::sanitise(&payload.name);
+    if verify_extension(std::path::Path::new(&name)).is_err() {
+        return Err(ApiError::new("Invalid file extension"));
+    }
+    if storage::exists(state.storage().as_ref(), &entry.path.join(&name)).await {
+        return Err(ApiError::new("Filename already exists"));
+    }
+
+    let sessions = UploadSessions::new()?;
+    let session = sessions
+        .create(
+            state.database(),
+            NewUploadSession {
+                entry_id,
+                account_id: account.id,
+                name,
+                size: payload.size,
+                sha256: payload.sha256,
+                chunk_size: payload.chunk_size,
+                options: serde_json::to_string(&options)?,
+            },
+        )
+        .await?;
+    Ok(Json(sessions.progress(session).await?))
+}
+
+/// Upload Progress
+///
+/// Get the chunks that were received by an upload.
+///
+/// This can be used to resume an upload that was interrupted.
+#[utoipa::path(
+    get,
+    path = "/api/uploads/{id}",
+    responses(
+        (status = 200, description = "Successful response", body = UploadProgress),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Upload not found or expired", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = String, Path, description = "The upload's ID")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "uploads"
+)]
+pub async fn get_upload(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: ApiToken,
+) -> Result<Json<UploadProgress>, ApiError> {
+    let sessions = UploadSessions::new()?;
+    let session = sessions.get(state.database(), &id, auth.id).await?;
+    Ok(Json(sessions.progress(session).await?))
+}
+
+/// Upload Chunk
+///
+/// Uploads a single chunk of the file, given as the raw request body.
+///
+/// Chunks are numbered starting from 0 and can be uploaded in any order.
+/// Every chunk must be exactly `chunk_size` bytes except the last one, which
+/// holds the remainder. Uploading a chunk again replaces it.
+///
+/// This has a more lenient rate limit than the other endpoints.
+#[utoipa::path(
+    put,
+    path = "/api/uploads/{id}/chunks/{index}",
+    request_body(
+        content = inline(ChunkBody),
+        content_type = "application/octet-stream",
+        description = "The chunk's contents"
+    ),
+    responses(
+        (status = 200, description = "Chunk received", body = UploadProgress),
+        (status = 400, description = "An error occurred", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Upload not found or expired", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = String, Path, description = "The upload's ID"),
+        ("index" = u32, Path, description = "The chunk's index"),
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "uploads"
+)]
+pub async fn upload_chunk(
+    State(state): State<AppState>,
+    Path((id, index)): Path<(String, u32)>,
+    auth: ApiToken,
+    body: Bytes,
+) -> Result<Json<UploadProgress>, ApiError> {
+    let sessions = UploadSessions::new()?;
+    let mut session = sessions.get(state.database(), &id, auth.id).await?;
+    sessions
+        .write_chunk(state.database(), &mut session, index, &body)
+        .await?;
+    Ok(Json(sessions.progress(session).await?))
+}
+
+#[derive(ToSchema)]
+struct ChunkBody {
+    #[schema(format = Binary)]
+    #[allow(dead_code)]
+    chunk: String,
+}
+
+/// Finalize Upload
+///
+/// Puts the uploaded chunks together and stores the file in the entry.
+///
+/// The file is checked against the size and SHA-256 hash given when the
+/// upload was created. If the hash does not match, the upload is kept so
+/// the affected chunks can be uploaded again.
+///
+/// Otherwise, the file goes through the same checks as a regular upload and
+/// the upload is removed regardless of the outcome.
+#[utoipa::path(
+    post,
+    path = "/api/uploads/{id}/finalize",
+    responses(
+        (status = 200, description = "Upload processed", body = UploadResult),
+        (status = 400, description = "An error occurred", body = ApiError),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Upload or entry not found", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = String, Path, description = "The upload's ID")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "uploads"
+)]
+pub async fn finalize_upload(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: ApiToken,
+) -> Result<Json<UploadResult>, ApiError> {
+    let account = get_account(&state, auth).await?;
+    let sessions = UploadSessions::new()?;
+    let session = sessions.get(state.database(), &id, account.id).await?;
+    let bytes = sessions.assemble(&session).await?;
+    let options: UploadQuery = serde_json::from_str(&session.options).unwrap_or_default();
+    let result = raw_upload_assembled_file(
+        state.clone(),
+        session.entry_id,
+        account,
+        session.name,
+        bytes,
+        options,
+        true,
+    )
+    .await;
+    if let Err(e) = sessions.remove(state.database(), &session.id).await {
+        tracing::error!(error=%e, "Could not remove finished upload session");
+    }
+
+    let result = result?;
+    if result.is_error() {
+        return Err(ApiError::new("Upload failed"));
+    }
+    Ok(Json(result))
+}
+
+/// Cancel Upload
+///
+/// Cancels an upload and removes the chunks that were uploaded.
+#[utoipa::path(
+    delete,
+    path = "/api/uploads/{id}",
+    responses(
+        (status = 204, description = "Upload cancelled"),
+        (status = 401, description = "User is unauthenticated", body = ApiError),
+        (status = 404, description = "Upload not found or expired", body = ApiError),
+        (status = 429, response = RateLimitResponse),
+    ),
+    params(
+        ("id" = String, Path, description = "The upload's ID")
+    ),
+    security(
+        ("api_key" = [])
+    ),
+    tag = "uploads"
+)]
+pub async fn cancel_upload(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    auth: ApiToken,
+) -> Result<StatusCode, ApiError> {
+    let sessions = UploadSessions::new()?;
+    let session = sessions.get(state.database(), &id, auth.id).await?;
+    sessions.remove(state.database(), &session.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
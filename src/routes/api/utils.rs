@@ -80,4 +80,5 @@ where
     ("x-ratelimit-reset" = f32, description = "The time, in UNIX timestamp seconds, when you can make requests again. Note this has a fractional component for milliseconds."),
     ("x-ratelimit-reset-after" = f32, description = "The number of seconds before you can try again. Note this has a fractional component for milliseconds."),
 ))]
+#[allow(dead_code)] // The error is only used for the schema
 pub struct RateLimitResponse(#[to_schema] ApiError);
@@ -1,21 +1,28 @@
 use crate::anilist::{self, MediaTitle};
+use crate::archive::{self, ArchiveKind};
+use crate::classify::{self, Language};
 use crate::database::{is_unique_constraint_violation, Table};
-use crate::download::{validate_path, DownloadResponse};
+use crate::download::{self, validate_path, DownloadResponse};
 use crate::error::{ApiError, ApiErrorCode, InternalError};
+use crate::files::{self, DuplicateFile, StoredFile};
 use crate::flash::{FlashMessage, Flasher, Flashes};
 use crate::headers::Referrer;
+use crate::lines::PendingLines;
 use crate::models::{Account, AccountCheck, DirectoryEntry, EntryFlags};
 use crate::ratelimit::RateLimit;
+use crate::relations::{ExternalIds, Range, Service};
+use crate::stats;
+use crate::storage::{self, Storage};
+use crate::subtitle::{self, Conversion, PreviewCue, Retime};
 use crate::utils::{is_over_length, FRAGMENT};
-use crate::{audit, filters};
+use crate::{aliases, audit, filters};
 use crate::{tmdb, AppState};
 use anyhow::{bail, Context};
 use askama::Template;
 use axum::body::{Body, Bytes};
-use axum::extract::multipart::Field;
 use axum::extract::{Json, Multipart, Query};
 use axum::http::header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE};
-use axum::http::{HeaderName, HeaderValue};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
 use axum::response::Redirect;
 use axum::routing::{delete, get, post};
 use axum::{
@@ -26,14 +33,14 @@ use axum::{
 use percent_encoding::percent_encode;
 use rusqlite::OptionalExtension;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, Write};
 use std::path::PathBuf;
 use time::OffsetDateTime;
 use tokio::task::JoinSet;
-use tower::ServiceExt;
 use tower_http::cors::CorsLayer;
-use tower_http::services::ServeFile;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 /// Represents a file entry, e.g. a subtitle or a ZIP file or whatever else.
 #[derive(Debug, Serialize, ToSchema)]
@@ -47,6 +54,72 @@ pub(crate) struct FileEntry {
     /// The date the file was last modified, in UTC, as an RFC3339 string.
     #[serde(with = "time::serde::rfc3339")]
     pub(crate) last_modified: OffsetDateTime,
+    /// The account ID that uploaded this file.
+    ///
+    /// This is not available for files that were scraped or uploaded before
+    /// uploads were tracked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) uploader_id: Option<i64>,
+    /// The SHA-256 hash of the file's contents, as a lowercase hexadecimal string.
+    pub(crate) sha256: String,
+    /// The episode range this file is for.
+    ///
+    /// This is a best-effort guess, based off of the filename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) episode: Option<Range>,
+    /// The language the file is written in.
+    ///
+    /// This is a best-effort guess, based off of the text of the file.
+    /// It's only available for SRT, ASS, and SSA files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) language: Option<Language>,
+    /// Whether the file is a closed caption (CC) track, e.g. it describes sounds
+    /// like `（拍手）` or labels who is speaking, rather than only having dialogue.
+    ///
+    /// This is a best-effort guess, based off of the text of the file.
+    /// It's only available for SRT, ASS, and SSA files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) closed_captions: Option<bool>,
+    /// The file's download URL converted to the requested format.
+    ///
+    /// This is only available if a format was requested and the file is a
+    /// subtitle file that can be converted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) converted_url: Option<String>,
+    /// The files within this file, if it's an archive.
+    ///
+    /// This is only available if archives were requested to be expanded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) members: Option<Vec<ArchiveMember>>,
+    /// The number of times this file was downloaded.
+    ///
+    /// This is only available to editors if download counts were requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) downloads: Option<u64>,
+}
+
+/// A file stored within an archive file entry.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ArchiveMember {
+    /// The member's download URL.
+    pub(crate) url: String,
+    /// The member's path within the archive, using `/` as the separator.
+    pub(crate) name: String,
+    /// The member's uncompressed size in bytes.
+    pub(crate) size: u64,
+    /// The episode range this member is for.
+    ///
+    /// This is a best-effort guess, based off of the filename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) episode: Option<Range>,
+}
+
+/// The first few cues of a file shown on the entry page.
+struct FilePreview {
+    name: String,
+    url: String,
+    cues: Vec<PreviewCue>,
+    error: Option<Cow<'static, str>>,
 }
 
 #[derive(Template)]
@@ -56,74 +129,339 @@ struct EntryTemplate {
     entry: DirectoryEntry,
     files: Vec<FileEntry>,
     flashes: Flashes,
+    preview: Option<FilePreview>,
+    /// The total number of downloads of every file, only shown to editors.
+    downloads: Option<u64>,
 }
 
-pub(crate) fn get_file_entries(entry_id: i64, path: &std::path::Path) -> std::io::Result<Vec<FileEntry>> {
-    let mut entries = Vec::new();
-    for file in path.read_dir()? {
-        let entry = file?;
-        let filename = entry.file_name();
-        let Some(filename) = filename.to_str() else {
-            continue;
-        };
+pub(crate) async fn get_file_entries(
+    state: &AppState,
+    entry_id: i64,
+    path: &std::path::Path,
+) -> anyhow::Result<Vec<FileEntry>> {
+    let files = files::sync_directory(state.database(), state.storage(), entry_id, path).await?;
+    let classifications = classify::for_entry(state.database(), entry_id).await?;
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let classification = classifications.get(&file.record.id);
+            FileEntry {
+                url: format!(
+                    "/entry/{entry_id}/download/{}",
+                    percent_encode(file.record.name.as_bytes(), FRAGMENT)
+                ),
+                name: file.record.name,
+                size: file.record.size,
+                last_modified: file.last_modified,
+                uploader_id: file.record.uploader_id,
+                sha256: file.record.sha256,
+                episode: file.record.episode,
+                language: classification.map(|c| c.language),
+                closed_captions: classification.map(|c| c.closed_captions),
+                converted_url: None,
+                members: None,
+                downloads: None,
+            }
+        })
+        .collect())
+}
 
-        let Ok(metadata) = entry.metadata() else { continue };
-        let last_modified = if let Ok(time) = metadata.modified() {
-            time.into()
-        } else {
-            OffsetDateTime::UNIX_EPOCH
-        };
+/// Fills in the `downloads` of every file entry.
+pub(crate) async fn fill_downloads(state: &AppState, entry_id: i64, files: &mut [FileEntry]) -> anyhow::Result<()> {
+    let counts = stats::for_entry(state.database(), entry_id).await?;
+    for file in files.iter_mut() {
+        file.downloads = Some(counts.get(&file.name).copied().unwrap_or_default());
+    }
+    Ok(())
+}
 
-        let url = format!(
-            "/entry/{entry_id}/download/{}",
-            percent_encode(filename.as_bytes(), FRAGMENT)
-        );
-        entries.push(FileEntry {
-            url,
-            name: filename.into(),
-            size: metadata.len(),
-            last_modified,
-        });
+/// Fills in the `members` of every archive file entry.
+///
+/// Archives that could not be read are left without members.
+pub(crate) async fn expand_archives(storage: &dyn Storage, path: &std::path::Path, files: &mut [FileEntry]) {
+    let archives = files
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| ArchiveKind::from_path(std::path::Path::new(&f.name)).is_some())
+        .map(|(index, f)| (index, path.join(&f.name)))
+        .collect::<Vec<_>>();
+    if archives.is_empty() {
+        return;
+    }
+
+    let mut readers = Vec::with_capacity(archives.len());
+    for (index, path) in archives {
+        match storage::open(storage, &path).await {
+            Ok(reader) => readers.push((index, path, reader)),
+            Err(e) => tracing::warn!(error=%e, path=%path.display(), "Could not open archive"),
+        }
+    }
+
+    let Ok(listings) = tokio::task::spawn_blocking(move || {
+        readers
+            .into_iter()
+            .filter_map(|(index, path, reader)| match archive::list(&path, reader) {
+                Ok(members) => Some((index, members)),
+                Err(e) => {
+                    tracing::warn!(error=%e, path=%path.display(), "Could not list archive members");
+                    None
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    else {
+        return;
+    };
+
+    for (index, members) in listings {
+        let file = &mut files[index];
+        let members = members
+            .into_iter()
+            .map(|member| {
+                let filename = member.name.rsplit('/').next().unwrap_or(&member.name);
+                ArchiveMember {
+                    url: format!("{}/{}", file.url, percent_encode(member.name.as_bytes(), FRAGMENT)),
+                    episode: files::episode_range(filename),
+                    name: member.name,
+                    size: member.size,
+                }
+            })
+            .collect();
+        file.members = Some(members);
     }
-    Ok(entries)
+}
+
+#[derive(Debug, Deserialize)]
+struct EntryQuery {
+    /// The name of a file to show a preview of.
+    #[serde(default)]
+    preview: Option<String>,
 }
 
 async fn get_entry(
     State(state): State<AppState>,
     Path(entry_id): Path<i64>,
+    Query(query): Query<EntryQuery>,
     account: Option<Account>,
     flashes: Flashes,
 ) -> Result<Response, InternalError> {
     let Some(entry) = state.get_directory_entry(entry_id).await else {
         return Ok(Redirect::to("/").into_response());
     };
-    let files = get_file_entries(entry_id, &entry.path)?;
+    let mut files = get_file_entries(&state, entry_id, &entry.path).await?;
+    let downloads = if account.as_ref().is_some_and(|acc| acc.flags.is_editor()) {
+        fill_downloads(&state, entry_id, &mut files).await?;
+        Some(files.iter().filter_map(|f| f.downloads).sum())
+    } else {
+        None
+    };
+    let preview = match query.preview {
+        Some(name) => {
+            let (cues, error) = match preview_file(&state, &entry.path, &name, DEFAULT_PREVIEW_CUES).await {
+                Ok(cues) => (cues, None),
+                Err(e) => (Vec::new(), Some(e.error)),
+            };
+            Some(FilePreview {
+                url: format!(
+                    "/entry/{entry_id}/download/{}",
+                    percent_encode(name.as_bytes(), FRAGMENT)
+                ),
+                name,
+                cues,
+                error,
+            })
+        }
+        None => None,
+    };
     Ok(EntryTemplate {
         account,
         entry,
         files,
         flashes,
+        preview,
+        downloads,
     }
     .into_response())
 }
 
+/// The number of cues previewed if no limit is given.
+pub(crate) const DEFAULT_PREVIEW_CUES: usize = 10;
+/// The maximum number of cues that can be previewed at once.
+pub(crate) const MAX_PREVIEW_CUES: usize = 100;
+
+/// Returns the first cues of an SRT, ASS, or SSA file in an entry's directory.
+pub(crate) async fn preview_file(
+    state: &AppState,
+    base: &std::path::Path,
+    name: &str,
+    limit: usize,
+) -> Result<Vec<PreviewCue>, ApiError> {
+    let Some(path) = validate_path(base, name).filter(|p| p.parent() == Some(base)) else {
+        return Err(ApiError::not_found("File not found."));
+    };
+    let Some(format) = subtitle::Format::from_path(&path) else {
+        return Err(ApiError::new("Only SRT, ASS, and SSA files can be previewed"));
+    };
+    let Ok(bytes) = state.storage().read(&path).await else {
+        return Err(ApiError::not_found("File not found."));
+    };
+    let limit = limit.clamp(1, MAX_PREVIEW_CUES);
+    tokio::task::spawn_blocking(move || subtitle::preview(format, &bytes, limit))
+        .await?
+        .map_err(|e| ApiError::new(format!("Could not read file: {e}")))
+}
+
+#[derive(Debug, Deserialize)]
+struct DownloadQuery {
+    #[serde(default)]
+    format: Option<Conversion>,
+    /// The number of milliseconds to shift the subtitle by.
+    #[serde(default)]
+    shift: Option<i64>,
+    /// The framerate the subtitle was timed for.
+    #[serde(default)]
+    from_fps: Option<f64>,
+    /// The framerate to convert the subtitle's timing to.
+    #[serde(default)]
+    to_fps: Option<f64>,
+}
+
 async fn download_entry(
     State(state): State<AppState>,
     Path((entry_id, filename)): Path<(i64, String)>,
+    Query(query): Query<DownloadQuery>,
     req: Request,
-) -> DownloadResponse {
+) -> Result<DownloadResponse, ApiError> {
     let Some(base) = state.get_directory_entry_path(entry_id).await else {
-        return DownloadResponse::NotFound;
+        return Ok(DownloadResponse::NotFound);
     };
 
     let Some(path) = validate_path(&base, filename.as_str()) else {
-        return DownloadResponse::NotFound;
+        return Ok(DownloadResponse::NotFound);
     };
 
-    match ServeFile::new(path).oneshot(req).await {
-        Ok(res) => DownloadResponse::File(res.map(axum::body::Body::new)),
-        Err(_) => DownloadResponse::NotFound,
+    // Paths that go through an archive, e.g. `archive.zip/file.srt`, refer to a member of that archive
+    let storage = state.storage().as_ref();
+    if !storage::exists(storage, &path).await {
+        if let Some((archive, member)) = filename.trim_start_matches('/').split_once('/') {
+            if let Some(archive) = validate_path(&base, archive) {
+                if ArchiveKind::from_path(&archive).is_some() && storage::is_file(storage, &archive).await {
+                    let response = download_archive_member(storage, archive, member.to_owned()).await;
+                    if matches!(response, DownloadResponse::File(_)) {
+                        record_download(&state, entry_id, &base, &path);
+                    }
+                    return Ok(response);
+                }
+            }
+        }
     }
+
+    let retime = Retime::new(query.shift, query.from_fps, query.to_fps).map_err(ApiError::new)?;
+    let needs_conversion = query.format.is_some() || !retime.is_identity();
+    if needs_conversion {
+        let Some(format) = subtitle::Format::from_path(&path) else {
+            return Err(ApiError::new("This file cannot be converted"));
+        };
+        if !retime.is_identity() || query.format.is_some_and(|to| to.is_needed_for(format)) {
+            let response = convert_file(storage, path.clone(), format, retime, query.format).await?;
+            if matches!(response, DownloadResponse::File(_)) {
+                record_download(&state, entry_id, &base, &path);
+            }
+            return Ok(response);
+        }
+    }
+
+    // Only complete downloads are counted, not HEAD requests or partial and cached responses
+    let is_head = req.method() == Method::HEAD;
+    let response = download::serve(storage, &path, req).await;
+    if let DownloadResponse::File(res) = &response {
+        if !is_head && res.status() == StatusCode::OK {
+            record_download(&state, entry_id, &base, &path);
+        }
+    }
+    Ok(response)
+}
+
+/// Records a download of a file that's directly within the entry's directory.
+///
+/// Members of an archive are recorded as a download of the archive itself.
+fn record_download(state: &AppState, entry_id: i64, base: &std::path::Path, path: &std::path::Path) {
+    let path = path.strip_prefix(base).ok().and_then(|p| p.components().next());
+    if let Some(name) = path.and_then(|c| c.as_os_str().to_str()) {
+        state.record_downloads(entry_id, vec![name.to_owned()]);
+    }
+}
+
+/// Responds with a single member of an archive, streamed out as it's decompressed.
+async fn download_archive_member(storage: &dyn Storage, archive: PathBuf, member: String) -> DownloadResponse {
+    let Ok(mut reader) = storage::open(storage, &archive).await else {
+        return DownloadResponse::NotFound;
+    };
+    let path = archive.clone();
+    let listing = tokio::task::spawn_blocking(move || archive::list(&path, &mut reader).map(|m| (m, reader))).await;
+    let Ok(Ok((members, reader))) = listing else {
+        return DownloadResponse::NotFound;
+    };
+    if !members.iter().any(|m| m.name == member) {
+        return DownloadResponse::NotFound;
+    }
+
+    let filename = member.rsplit('/').next().unwrap_or(&member).to_owned();
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let result = archive::read_member(&archive, reader, &member, |chunk| {
+            tx.blocking_send(Ok(Bytes::copy_from_slice(chunk))).is_ok()
+        });
+        if let Err(e) = result {
+            let _ = tx.blocking_send(Err(std::io::Error::other(e)));
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+    let disposition = format!(
+        "attachment; filename*=UTF-8''{}",
+        percent_encode(filename.as_bytes(), FRAGMENT)
+    );
+    let headers = [
+        (CONTENT_TYPE, String::from("application/octet-stream")),
+        (CONTENT_DISPOSITION, disposition),
+    ];
+    // The size in the archive's headers can't be trusted, so the body is sent chunked
+    DownloadResponse::File((headers, Body::from_stream(stream)).into_response())
+}
+
+/// Responds with the subtitle file at the given path converted to another format.
+async fn convert_file(
+    storage: &dyn Storage,
+    path: PathBuf,
+    format: subtitle::Format,
+    retime: Retime,
+    to: Option<Conversion>,
+) -> Result<DownloadResponse, ApiError> {
+    let Ok(bytes) = storage.read(&path).await else {
+        return Ok(DownloadResponse::NotFound);
+    };
+
+    let converted = tokio::task::spawn_blocking(move || subtitle::retime(format, &bytes, &retime, to))
+        .await?
+        .map_err(|e| ApiError::new(format!("Could not convert file: {e}")))?;
+
+    let (extension, content_type) = match to {
+        Some(to) if to.is_needed_for(format) => (to.extension(), to.content_type()),
+        _ => (format.extension(), format.content_type()),
+    };
+    let filename = path
+        .with_extension(extension)
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let disposition = format!(
+        "inline; filename*=UTF-8''{}",
+        percent_encode(filename.as_bytes(), FRAGMENT)
+    );
+    let headers = [(CONTENT_TYPE, content_type), (CONTENT_DISPOSITION, &disposition)];
+    Ok(DownloadResponse::File((headers, converted).into_response()))
 }
 
 #[derive(Debug, Deserialize)]
@@ -166,10 +504,29 @@ impl From<CreateDirectoryEntry> for PendingDirectoryEntry {
     }
 }
 
+/// The information to create an entry with.
+struct EntryInfo {
+    titles: MediaTitle,
+    flags: EntryFlags,
+    aliases: Vec<String>,
+    mal_id: Option<u32>,
+}
+
+impl EntryInfo {
+    fn new(titles: MediaTitle, flags: EntryFlags) -> Self {
+        Self {
+            titles,
+            flags,
+            aliases: Vec::new(),
+            mal_id: None,
+        }
+    }
+}
+
 impl PendingDirectoryEntry {
-    async fn get_info(&self, state: &AppState) -> anyhow::Result<Option<(MediaTitle, EntryFlags)>> {
+    async fn get_info(&self, state: &AppState) -> anyhow::Result<Option<EntryInfo>> {
         if let Some((title, flags)) = self.titles.as_ref().zip(self.flags) {
-            return Ok(Some((title.clone(), flags)));
+            return Ok(Some(EntryInfo::new(title.clone(), flags)));
         }
         match self.anilist_id {
             Some(id) => {
@@ -181,7 +538,12 @@ impl PendingDirectoryEntry {
                 flags.set_anime(self.anime);
                 flags.set_movie(media.is_movie());
                 flags.set_adult(media.adult);
-                Ok(Some((media.title, flags)))
+                Ok(Some(EntryInfo {
+                    aliases: crate::aliases::seed(&media.title, media.synonyms),
+                    titles: media.title,
+                    flags,
+                    mal_id: media.mal_id,
+                }))
             }
             None => {
                 if let Some(id) = self.tmdb_id {
@@ -194,7 +556,13 @@ impl PendingDirectoryEntry {
                     flags.set_anime(self.anime);
                     flags.set_movie(id.is_movie());
                     flags.set_adult(info.is_adult());
-                    Ok(Some((info.titles(), flags)))
+                    let titles = info.titles();
+                    Ok(Some(EntryInfo {
+                        aliases: crate::aliases::seed(&titles, info.alternative_titles()),
+                        titles,
+                        flags,
+                        mal_id: None,
+                    }))
                 } else {
                     Ok(None)
                 }
@@ -236,22 +604,32 @@ pub async fn raw_create_directory_entry(
 ) -> Result<(i64, PathBuf), ApiError> {
     let creator_id = account.id;
 
-    let (names, flags) = match pending.get_info(state).await? {
-        Some(title) => title,
+    let info = match pending.get_info(state).await? {
+        Some(info) => info,
         None if account.flags.is_editor() => {
             if let Some(name) = pending.name.clone() {
                 let mut flags = EntryFlags::new();
                 flags.set_anime(pending.anime);
-                (MediaTitle::new(name), flags)
+                EntryInfo::new(MediaTitle::new(name), flags)
             } else {
                 return Err(ApiError::new("Missing name, anilist_id, or tmdb_id for directory."));
             }
         }
         None => return Err(ApiError::new("Missing anilist_id or tmdb_id for directory.")),
     };
+    let EntryInfo {
+        titles: names,
+        flags,
+        aliases,
+        mal_id,
+    } = info;
+    let external_ids = match pending.anilist_id {
+        Some(id) => state.external_ids(id, mal_id).await,
+        None => ExternalIds::default(),
+    };
 
     let path = pending.path(&names.romaji, pending.anime, state);
-    if path.exists() {
+    if storage::exists(state.storage().as_ref(), &path).await {
         return Err(ApiError::new("Path already exists.").with_code(ApiErrorCode::EntryAlreadyExists));
     }
 
@@ -268,7 +646,7 @@ pub async fn raw_create_directory_entry(
     let romaji = names.romaji.clone();
     let response = state
         .database()
-        .call(move |con| -> Result<(i64, PathBuf), ApiError> {
+        .call(move |con| -> Result<i64, ApiError> {
             let tx = con.transaction()?;
             let result: rusqlite::Result<i64> = {
                 let mut stmt = tx.prepare_cached(query)?;
@@ -288,25 +666,36 @@ pub async fn raw_create_directory_entry(
                 )
             };
 
-            let url = match result {
-                Ok(entry_id) => {
-                    std::fs::create_dir(&path).map_err(|_| {
-                        ApiError::new(format!("Could not create directory {}", path.display()))
-                            .with_code(ApiErrorCode::ServerError)
-                    })?;
-                    (entry_id, path)
-                }
+            let entry_id = match result {
+                Ok(entry_id) => entry_id,
                 Err(e) if is_unique_constraint_violation(&e) => {
                     return Err(ApiError::new("Entry already exists.").with_code(ApiErrorCode::EntryAlreadyExists))
                 }
                 Err(e) => return Err(e.into()),
             };
 
+            crate::aliases::insert(&tx, entry_id, &aliases)?;
+            crate::relations::save_external_ids(&tx, entry_id, external_ids)?;
             tx.commit()?;
-            Ok(url)
+            Ok(entry_id)
         })
         .await;
 
+    let response = match response {
+        Ok(entry_id) => match state.storage().create_directory(&path).await {
+            Ok(()) => Ok((entry_id, path)),
+            Err(_) => {
+                let _ = state
+                    .database()
+                    .execute("DELETE FROM directory_entry WHERE id = ?", [entry_id])
+                    .await;
+                Err(ApiError::new(format!("Could not create directory {}", path.display()))
+                    .with_code(ApiErrorCode::ServerError))
+            }
+        },
+        Err(e) => Err(e),
+    };
+
     if let Ok((entry_id, _)) = &response {
         let audit_data = audit::CreateEntry {
             anime: pending.anime,
@@ -359,26 +748,48 @@ async fn create_directory_entry(
 }
 
 #[derive(Deserialize)]
-struct EditDirectoryEntry {
-    name: String,
+pub struct EditDirectoryEntry {
+    pub name: String,
     #[serde(deserialize_with = "crate::utils::empty_string_is_none")]
-    japanese_name: Option<String>,
+    pub japanese_name: Option<String>,
     #[serde(deserialize_with = "crate::utils::empty_string_is_none")]
-    english_name: Option<String>,
+    pub english_name: Option<String>,
     #[serde(deserialize_with = "anilist_id_or_url")]
-    anilist_id: Option<u32>,
+    pub anilist_id: Option<u32>,
     #[serde(deserialize_with = "crate::utils::empty_string_is_none")]
-    notes: Option<String>,
+    pub notes: Option<String>,
     #[serde(rename = "tmdb_url", deserialize_with = "tmdb_url")]
-    tmdb_id: Option<tmdb::Id>,
+    pub tmdb_id: Option<tmdb::Id>,
     #[serde(default)]
-    unverified: bool,
+    pub unverified: bool,
     #[serde(default)]
-    adult: bool,
+    pub adult: bool,
     #[serde(default)]
-    movie: bool,
+    pub movie: bool,
     #[serde(default)]
-    anime: bool,
+    pub anime: bool,
+    /// Left as-is when missing.
+    #[serde(default, deserialize_with = "aliases_list_or_lines")]
+    pub aliases: Option<Vec<String>>,
+}
+
+impl From<&DirectoryEntry> for EditDirectoryEntry {
+    /// Returns an edit that keeps the entry as-is.
+    fn from(entry: &DirectoryEntry) -> Self {
+        Self {
+            name: entry.name.clone(),
+            japanese_name: entry.japanese_name.clone(),
+            english_name: entry.english_name.clone(),
+            anilist_id: entry.anilist_id,
+            notes: entry.notes.clone(),
+            tmdb_id: entry.tmdb_id,
+            unverified: entry.flags.is_unverified(),
+            adult: entry.flags.is_adult(),
+            movie: entry.flags.is_movie(),
+            anime: entry.flags.is_anime(),
+            aliases: None,
+        }
+    }
 }
 
 impl EditDirectoryEntry {
@@ -398,21 +809,34 @@ impl EditDirectoryEntry {
         }
     }
 
-    fn validate(&self) -> Vec<&'static str> {
+    pub fn validate(&self) -> Vec<Cow<'static, str>> {
         let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push("Name cannot be empty.".into());
+        }
         if self.name.len() > 1024 {
-            errors.push("Name cannot be more than 1024 bytes.");
+            errors.push("Name cannot be more than 1024 bytes.".into());
         }
         if is_over_length(&self.english_name, 1024) {
-            errors.push("English name cannot be more than 1024 bytes.");
+            errors.push("English name cannot be more than 1024 bytes.".into());
         }
 
         if is_over_length(&self.japanese_name, 1024) {
-            errors.push("Japanese name cannot be more than 1024 bytes.");
+            errors.push("Japanese name cannot be more than 1024 bytes.".into());
         }
 
         if is_over_length(&self.notes, 1024) {
-            errors.push("Notes cannot be more than 1024 bytes.");
+            errors.push("Notes cannot be more than 1024 bytes.".into());
+        }
+
+        if let Some(list) = &self.aliases {
+            if list.len() > aliases::MAX_ALIASES {
+                errors.push(format!("Cannot have more than {} aliases.", aliases::MAX_ALIASES).into());
+            }
+
+            if list.iter().any(|a| a.len() > aliases::MAX_ALIAS_LENGTH) {
+                errors.push(format!("Aliases cannot be more than {} bytes.", aliases::MAX_ALIAS_LENGTH).into());
+            }
         }
         errors
     }
@@ -438,6 +862,24 @@ where
     }
 }
 
+/// Deserializes aliases from either a list or newline separated text, e.g. from a textarea.
+fn aliases_list_or_lines<'de, D>(de: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Aliases {
+        List(Vec<String>),
+        Lines(String),
+    }
+
+    Ok(Some(match Aliases::deserialize(de)? {
+        Aliases::List(list) => aliases::normalize(list),
+        Aliases::Lines(text) => aliases::normalize(text.lines()),
+    }))
+}
+
 fn tmdb_url<'de, D>(de: D) -> Result<Option<tmdb::Id>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -452,35 +894,21 @@ where
     }
 }
 
-async fn edit_directory_entry(
-    State(state): State<AppState>,
-    Path(entry_id): Path<i64>,
-    account: Account,
-    flasher: Flasher,
-    Referrer(url): Referrer,
-    Form(payload): Form<EditDirectoryEntry>,
-) -> Response {
-    if !account.flags.is_editor() {
-        return flasher.add("You do not have permissions to edit this.").bail(&url);
-    }
-
-    let Some(entry) = state.get_directory_entry(entry_id).await else {
-        return flasher.add("Directory entry not found.").bail(&url);
-    };
-
-    let errors = payload.validate();
-    if !errors.is_empty() {
-        for error in errors {
-            flasher.add(error);
-        }
-        return Redirect::to(&url).into_response();
-    }
-
-    // maybe refactor this?
+/// Applies the edit to the entry, recording what changed in the audit log.
+///
+/// Returns `false` if nothing was changed. The payload should be validated beforehand.
+pub async fn raw_edit_directory_entry(
+    state: &AppState,
+    account: &Account,
+    entry: DirectoryEntry,
+    payload: EditDirectoryEntry,
+) -> Result<bool, ApiError> {
+    let entry_id = entry.id;
     let mut columns = Vec::with_capacity(11);
     let mut params: Vec<Box<dyn rusqlite::ToSql + Send>> = Vec::with_capacity(11);
     let mut audit_data = audit::EditEntry::default();
     let flags = payload.apply_flags(entry.flags);
+    let mut external_ids = None;
 
     if entry.name != payload.name {
         columns.push("name");
@@ -505,6 +933,21 @@ async fn edit_directory_entry(
         audit_data.before.anilist_id = entry.anilist_id;
         audit_data.after.anilist_id = payload.anilist_id;
         params.push(Box::new(payload.anilist_id));
+        // The external IDs belong to the old AniList ID so they're replaced as well.
+        // If AniList can't be reached they're left unset for fill_external_ids to fill in later.
+        external_ids = Some(match payload.anilist_id {
+            Some(id) => match anilist::search_by_id(&state.client, id).await {
+                Ok(media) => {
+                    let media = media.ok_or_else(|| ApiError::new("AniList did not return results for this ID."))?;
+                    state.external_ids(id, media.mal_id).await
+                }
+                Err(e) => {
+                    tracing::warn!(error=%e, entry_id, anilist_id = id, "Could not get external IDs from AniList");
+                    ExternalIds::default()
+                }
+            },
+            None => ExternalIds::default(),
+        });
     }
     if entry.tmdb_id != payload.tmdb_id {
         columns.push("tmdb_id");
@@ -524,41 +967,93 @@ async fn edit_directory_entry(
         audit_data.after.flags = Some(flags);
         params.push(Box::new(flags));
     }
+    let new_aliases = payload.aliases.filter(|aliases| *aliases != entry.aliases);
+    let aliases_changed = new_aliases.is_some();
+    if let Some(aliases) = &new_aliases {
+        audit_data.before.aliases = Some(entry.aliases);
+        audit_data.after.aliases = Some(aliases.clone());
+    }
 
-    if !columns.is_empty() {
-        params.push(Box::new(entry_id));
-        let query = DirectoryEntry::update_query(&columns);
-        audit_data.changed = columns.into_iter().map(String::from).collect();
-        match state
-            .database()
-            .execute(query, rusqlite::params_from_iter(params))
-            .await
-        {
-            Ok(_) => {
-                state.cached_directories().invalidate().await;
-                state
-                    .audit(audit::AuditLogEntry::full(audit_data, entry_id, account.id))
-                    .await;
-                flasher.add(FlashMessage::success("Successfully edited entry."));
-                Redirect::to(&url).into_response()
+    if columns.is_empty() && !aliases_changed {
+        return Ok(false);
+    }
+
+    params.push(Box::new(entry_id));
+    let query = (!columns.is_empty()).then(|| DirectoryEntry::update_query(&columns));
+    audit_data.changed = columns.into_iter().map(String::from).collect();
+    if aliases_changed {
+        audit_data.changed.push(String::from("aliases"));
+    }
+    let result = state
+        .database()
+        .call(move |conn| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            if let Some(query) = query {
+                tx.execute(&query, rusqlite::params_from_iter(params))?;
             }
-            Err(rusqlite::Error::SqliteFailure(error, Some(s)))
-                if error.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
-            {
-                if let Some(suffix) = s.strip_prefix("UNIQUE constraint failed: directory_entry.") {
-                    flasher
-                        .add(format!("An entry already exists with this {suffix} field."))
-                        .bail(&url)
-                } else {
-                    flasher
-                        .add("An entry already exists with one of these fields.")
-                        .bail(&url)
-                }
+            if let Some(list) = new_aliases {
+                aliases::replace(&tx, entry_id, &list)?;
+            }
+            if let Some(ids) = external_ids {
+                tx.execute("DELETE FROM entry_external_id WHERE entry_id = ?", [entry_id])?;
+                crate::relations::save_external_ids(&tx, entry_id, ids)?;
             }
-            Err(e) => flasher.add(format!("SQL Error: {e}")).bail(&url),
+            tx.commit()
+        })
+        .await;
+    match result {
+        Ok(_) => {
+            state.cached_directories().invalidate().await;
+            state
+                .audit(audit::AuditLogEntry::full(audit_data, entry_id, account.id))
+                .await;
+            Ok(true)
         }
-    } else {
-        Redirect::to(&url).into_response()
+        Err(rusqlite::Error::SqliteFailure(error, Some(s)))
+            if error.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+        {
+            let error = if let Some(suffix) = s.strip_prefix("UNIQUE constraint failed: directory_entry.") {
+                ApiError::new(format!("An entry already exists with this {suffix} field."))
+            } else {
+                ApiError::new("An entry already exists with one of these fields.")
+            };
+            Err(error.with_code(ApiErrorCode::EntryAlreadyExists))
+        }
+        Err(e) => Err(ApiError::new(format!("SQL Error: {e}")).with_code(ApiErrorCode::ServerError)),
+    }
+}
+
+async fn edit_directory_entry(
+    State(state): State<AppState>,
+    Path(entry_id): Path<i64>,
+    account: Account,
+    flasher: Flasher,
+    Referrer(url): Referrer,
+    Form(payload): Form<EditDirectoryEntry>,
+) -> Response {
+    if !account.flags.is_editor() {
+        return flasher.add("You do not have permissions to edit this.").bail(&url);
+    }
+
+    let Some(entry) = state.get_directory_entry(entry_id).await else {
+        return flasher.add("Directory entry not found.").bail(&url);
+    };
+
+    let errors = payload.validate();
+    if !errors.is_empty() {
+        for error in errors {
+            flasher.add(error.into_owned());
+        }
+        return Redirect::to(&url).into_response();
+    }
+
+    match raw_edit_directory_entry(&state, &account, entry, payload).await {
+        Ok(true) => {
+            flasher.add(FlashMessage::success("Successfully edited entry."));
+            Redirect::to(&url).into_response()
+        }
+        Ok(false) => Redirect::to(&url).into_response(),
+        Err(e) => flasher.add(e.error.as_ref()).bail(&url),
     }
 }
 
@@ -688,7 +1183,10 @@ async fn move_directory_entries(
     for file in payload.files {
         let from = entry.join(&file);
         let to = path.join(&file);
-        let error = to.exists() || tokio::fs::rename(from, to).await.is_err();
+        let error = state.storage().rename(&from, &to).await.is_err();
+        if !error {
+            let _ = files::relocate(state.database(), from_entry_id, entry_id, file.clone()).await;
+        }
         audit_data.add_file(file, error);
         if error {
             failed += 1;
@@ -767,7 +1265,7 @@ async fn bulk_delete_files(
             )
             .await?;
         state.cached_directories().invalidate().await;
-        let result = tokio::fs::remove_dir_all(entry).await;
+        let result = state.storage().delete_directory(&entry).await;
         state
             .audit(
                 audit::AuditLogEntry::new(audit::DeleteEntry {
@@ -779,7 +1277,7 @@ async fn bulk_delete_files(
             .await;
         result?;
     } else {
-        let trash = crate::trash::Trash::new()?;
+        let trash = state.trash().await?;
         let mut audit_data = audit::DeleteFiles {
             permanent: account.flags.is_admin(),
             files: Vec::with_capacity(payload.files.len()),
@@ -790,10 +1288,23 @@ async fn bulk_delete_files(
         for file in payload.files {
             let path = entry.join(&file);
             let result = if account.flags.is_admin() {
-                tokio::fs::remove_file(path).await
+                state.storage().delete(&path).await
             } else {
-                trash.put(path, entry_id, payload.reason.clone()).await
+                let uploader_id = state
+                    .database()
+                    .get::<StoredFile, _, _>(
+                        "SELECT * FROM file WHERE entry_id = ? AND name = ?",
+                        (entry_id, file.clone()),
+                    )
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|f| f.uploader_id);
+                trash.put(path, entry_id, payload.reason.clone(), uploader_id).await
             };
+            if result.is_ok() {
+                let _ = files::remove(state.database(), entry_id, file.clone()).await;
+            }
             audit_data.add_file(file, result.is_err());
             match result {
                 Ok(_) => success += 1,
@@ -908,7 +1419,10 @@ async fn bulk_rename_files(
     for file in files {
         let from = entry.join(&file.from);
         let to = entry.join(&file.to);
-        let errored = to.exists() || tokio::fs::rename(from, to).await.is_err();
+        let errored = state.storage().rename(&from, &to).await.is_err();
+        if !errored {
+            let _ = files::rename(state.database(), entry_id, file.from.clone(), file.to.clone()).await;
+        }
         data.add_file(file.from, file.to, errored);
         if errored {
             failed += 1;
@@ -927,20 +1441,159 @@ async fn bulk_rename_files(
     }))
 }
 
+/// A request to save a retimed copy of a subtitle file.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RetimeRequest {
+    /// The name of the SRT, ASS, or SSA file to retime.
+    file: String,
+    /// The number of milliseconds to shift every timestamp by. This can be negative.
+    #[serde(default)]
+    shift: Option<i64>,
+    /// The framerate the file was timed for.
+    #[serde(default)]
+    from_fps: Option<f64>,
+    /// The framerate to convert the timing to. This must be given along with `from_fps`.
+    #[serde(default)]
+    to_fps: Option<f64>,
+    /// The name of the retimed copy.
+    ///
+    /// Defaults to the original name with `.retimed` before the extension.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// A retimed copy of a file that was saved to an entry.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RetimeResult {
+    /// The ID of the entry that the copy was saved to.
+    entry_id: i64,
+    /// The name of the copy.
+    name: String,
+    /// The URL to download the copy.
+    url: String,
+}
+
+/// Saves a retimed copy of a subtitle file into the same entry.
+pub async fn raw_retime_file(
+    state: &AppState,
+    entry_id: i64,
+    account: &Account,
+    request: RetimeRequest,
+    api: bool,
+) -> Result<RetimeResult, ApiError> {
+    if !account.flags.is_editor() {
+        return Err(ApiError::forbidden());
+    }
+
+    let Some(entry) = state.get_directory_entry_path(entry_id).await else {
+        return Err(ApiError::not_found("Directory entry not found."));
+    };
+
+    let Some(source) = validate_path(&entry, &request.file).filter(|p| p.parent() == Some(entry.as_path())) else {
+        return Err(ApiError::not_found("File not found."));
+    };
+
+    let Some(format) = subtitle::Format::from_path(&source) else {
+        return Err(ApiError::new("Only SRT, ASS, and SSA files can be retimed"));
+    };
+
+    let retime = Retime::new(request.shift, request.from_fps, request.to_fps).map_err(ApiError::new)?;
+    if retime.is_identity() {
+        return Err(ApiError::new("Retiming would not change the file"));
+    }
+
+    let name = match request.name.as_deref() {
+        Some(name) => sanitise_file_name::sanitise(name),
+        None => {
+            let stem = source.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+            format!("{stem}.retimed.{}", format.extension())
+        }
+    };
+    if subtitle::Format::from_path(std::path::Path::new(&name)) != Some(format) {
+        return Err(ApiError::new("The retimed copy must have the same extension"));
+    }
+
+    let path = entry.join(&name);
+    if storage::exists(state.storage().as_ref(), &path).await {
+        return Err(ApiError::new("filename already exists"));
+    }
+
+    let Ok(bytes) = state.storage().read(&source).await else {
+        return Err(ApiError::not_found("File not found."));
+    };
+    let retimed = tokio::task::spawn_blocking(move || subtitle::retime(format, &bytes, &retime, None))
+        .await?
+        .map_err(|e| ApiError::new(format!("Could not retime file: {e}")))?
+        .into_bytes();
+
+    state.storage().write(&path, &retimed, false).await?;
+
+    let lines = PendingLines::new(entry_id, name.clone(), &retimed);
+    let record = StoredFile::new(entry_id, name.clone(), &retimed, Some(account.id));
+    if let Err(e) = files::record(state.database(), vec![record]).await {
+        tracing::error!(error=%e, "Could not record retimed file");
+    }
+    if let Err(e) = crate::lines::index(state.database(), lines.into_iter().collect()).await {
+        tracing::error!(error=%e, "Could not index retimed file");
+    }
+
+    let _ = state
+        .database()
+        .execute(
+            "UPDATE directory_entry SET last_updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            [entry_id],
+        )
+        .await;
+    state.cached_directories().invalidate().await;
+
+    let data = audit::RetimeFile {
+        source: request.file,
+        name: name.clone(),
+        shift: retime.shift,
+        from_fps: retime.framerate.map(|(from, _)| from),
+        to_fps: retime.framerate.map(|(_, to)| to),
+        api,
+    };
+    state
+        .audit(audit::AuditLogEntry::full(data, entry_id, account.id))
+        .await;
+
+    Ok(RetimeResult {
+        entry_id,
+        url: format!(
+            "/entry/{entry_id}/download/{}",
+            percent_encode(name.as_bytes(), FRAGMENT)
+        ),
+        name,
+    })
+}
+
+async fn retime_file(
+    State(state): State<AppState>,
+    Path(entry_id): Path<i64>,
+    account: Account,
+    Json(request): Json<RetimeRequest>,
+) -> Result<Json<RetimeResult>, ApiError> {
+    raw_retime_file(&state, entry_id, &account, request, false)
+        .await
+        .map(Json)
+}
+
 #[derive(Debug)]
 struct ProcessedFile {
     path: PathBuf,
     bytes: Bytes,
+    /// The source encoding of the file if it was a text file that was not UTF-8.
+    encoding: Option<&'static encoding_rs::Encoding>,
+    /// The name of the uploaded archive this file was extracted from.
+    archive: Option<String>,
+    /// The SHA-256 hash of the file's contents.
+    sha256: String,
 }
 
 impl ProcessedFile {
-    fn write_to_disk(self) -> std::io::Result<()> {
-        let mut fp = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(self.path)?;
-        fp.write_all(&self.bytes)?;
-        Ok(())
+    fn name(&self) -> &str {
+        self.path.file_name().and_then(|x| x.to_str()).unwrap()
     }
 }
 
@@ -952,60 +1605,271 @@ pub struct PendingFileEntry {
 }
 
 impl PendingFileEntry {
-    pub fn write_to_disk(&self, base_path: PathBuf) -> std::io::Result<()> {
+    pub async fn store(&self, storage: &dyn Storage, base_path: &std::path::Path) -> std::io::Result<()> {
         let path = base_path.join(sanitise_file_name::sanitise(&self.name));
-        let mut fp = std::fs::OpenOptions::new().write(true).create_new(true).open(path)?;
-        fp.write_all(&self.data)?;
-        Ok(())
+        storage.write(&path, &self.data, false).await
     }
 }
 
+#[derive(Default)]
 struct ProcessedFiles {
     files: Vec<ProcessedFile>,
-    skipped: usize,
+    rejected: Vec<RejectedFile>,
+    /// Archives that were rejected for being potentially malicious.
+    unsafe_archives: Vec<RejectedFile>,
+}
+
+impl ProcessedFiles {
+    fn reject(&mut self, name: Option<String>, reason: impl Into<String>) {
+        self.rejected.push(RejectedFile {
+            name,
+            reason: reason.into(),
+            duplicate_of: None,
+        });
+    }
+}
+
+/// A file that was rejected during an upload.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RejectedFile {
+    /// The name of the file, if one was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// The reason the file was rejected.
+    reason: String,
+    /// The file already stored in the library that this file is an exact copy of.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<DuplicateFile>,
+}
+
+/// An uploaded file that is an exact copy of a file already stored in the library.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DuplicateUpload {
+    /// The name of the uploaded file.
+    name: String,
+    /// The file that was already stored.
+    duplicate_of: DuplicateFile,
+}
+
+impl std::fmt::Display for RejectedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}: {}", self.reason),
+            None => f.write_str(&self.reason),
+        }
+    }
+}
+
+pub(crate) fn verify_extension(file_name: &std::path::Path) -> anyhow::Result<()> {
+    match file_name.extension().and_then(|ext| ext.to_str()) {
+        Some("srt" | "ass" | "ssa" | "zip" | "sub" | "sup" | "idx" | "7z") => Ok(()),
+        _ => bail!("invalid file extension"),
+    }
 }
 
 async fn verify_file(
+    storage: &dyn Storage,
     entry_path: &std::path::Path,
     file_name: PathBuf,
-    field: Field<'_>,
+    mut bytes: Bytes,
+    options: &UploadQuery,
 ) -> anyhow::Result<ProcessedFile> {
-    match file_name.extension().and_then(|ext| ext.to_str()) {
-        Some("srt" | "ass" | "ssa" | "zip" | "sub" | "sup" | "idx" | "7z") => {
-            let path = entry_path.join(file_name);
-            if path.exists() {
-                bail!("filename already exists")
+    verify_extension(&file_name)?;
+    let path = entry_path.join(file_name);
+    if storage::exists(storage, &path).await {
+        bail!("filename already exists")
+    }
+    let mut encoding = None;
+    if let Some(format) = subtitle::Format::from_path(&path) {
+        subtitle::validate(format, &bytes)?;
+        if options.keep_encoding {
+            encoding = crate::encoding::detect(&bytes);
+        } else if let (detected, Cow::Owned(converted)) = crate::encoding::to_utf8(&bytes) {
+            encoding = detected;
+            bytes = Bytes::from(converted);
+        }
+        encoding = encoding.filter(|e| *e != encoding_rs::UTF_8);
+    }
+    Ok(ProcessedFile {
+        sha256: files::sha256_hex(&bytes),
+        path,
+        bytes,
+        encoding,
+        archive: None,
+    })
+}
+
+/// Inspects an uploaded archive for zip bombs and unsafe member paths.
+///
+/// Returns `false` if the archive was rejected.
+async fn inspect_archive(name: &str, bytes: Bytes, processed: &mut ProcessedFiles) -> bool {
+    let file_name = PathBuf::from(name);
+    let result =
+        tokio::task::spawn_blocking(move || archive::inspect(&file_name, &bytes, archive::Limits::default())).await;
+    let error = match result {
+        Ok(Ok(())) => return true,
+        Ok(Err(archive::InspectError::Invalid(e))) => {
+            processed.reject(Some(name.to_owned()), format!("could not read archive: {e}"));
+            return false;
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(e) => {
+            processed.reject(Some(name.to_owned()), format!("could not read archive: {e}"));
+            return false;
+        }
+    };
+
+    tracing::warn!(name, error, "Rejected unsafe archive upload");
+    processed.reject(Some(name.to_owned()), error.clone());
+    processed.unsafe_archives.push(RejectedFile {
+        name: Some(name.to_owned()),
+        reason: error,
+        duplicate_of: None,
+    });
+    false
+}
+
+/// Extracts the files within an uploaded archive and verifies each of them.
+async fn extract_archive(
+    storage: &dyn Storage,
+    entry_path: &std::path::Path,
+    name: &str,
+    bytes: Bytes,
+    options: &UploadQuery,
+    processed: &mut ProcessedFiles,
+) {
+    let file_name = PathBuf::from(name);
+    let members = match tokio::task::spawn_blocking(move || archive::extract(&file_name, &bytes)).await {
+        Ok(Ok(members)) => members,
+        Ok(Err(e)) => {
+            processed.reject(Some(name.to_owned()), format!("could not extract archive: {e}"));
+            return;
+        }
+        Err(e) => {
+            processed.reject(Some(name.to_owned()), format!("could not extract archive: {e}"));
+            return;
+        }
+    };
+
+    if members.is_empty() {
+        processed.reject(Some(name.to_owned()), "archive has no files");
+        return;
+    }
+
+    for (member, bytes) in members {
+        let filename = member.name.rsplit('/').next().unwrap_or(&member.name);
+        let filename = sanitise_file_name::sanitise(filename);
+        // Members are flattened into the entry, so e.g. `s1/01.srt` and `s2/01.srt` would clash
+        if processed.files.iter().any(|file| file.name() == filename) {
+            processed.reject(
+                Some(format!("{name}/{}", member.name)),
+                format!("another file in this upload is already named {filename}"),
+            );
+            continue;
+        }
+        let file_name = PathBuf::from(filename);
+        match verify_file(storage, entry_path, file_name, Bytes::from(bytes), options).await {
+            Ok(mut file) => {
+                file.archive = Some(name.to_owned());
+                processed.files.push(file);
+            }
+            Err(e) => {
+                tracing::debug!(error=%e, "Skipped archive member due to validation issue");
+                processed.reject(Some(format!("{name}/{}", member.name)), e.to_string());
             }
-            let bytes = field.bytes().await?;
-            Ok(ProcessedFile { path, bytes })
         }
-        _ => bail!("invalid file extension"),
     }
 }
 
-async fn process_files(entry_path: &std::path::Path, mut multipart: Multipart) -> anyhow::Result<ProcessedFiles> {
-    let mut files = Vec::new();
-    let mut skipped = 0;
+/// Verifies a single uploaded file, extracting it if it's an archive that should be extracted.
+async fn process_file(
+    storage: &dyn Storage,
+    entry_path: &std::path::Path,
+    name: String,
+    bytes: anyhow::Result<Bytes>,
+    options: &UploadQuery,
+    processed: &mut ProcessedFiles,
+) {
+    let file_name = PathBuf::from(&name);
+    let bytes = match verify_extension(&file_name) {
+        Ok(()) => bytes,
+        Err(e) => Err(e),
+    };
+
+    let is_archive = ArchiveKind::from_path(&file_name).is_some();
+    if let Ok(bytes) = &bytes {
+        if is_archive && !inspect_archive(&name, bytes.clone(), processed).await {
+            return;
+        }
+    }
+
+    let result = match bytes {
+        Ok(bytes) if options.extract && is_archive => {
+            extract_archive(storage, entry_path, &name, bytes, options, processed).await;
+            return;
+        }
+        Ok(bytes) => verify_file(storage, entry_path, file_name, bytes, options).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(file) => processed.files.push(file),
+        Err(e) => {
+            tracing::debug!(error=%e, "Skipped file due to validation issue");
+            processed.reject(Some(name), e.to_string());
+        }
+    }
+}
+
+async fn process_files(
+    storage: &dyn Storage,
+    entry_path: &std::path::Path,
+    mut multipart: Multipart,
+    options: &UploadQuery,
+) -> anyhow::Result<ProcessedFiles> {
+    let mut processed = ProcessedFiles::default();
     while let Some(field) = multipart.next_field().await? {
-        let Some(name) = field.file_name().map(sanitise_file_name::sanitise).map(PathBuf::from) else {
+        let Some(name) = field.file_name().map(sanitise_file_name::sanitise) else {
             tracing::debug!("Skipped file due to missing filename");
-            skipped += 1;
+            processed.reject(None, "missing filename");
             continue;
         };
 
-        match verify_file(entry_path, name, field).await {
-            Ok(file) => files.push(file),
-            Err(e) => {
-                tracing::debug!(error=%e, "Skipped file due to validation issue");
-                skipped += 1
-            }
-        }
+        // The extension is checked before reading the body so invalid files aren't read at all
+        let bytes = match verify_extension(std::path::Path::new(&name)) {
+            Ok(()) => field.bytes().await.map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        process_file(storage, entry_path, name, bytes, options, &mut processed).await;
     }
-    Ok(ProcessedFiles { files, skipped })
+    Ok(processed)
+}
+
+/// Options that control how uploaded files are processed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+pub struct UploadQuery {
+    /// Whether to store text subtitle files in their original encoding.
+    ///
+    /// By default, SRT, ASS, and SSA files that are encoded in e.g. Shift-JIS,
+    /// EUC-JP, or UTF-16 are converted to UTF-8 before being stored.
+    #[serde(default)]
+    pub keep_encoding: bool,
+    /// Whether to extract uploaded ZIP and 7z archives into the entry.
+    ///
+    /// The files within the archive go through the same checks as regular
+    /// uploads and the archive itself is not stored.
+    #[serde(default)]
+    pub extract: bool,
+    /// Whether to store files that are exact copies of a file already in the library.
+    ///
+    /// By default these files are rejected. If this is set then they're stored
+    /// anyway and reported in the `duplicates` field of the result.
+    #[serde(default)]
+    pub allow_duplicates: bool,
 }
 
 /// The result of an upload operation.
-#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct UploadResult {
     /// The number of files that did not succeed due to a filesystem error.
     errors: usize,
@@ -1013,6 +1877,14 @@ pub struct UploadResult {
     total: usize,
     /// The number of files that were skipped due to some reason
     skipped: usize,
+    /// The files that were skipped along with the reason why.
+    rejected: Vec<RejectedFile>,
+    /// The files that were stored despite already existing in the library.
+    duplicates: Vec<DuplicateUpload>,
+    /// The files that were held for review because they're mostly not in Japanese.
+    ///
+    /// These files are not visible until they're approved.
+    held: Vec<String>,
 }
 
 impl UploadResult {
@@ -1029,23 +1901,144 @@ impl UploadResult {
     }
 }
 
+/// Checks the processed files against the files already stored in the library.
+///
+/// Exact copies are rejected unless `allow` is set, in which case they're kept
+/// and returned instead.
+async fn check_duplicates(
+    state: &AppState,
+    entry: &DirectoryEntry,
+    processed: &mut ProcessedFiles,
+    allow: bool,
+) -> Vec<DuplicateUpload> {
+    let hashes = processed.files.iter().map(|f| f.sha256.clone()).collect();
+    let mut existing = match files::find_by_hashes(state.database(), hashes).await {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::error!(error=%e, "Could not check for duplicate files");
+            HashMap::new()
+        }
+    };
+
+    let mut duplicates = Vec::new();
+    let mut accepted = Vec::with_capacity(processed.files.len());
+    for file in std::mem::take(&mut processed.files) {
+        let name = file.name().to_owned();
+        match existing.get(&file.sha256) {
+            None => {
+                // Copies within the same upload are duplicates of the first one
+                existing.insert(
+                    file.sha256.clone(),
+                    DuplicateFile {
+                        entry_id: entry.id,
+                        entry_name: entry.name.clone(),
+                        name,
+                    },
+                );
+                accepted.push(file);
+            }
+            Some(duplicate) if allow => {
+                duplicates.push(DuplicateUpload {
+                    name,
+                    duplicate_of: duplicate.clone(),
+                });
+                accepted.push(file);
+            }
+            Some(duplicate) => {
+                tracing::debug!(name, "Skipped file due to being a duplicate");
+                processed.rejected.push(RejectedFile {
+                    name: Some(name),
+                    reason: format!("duplicate of {duplicate}"),
+                    duplicate_of: Some(duplicate.clone()),
+                });
+            }
+        }
+    }
+    processed.files = accepted;
+    duplicates
+}
+
 pub async fn raw_upload_file(
     state: AppState,
     entry_id: i64,
     account: Account,
     multipart: Multipart,
+    options: UploadQuery,
     api: bool,
 ) -> Result<UploadResult, ApiError> {
-    let Some(entry) = state.get_directory_entry_path(entry_id).await else {
+    let Some(entry) = state.get_directory_entry(entry_id).await else {
         return Err(ApiError::not_found("Entry not found"));
     };
 
-    let Ok(processed) = process_files(&entry, multipart).await else {
+    let Ok(processed) = process_files(state.storage().as_ref(), &entry.path, multipart, &options).await else {
         return Err(ApiError::new("Internal error when processing files").with_code(ApiErrorCode::ServerError));
     };
+    store_uploaded_files(state, entry, account, processed, options, api).await
+}
+
+/// Stores a file that was uploaded in chunks through an upload session.
+///
+/// The file goes through the same checks as a regular upload.
+pub async fn raw_upload_assembled_file(
+    state: AppState,
+    entry_id: i64,
+    account: Account,
+    name: String,
+    bytes: Vec<u8>,
+    options: UploadQuery,
+    api: bool,
+) -> Result<UploadResult, ApiError> {
+    let Some(entry) = state.get_directory_entry(entry_id).await else {
+        return Err(ApiError::not_found("Entry not found"));
+    };
+
+    let mut processed = ProcessedFiles::default();
+    let name = sanitise_file_name::sanitise(&name);
+    process_file(
+        state.storage().as_ref(),
+        &entry.path,
+        name,
+        Ok(Bytes::from(bytes)),
+        &options,
+        &mut processed,
+    )
+    .await;
+    store_uploaded_files(state, entry, account, processed, options, api).await
+}
+
+/// Stores the files that passed verification and records the upload in the audit log.
+async fn store_uploaded_files(
+    state: AppState,
+    entry: DirectoryEntry,
+    account: Account,
+    mut processed: ProcessedFiles,
+    options: UploadQuery,
+    api: bool,
+) -> Result<UploadResult, ApiError> {
+    let entry_id = entry.id;
+    if !processed.unsafe_archives.is_empty() {
+        let description = crate::utils::join_iter(
+            "\n",
+            processed.unsafe_archives.iter().map(|x| format!("- {x}")).take(25),
+        );
+        state.send_alert(
+            crate::discord::Alert::error("Rejected Archive Upload")
+                .url(format!("/entry/{entry_id}"))
+                .description(description)
+                .account(account.clone())
+                .field("Total", processed.unsafe_archives.len()),
+        );
+    }
+
+    let duplicates = check_duplicates(&state, &entry, &mut processed, options.allow_duplicates).await;
 
     if processed.files.is_empty() {
-        return Err(ApiError::new("Did not upload any files."));
+        let mut message = String::from("Did not upload any files.");
+        for file in processed.rejected.iter() {
+            message.push('\n');
+            message.push_str(&file.to_string());
+        }
+        return Err(ApiError::new(message));
     }
 
     let mut errored = 0usize;
@@ -1053,27 +2046,86 @@ pub async fn raw_upload_file(
     let mut data = audit::Upload {
         files: Vec::with_capacity(total),
         api,
+        encodings: BTreeMap::new(),
+        original_encoding: options.keep_encoding,
+        archives: BTreeMap::new(),
+        duplicates: duplicates
+            .iter()
+            .map(|d| (d.name.clone(), d.duplicate_of.entry_id))
+            .collect(),
+        held: Vec::new(),
     };
+    let mut records = Vec::with_capacity(total);
     let mut set = JoinSet::new();
+    let uploader_id = account.id;
+    let needs_review = !account.flags.is_editor();
     for file in processed.files.into_iter() {
+        let name = file.name();
+        if let Some(encoding) = file.encoding {
+            data.encodings.insert(name.to_owned(), encoding.name().to_owned());
+        }
+        if let Some(archive) = &file.archive {
+            data.archives.insert(name.to_owned(), archive.clone());
+        }
         set.spawn_blocking(move || {
-            let name = file.path.file_name().and_then(|x| x.to_str()).unwrap().to_owned();
-            let failed = file.write_to_disk().is_err();
-            audit::FileOperation { name, failed }
+            let name = file.name().to_owned();
+            let size = file.bytes.len() as u64;
+            let record = StoredFile::with_hash(entry_id, name.clone(), size, file.sha256.clone(), Some(uploader_id));
+            let lines = PendingLines::new(entry_id, name, &file.bytes);
+            let held = needs_review
+                && lines
+                    .as_ref()
+                    .is_some_and(|l| classify::is_mostly_non_japanese(&l.lines));
+            (file, record, lines, held)
         });
     }
 
+    let mut pending_lines = Vec::new();
+    let mut held = Vec::new();
     while let Some(task) = set.join_next().await {
         match task {
-            Ok(op) => {
+            Ok((file, record, lines, review)) => {
+                let failed = state.storage().write(&file.path, &file.bytes, false).await.is_err();
+                let op = audit::FileOperation {
+                    name: record.name.clone(),
+                    failed,
+                };
                 errored += op.failed as usize;
+                if !op.failed {
+                    let is_held = review && hold_for_review(&state, file.path, entry_id, uploader_id).await;
+                    if is_held {
+                        held.push(op.name.clone());
+                    } else {
+                        records.push(record);
+                        pending_lines.extend(lines);
+                    }
+                }
                 data.files.push(op);
             }
             _ => errored += 1,
         }
     }
 
-    let successful = total > 0 && errored == 0 && processed.skipped == 0;
+    if !held.is_empty() {
+        let description = crate::utils::join_iter("\n", held.iter().map(|x| format!("- {x}")).take(25));
+        state.send_alert(
+            crate::discord::Alert::info("Upload Held For Review")
+                .url(format!("/entry/{entry_id}"))
+                .description(description)
+                .account(account.clone())
+                .field("Total", held.len()),
+        );
+        data.held.clone_from(&held);
+    }
+
+    if let Err(e) = files::record(state.database(), records).await {
+        tracing::error!(error=%e, "Could not record uploaded files");
+    }
+    if let Err(e) = crate::lines::index(state.database(), pending_lines).await {
+        tracing::error!(error=%e, "Could not index uploaded files");
+    }
+
+    let successful = total > 0 && errored == 0 && processed.rejected.is_empty();
     if successful && errored != total {
         let _ = state
             .database()
@@ -1092,39 +2144,244 @@ pub async fn raw_upload_file(
     Ok(UploadResult {
         errors: errored,
         total,
-        skipped: processed.skipped,
+        skipped: processed.rejected.len(),
+        rejected: processed.rejected,
+        duplicates,
+        held,
     })
 }
 
+/// The reason given to files that are held for review.
+const HELD_FOR_REVIEW: &str = "Held for review: the file is mostly not in Japanese";
+
+/// Moves an uploaded file to the trash so it can be reviewed before it's restored.
+///
+/// Returns `false` if the file could not be moved, in which case it's kept.
+async fn hold_for_review(state: &AppState, path: PathBuf, entry_id: i64, uploader_id: i64) -> bool {
+    let result = match state.trash().await {
+        Ok(trash) => trash
+            .put(path, entry_id, Some(HELD_FOR_REVIEW.to_owned()), Some(uploader_id))
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!(error=%e, "Could not hold uploaded file for review");
+            false
+        }
+    }
+}
+
 async fn upload_file(
     State(state): State<AppState>,
     Path(entry_id): Path<i64>,
     Referrer(url): Referrer,
     account: Account,
     flasher: Flasher,
+    Query(options): Query<UploadQuery>,
     multipart: Multipart,
 ) -> Response {
-    let result = match raw_upload_file(state, entry_id, account, multipart, false).await {
+    let result = match raw_upload_file(state, entry_id, account, multipart, options, false).await {
         Ok(result) => result,
         Err(msg) => return flasher.add(msg.error.as_ref()).bail(&url),
     };
-    let message = if result.is_success() {
+    let message = if result.is_success() && !result.duplicates.is_empty() {
+        let mut message = String::from("Upload successful, but some files already exist elsewhere.");
+        for file in result.duplicates.iter() {
+            message.push('\n');
+            message.push_str(&format!("{}: duplicate of {}", file.name, file.duplicate_of));
+        }
+        FlashMessage::warning(message)
+    } else if result.is_success() && !result.held.is_empty() {
+        let mut message =
+            String::from("Upload successful, but some files are mostly not in Japanese and are held for review.");
+        for name in result.held.iter() {
+            message.push('\n');
+            message.push_str(name);
+        }
+        FlashMessage::warning(message)
+    } else if result.is_success() {
         FlashMessage::success("Upload successful.")
     } else if result.is_error() {
         FlashMessage::error("Upload failed.")
     } else {
         let successful = result.successful();
-        FlashMessage::warning(format!(
+        let mut message = format!(
             "Uploaded {successful} file{}, {} {} skipped and {} failed",
             if successful == 1 { "" } else { "s" },
             result.skipped,
             if result.skipped == 1 { "was" } else { "were" },
             result.errors,
-        ))
+        );
+        for file in result.rejected.iter() {
+            message.push('\n');
+            message.push_str(&file.to_string());
+        }
+        FlashMessage::warning(message)
     };
     flasher.add(message).bail(&url)
 }
 
+/// Filters for which files of an entry to download as a ZIP archive.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ArchiveQuery {
+    /// Only include files for episodes within this range, e.g. `13`, `13-24`, or `13-` for every
+    /// episode from 13 onwards.
+    ///
+    /// This is a best-effort guess, based off of the filename.
+    #[serde(default, deserialize_with = "crate::utils::generic_empty_string_is_none")]
+    episodes: Option<Range>,
+    /// Only include files whose name matches this pattern, ignoring case.
+    ///
+    /// `*` matches any run of characters and `?` matches a single character, e.g. `*.srt`.
+    #[serde(default, deserialize_with = "crate::utils::empty_string_is_none")]
+    glob: Option<String>,
+}
+
+impl ArchiveQuery {
+    pub(crate) fn matches(&self, file: &FileEntry) -> bool {
+        let episodes = match self.episodes {
+            Some(range) => file.episode.is_some_and(|e| e.overlaps(&range)),
+            None => true,
+        };
+        let glob = match self.glob.as_deref() {
+            Some(pattern) => crate::utils::glob_matches(pattern, &file.name),
+            None => true,
+        };
+        episodes && glob
+    }
+}
+
+/// A writer that sends what's written to it through a channel in chunks.
+struct ChannelWriter {
+    tx: tokio::sync::mpsc::Sender<std::io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    fn new(tx: tokio::sync::mpsc::Sender<std::io::Result<Bytes>>) -> Self {
+        Self {
+            tx,
+            buffer: Vec::with_capacity(Self::CHUNK_SIZE),
+        }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= Self::CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(Self::CHUNK_SIZE),
+        ));
+        // The receiver is only gone if the client disconnected, so stop writing
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Converts a subtitle file for a ZIP archive, returning its new name and contents.
+///
+/// Returns `None` if the file does not need to be or could not be converted.
+fn convert_for_zip(path: &std::path::Path, reader: &mut impl Read, to: Conversion) -> Option<(String, Vec<u8>)> {
+    let format = subtitle::Format::from_path(path)?;
+    if !to.is_needed_for(format) {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).ok()?;
+    let converted = subtitle::convert(format, &bytes, to).ok()?;
+    let name = path.with_extension(to.extension());
+    Some((name.file_name()?.to_str()?.to_owned(), converted.into_bytes()))
+}
+
+/// Responds with a ZIP archive of the given files.
+///
+/// The archive is sent while it's being written rather than being built in memory first.
+/// If a conversion is given then subtitle files are converted to that format when possible.
+/// Files that can't be opened are skipped. Once the archive is finished a download is
+/// recorded for every file that was written to it.
+pub(crate) fn stream_zip(
+    state: AppState,
+    entry_id: i64,
+    filename: String,
+    files: Vec<PathBuf>,
+    convert: Option<Conversion>,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(4);
+    let handle = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut zip = archive::StreamingZipWriter::new(ChannelWriter::new(tx.clone()));
+        let storage = state.storage();
+        let write = || -> std::io::Result<Vec<String>> {
+            let mut written = Vec::with_capacity(files.len());
+            let mut names = files
+                .iter()
+                .filter_map(|path| path.file_name().and_then(|x| x.to_str()).map(String::from))
+                .collect::<std::collections::HashSet<_>>();
+            for path in files {
+                let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+                    continue;
+                };
+                let Ok(Some(metadata)) = handle.block_on(storage.stat(&path)) else {
+                    continue;
+                };
+                if metadata.is_directory {
+                    continue;
+                }
+                let Ok(mut reader) = handle.block_on(storage::open(storage.as_ref(), &path)) else {
+                    continue;
+                };
+                let converted = convert.and_then(|to| convert_for_zip(&path, &mut reader, to));
+                match converted {
+                    // Keep the original if the converted name would clash with another file
+                    Some((converted_name, bytes)) if !names.contains(&converted_name) => {
+                        zip.add_file(&converted_name, metadata.modified, bytes.as_slice())?;
+                        names.insert(converted_name);
+                    }
+                    _ => {
+                        reader.rewind()?;
+                        zip.add_file(name, metadata.modified, reader)?
+                    }
+                }
+                written.push(name.to_owned());
+            }
+            Ok(written)
+        };
+        match write().and_then(|written| zip.finish().map(|_| written)) {
+            Ok(written) => state.record_downloads(entry_id, written),
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                tracing::error!(error=%e, "Could not write ZIP archive");
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+    let headers = [
+        (CONTENT_TYPE, String::from("application/zip")),
+        (CONTENT_DISPOSITION, format!("attachment; filename=\"{filename}\"")),
+        (HeaderName::from_static("x-jimaku-filename"), filename),
+    ];
+    (headers, Body::from_stream(stream)).into_response()
+}
+
 async fn bulk_download(
     State(state): State<AppState>,
     Path(entry_id): Path<i64>,
@@ -1134,59 +2391,67 @@ async fn bulk_download(
         return Err(ApiError::not_found("Directory entry not found."));
     };
 
+    // Only plain file names are allowed so the resulting archive can't
+    // be used for path traversal when it's extracted
+    let files = payload
+        .files
+        .iter()
+        .filter_map(|file| validate_path(&entry.path, file))
+        .filter(|path| path.parent() == Some(entry.path.as_path()))
+        .collect::<Vec<_>>();
     let filename = sanitise_file_name::sanitise(&format!("{}.zip", &entry.name));
-    let buffer = tokio::task::spawn_blocking(move || -> std::io::Result<_> {
-        let options = zip::write::FileOptions::default();
-        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    Ok(stream_zip(state, entry_id, filename, files, None))
+}
 
-        for file in payload.files {
-            let path = entry.path.join(&file);
-            let Ok(contents) = std::fs::read(&path) else {
-                continue;
-            };
-            zip.start_file(file, options)?;
-            zip.write_all(&contents)?;
-        }
-        let mut buffer = zip.finish()?.into_inner();
-        buffer.shrink_to_fit();
-        Ok(Bytes::from(buffer))
-    })
-    .await??;
+async fn filtered_bulk_download(
+    State(state): State<AppState>,
+    Path(entry_id): Path<i64>,
+    Query(query): Query<ArchiveQuery>,
+) -> Result<Response, ApiError> {
+    let Some(entry) = state.get_directory_entry(entry_id).await else {
+        return Err(ApiError::not_found("Directory entry not found."));
+    };
 
-    let body = Body::from(buffer);
-    let headers = [
-        (CONTENT_TYPE, "application/zip"),
-        (CONTENT_DISPOSITION, &format!("attachment; filename=\"{filename}\"")),
-        (HeaderName::from_static("x-jimaku-filename"), &filename),
-    ];
-    Ok((headers, body).into_response())
+    let files = get_file_entries(&state, entry_id, &entry.path)
+        .await?
+        .into_iter()
+        .filter(|file| query.matches(file))
+        .map(|file| entry.path.join(file.name))
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(ApiError::not_found("No files matched."));
+    }
+    let filename = sanitise_file_name::sanitise(&format!("{}.zip", &entry.name));
+    Ok(stream_zip(state, entry_id, filename, files, None))
 }
 
 #[derive(Deserialize)]
 struct RelationsRequest {
+    #[serde(default)]
     anilist_ids: Vec<u32>,
+    #[serde(default)]
+    mal_ids: Vec<u32>,
+    #[serde(default)]
+    kitsu_ids: Vec<u32>,
 }
 
 async fn relations(
     State(state): State<AppState>,
     Json(requested): Json<RelationsRequest>,
 ) -> Result<Json<Vec<DirectoryEntry>>, ApiError> {
-    if requested.anilist_ids.len() > 250 {
-        return Err(ApiError::new("Can only request up to 250 AniList IDs"));
-    }
-    let mut query = "SELECT * FROM directory_entry WHERE anilist_id IN (".to_string();
-    for _ in &requested.anilist_ids {
-        query.push('?');
-        query.push(',');
+    if requested.anilist_ids.len() + requested.mal_ids.len() + requested.kitsu_ids.len() > 250 {
+        return Err(ApiError::new("Can only request up to 250 IDs"));
     }
-    if query.ends_with(',') {
-        query.pop();
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    for (service, ids) in [
+        (Service::AniList, requested.anilist_ids),
+        (Service::MyAnimeList, requested.mal_ids),
+        (Service::Kitsu, requested.kitsu_ids),
+    ] {
+        let found = crate::relations::find_entries(state.database(), service, ids).await?;
+        entries.extend(found.into_iter().filter(|entry| seen.insert(entry.id)));
     }
-    query.push(')');
-    let entries = state
-        .database()
-        .all(query, rusqlite::params_from_iter(requested.anilist_ids))
-        .await?;
 
     Ok(Json(entries))
 }
@@ -1250,7 +2515,9 @@ async fn get_full_data_from_anilist(
     let mut result = Vec::with_capacity(entries.len());
     for entry in entries.into_iter() {
         result.push(EntryWithFiles {
-            files: get_file_entries(entry.id, &entry.path).unwrap_or_default(),
+            files: get_file_entries(&state, entry.id, &entry.path)
+                .await
+                .unwrap_or_default(),
             entry,
         });
     }
@@ -1267,6 +2534,7 @@ struct TmdbInfo {
     title: MediaTitle,
     adult: bool,
     movie: bool,
+    aliases: Vec<String>,
 }
 
 async fn tmdb_lookup(
@@ -1281,10 +2549,14 @@ async fn tmdb_lookup(
     Ok(Json(
         tmdb::get_media_info(&state.client, &state.config().tmdb_api_key, query.id)
             .await?
-            .map(|info| TmdbInfo {
-                title: info.titles(),
-                adult: info.is_adult(),
-                movie: query.id.is_movie(),
+            .map(|info| {
+                let title = info.titles();
+                TmdbInfo {
+                    aliases: aliases::seed(&title, info.alternative_titles()),
+                    title,
+                    adult: info.is_adult(),
+                    movie: query.id.is_movie(),
+                }
             }),
     ))
 }
@@ -1429,29 +2701,53 @@ async fn create_imported_entry(
     let mut data = audit::Upload {
         files: Vec::with_capacity(payload.files.len()),
         api: false,
+        encodings: BTreeMap::new(),
+        original_encoding: false,
+        archives: BTreeMap::new(),
+        duplicates: BTreeMap::new(),
+        held: Vec::new(),
     };
     for file in payload.files {
-        let p = path.clone();
+        let failed = file.store(state.storage().as_ref(), &path).await.is_err();
         set.spawn_blocking(move || {
-            let failed = file.write_to_disk(p).is_err();
-            audit::FileOperation {
-                name: file.name,
-                failed,
-            }
+            let name = sanitise_file_name::sanitise(&file.name);
+            let lines = PendingLines::new(id, name.clone(), &file.data);
+            let record = StoredFile::new(id, name, &file.data, Some(account_id));
+            (
+                audit::FileOperation {
+                    name: file.name,
+                    failed,
+                },
+                record,
+                lines,
+            )
         });
     }
 
     let mut errors = 0;
+    let mut records = Vec::with_capacity(data.files.capacity());
+    let mut pending_lines = Vec::new();
     while let Some(task) = set.join_next().await {
         match task {
-            Ok(op) => {
+            Ok((op, record, lines)) => {
                 errors += op.failed as usize;
+                if !op.failed {
+                    records.push(record);
+                    pending_lines.extend(lines);
+                }
                 data.files.push(op);
             }
             _ => errors += 1,
         }
     }
 
+    if let Err(e) = files::record(state.database(), records).await {
+        tracing::error!(error=%e, "Could not record imported files");
+    }
+    if let Err(e) = crate::lines::index(state.database(), pending_lines).await {
+        tracing::error!(error=%e, "Could not index imported files");
+    }
+
     state.audit(audit::AuditLogEntry::full(data, id, account_id)).await;
 
     Ok(Json(ImportResult { entry_id: id, errors }))
@@ -1471,6 +2767,7 @@ pub fn routes() -> Router<AppState> {
         .route("/entry/:id/edit", post(edit_directory_entry))
         .route("/entry/:id/move", post(move_directory_entries))
         .route("/entry/:id/rename", post(bulk_rename_files))
+        .route("/entry/:id/retime", post(retime_file))
         .route("/entry/:id", delete(bulk_delete_files))
         .route(
             "/entry/:id/report",
@@ -1483,7 +2780,9 @@ pub fn routes() -> Router<AppState> {
         )
         .route(
             "/entry/:id/bulk",
-            post(bulk_download).layer(RateLimit::default().build()),
+            get(filtered_bulk_download)
+                .post(bulk_download)
+                .layer(RateLimit::default().build()),
         )
         .route("/entry/relations", post(relations))
         .route("/entry/relations/tmdb", post(bulk_tmdb_lookup))
@@ -1495,3 +2794,44 @@ pub fn routes() -> Router<AppState> {
         .route("/entry/import", post(import_entry))
         .route("/entry/import/create", post(create_imported_entry))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Memory;
+
+    fn zip(members: &[(&str, &str)]) -> Bytes {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in members {
+            writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        Bytes::from(writer.finish().unwrap().into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_extract_archive_duplicate_names() {
+        let storage = Memory::default();
+        let entry_path = std::path::Path::new("entry");
+        storage.create_directory(entry_path).await.unwrap();
+
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\nこんにちは\n";
+        let bytes = zip(&[("s1/01.srt", srt), ("s2/01.srt", srt), ("s2/02.srt", srt)]);
+        let mut processed = ProcessedFiles::default();
+        extract_archive(
+            &storage,
+            entry_path,
+            "season.zip",
+            bytes,
+            &UploadQuery::default(),
+            &mut processed,
+        )
+        .await;
+
+        let names = processed.files.iter().map(|f| f.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["01.srt", "02.srt"]);
+        assert_eq!(processed.rejected.len(), 1);
+        assert_eq!(processed.rejected[0].name.as_deref(), Some("season.zip/s2/01.srt"));
+        assert!(processed.rejected[0].reason.contains("01.srt"));
+    }
+}
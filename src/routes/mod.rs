@@ -11,11 +11,12 @@ use axum::{
     extract::{Path, Query, State},
     response::IntoResponse,
     routing::get,
-    Extension, Router,
+    Extension, Json, Router,
 };
 use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use std::collections::HashMap;
 
-use crate::{models::DirectoryEntry, AppState};
+use crate::{models::DirectoryEntry, search::NormalizedTitle, AppState};
 
 mod admin;
 mod api;
@@ -77,6 +78,32 @@ async fn dramas(
     cacher.cache_template("dramas", template, encoding, bypass_cache).await
 }
 
+#[derive(serde::Deserialize)]
+struct ListingSearchQuery {
+    query: String,
+    #[serde(default = "crate::utils::default_true")]
+    anime: bool,
+}
+
+/// Scores the entries of a listing page against the query.
+///
+/// The listing pages rank their entries with this so that queries match across scripts
+/// the same way the API search does. Entries that don't match are omitted.
+async fn search_listing(
+    State(state): State<AppState>,
+    Query(query): Query<ListingSearchQuery>,
+) -> Json<HashMap<i64, isize>> {
+    let listing = state.directory_listing().await;
+    let normalized = NormalizedTitle::new(&query.query);
+    let scores = listing
+        .entries
+        .iter()
+        .filter(|e| e.flags.is_anime() == query.anime)
+        .filter_map(|e| Some((e.id, listing.index.score(e.id, &normalized)?)))
+        .collect();
+    Json(scores)
+}
+
 #[derive(Template)]
 #[template(path = "help.html")]
 struct HelpTemplate {
@@ -152,6 +179,7 @@ pub fn all() -> Router<AppState> {
     Router::new()
         .route("/", get(index))
         .route("/dramas", get(dramas))
+        .route("/search", get(search_listing))
         .route("/help", get(help_page))
         .route("/contact", get(contact_page))
         .route("/download-zip", get(bypass_download_zip_cors))
@@ -0,0 +1,221 @@
+//! Searching entries by title across romaji, hiragana and katakana.
+//!
+//! Titles are stored in whatever script the source used, e.g. `Shingeki no Kyojin`
+//! for the romaji name and `進撃の巨人` for the Japanese name. In order to match
+//! queries across scripts every title is normalized into a few forms ahead of time
+//! and queries are normalized the same way before being compared.
+
+use std::collections::HashMap;
+
+use crate::{
+    japanese::{normalize_diacritics, romaji_to_hiragana},
+    models::DirectoryEntry,
+};
+
+/// The minimum fuzzy score for a title to be considered a match.
+pub const MIN_SCORE: isize = 100;
+
+/// Returns `true` if the character is a katakana character with a hiragana counterpart.
+#[inline]
+const fn is_convertible_katakana(ch: char) -> bool {
+    matches!(ch, '\u{30a1}'..='\u{30f6}')
+}
+
+/// Returns `true` if the character is a hiragana character with a katakana counterpart.
+#[inline]
+const fn is_convertible_hiragana(ch: char) -> bool {
+    matches!(ch, '\u{3041}'..='\u{3096}')
+}
+
+/// Returns `true` if the character can be part of a romaji word.
+#[inline]
+fn is_romaji_char(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '\''
+}
+
+/// Folds full-width ASCII characters into their regular counterparts.
+fn fold_width(ch: char) -> char {
+    match ch {
+        '\u{ff01}'..='\u{ff5e}' => char::from_u32(ch as u32 - 0xfee0).unwrap_or(ch),
+        '\u{3000}' => ' ',
+        _ => ch,
+    }
+}
+
+/// Converts the katakana in the string to hiragana, keeping everything else as-is.
+pub fn katakana_to_hiragana(s: &str) -> String {
+    s.chars()
+        .map(|ch| {
+            if is_convertible_katakana(ch) {
+                char::from_u32(ch as u32 - 0x60).unwrap_or(ch)
+            } else {
+                ch
+            }
+        })
+        .collect()
+}
+
+/// Converts the hiragana in the string to katakana, keeping everything else as-is.
+pub fn hiragana_to_katakana(s: &str) -> String {
+    s.chars()
+        .map(|ch| {
+            if is_convertible_hiragana(ch) {
+                char::from_u32(ch as u32 + 0x60).unwrap_or(ch)
+            } else {
+                ch
+            }
+        })
+        .collect()
+}
+
+/// Converts every romaji word and katakana character in the string to hiragana.
+///
+/// Kanji, punctuation and whitespace are kept as-is.
+fn to_hiragana(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(is_romaji_char) {
+        output.push_str(&katakana_to_hiragana(&rest[..start]));
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_romaji_char(c)).unwrap_or(rest.len());
+        output.push_str(&romaji_to_hiragana(&rest[..end]));
+        rest = &rest[end..];
+    }
+    output.push_str(&katakana_to_hiragana(rest));
+    output
+}
+
+/// A title normalized into forms that can be compared across scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedTitle {
+    /// The title in lowercase with diacritics expanded, e.g. `Sōsō` becomes `sousou`.
+    pub romaji: String,
+    /// The title with romaji words and katakana converted to hiragana.
+    pub hiragana: String,
+    /// The title with romaji words and hiragana converted to katakana.
+    pub katakana: String,
+}
+
+impl NormalizedTitle {
+    pub fn new(title: &str) -> Self {
+        let folded = title.chars().map(fold_width).collect::<String>();
+        let romaji = normalize_diacritics(&folded).to_lowercase();
+        let hiragana = to_hiragana(&romaji);
+        let katakana = hiragana_to_katakana(&hiragana);
+        Self {
+            romaji,
+            hiragana,
+            katakana,
+        }
+    }
+
+    /// Returns the best fuzzy score of the query against this title, comparing like forms.
+    pub fn score(&self, query: &NormalizedTitle) -> Option<isize> {
+        [
+            (&query.romaji, &self.romaji),
+            (&query.hiragana, &self.hiragana),
+            (&query.katakana, &self.katakana),
+        ]
+        .into_iter()
+        .filter(|(query, _)| !query.is_empty())
+        .filter_map(|(query, target)| sublime_fuzzy::best_match(query, target))
+        .map(|m| m.score())
+        .max()
+    }
+
+    /// Returns a score relative to the score of this query matched against itself, from 0 to 1.
+    ///
+    /// Fuzzy matching does not penalize unmatched characters in the target, so a
+    /// value of 1 does not mean the titles are equal, e.g. a query that is a prefix
+    /// of the title also gets 1.
+    pub fn confidence(&self, score: isize) -> f32 {
+        match self.score(self) {
+            Some(perfect) if perfect > 0 => (score as f32 / perfect as f32).clamp(0.0, 1.0),
+            _ => 0.0,
+        }
+    }
+}
+
+/// An index of the normalized titles and aliases of every entry.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    titles: HashMap<i64, Vec<NormalizedTitle>>,
+}
+
+impl SearchIndex {
+    pub fn new(entries: &[DirectoryEntry]) -> Self {
+        let titles = entries
+            .iter()
+            .map(|entry| (entry.id, entry.titles().map(NormalizedTitle::new).collect()))
+            .collect();
+        Self { titles }
+    }
+
+    /// Returns the best score of the query against any title of the entry.
+    ///
+    /// Returns `None` if the entry is not indexed or none of its titles meet [`MIN_SCORE`].
+    pub fn score(&self, entry_id: i64, query: &NormalizedTitle) -> Option<isize> {
+        self.titles
+            .get(&entry_id)?
+            .iter()
+            .filter_map(|title| title.score(query))
+            .max()
+            .filter(|score| *score >= MIN_SCORE)
+    }
+
+    /// Returns the ID and score of the entry that best matches the query.
+    pub fn best_match(&self, query: &NormalizedTitle) -> Option<(i64, isize)> {
+        self.titles
+            .keys()
+            .filter_map(|id| Some((*id, self.score(*id, query)?)))
+            .max_by_key(|(id, score)| (*score, std::cmp::Reverse(*id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalized_title() {
+        let title = NormalizedTitle::new("Sōsō no Frieren");
+        assert_eq!(title.romaji, "sousou no frieren");
+        assert_eq!(NormalizedTitle::new("Sousou no Frieren").romaji, title.romaji);
+
+        let title = NormalizedTitle::new("Shingeki no Kyojin");
+        assert_eq!(title.hiragana, "しんげき の きょじん");
+        assert_eq!(title.katakana, "シンゲキ ノ キョジン");
+
+        let title = NormalizedTitle::new("進撃の巨人 ファイナルシーズン");
+        assert_eq!(title.hiragana, "進撃の巨人 ふぁいなるしーずん");
+        assert_eq!(title.katakana, "進撃ノ巨人 ファイナルシーズン");
+
+        assert_eq!(NormalizedTitle::new("ＳＰＹ×ＦＡＭＩＬＹ").romaji, "spy×family");
+    }
+
+    fn matches(title: &NormalizedTitle, query: &str) -> bool {
+        title
+            .score(&NormalizedTitle::new(query))
+            .is_some_and(|score| score >= MIN_SCORE)
+    }
+
+    #[test]
+    fn test_cross_script_score() {
+        let entry = NormalizedTitle::new("Shingeki no Kyojin");
+        assert!(matches(&entry, "しんげき"));
+        assert!(matches(&entry, "シンゲキ"));
+        assert!(matches(&entry, "shingeki"));
+
+        let query = NormalizedTitle::new("Shingeki no Kyojin");
+        assert_eq!(query.confidence(entry.score(&query).unwrap()), 1.0);
+        let query = NormalizedTitle::new("shingeki");
+        assert_eq!(query.confidence(entry.score(&query).unwrap()), 1.0);
+        let query = NormalizedTitle::new("shingki");
+        assert!(query.confidence(entry.score(&query).unwrap()) < 1.0);
+
+        let entry = NormalizedTitle::new("かぐや様は告らせたい");
+        assert!(matches(&entry, "kaguya"));
+        assert!(matches(&entry, "カグヤ"));
+        assert!(!matches(&entry, "frieren"));
+    }
+}
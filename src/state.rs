@@ -1,18 +1,41 @@
 use quick_cache::sync::Cache;
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::sync::{RwLock, RwLockReadGuard};
 
 use crate::{
+    anilist,
     audit::AuditLogEntry,
     auth::hash_password,
     cached::TimedCachedValue,
     database::Table,
     models::{Account, DirectoryEntry, Session},
-    relations::Relations,
+    relations::{self, ExternalIds, Relations},
+    search::SearchIndex,
+    storage::{FileSystem, Storage},
     token::MAX_TOKEN_AGE,
+    trash::Trash,
     Config, Database,
 };
 
+/// The cached directory entries along with their search index.
+#[derive(Debug, Default)]
+pub struct DirectoryListing {
+    pub entries: Vec<DirectoryEntry>,
+    pub index: SearchIndex,
+}
+
+impl DirectoryListing {
+    pub fn new(entries: Vec<DirectoryEntry>) -> Self {
+        let index = SearchIndex::new(&entries);
+        Self { entries, index }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct SessionInfo {
     pub id: i64,
@@ -40,7 +63,8 @@ impl SessionInfo {
 struct InnerState {
     config: Config,
     database: Database,
-    cached_directories: TimedCachedValue<Vec<DirectoryEntry>>,
+    storage: Arc<dyn Storage>,
+    cached_directories: TimedCachedValue<DirectoryListing>,
     relations: RwLock<Relations>,
     cached_users: Cache<i64, Account>,
     valid_sessions: Cache<String, SessionInfo>,
@@ -56,6 +80,11 @@ pub struct AppState {
 
 impl AppState {
     pub async fn new(config: Config, database: Database) -> Self {
+        Self::with_storage(config, database, Arc::new(FileSystem)).await
+    }
+
+    /// Creates the state with a different storage backend for entry files.
+    pub async fn with_storage(config: Config, database: Database, storage: Arc<dyn Storage>) -> Self {
         let incorrect_default_password_hash =
             hash_password("incorrect-default-password").expect("could not hash default password");
         let client = reqwest::Client::builder()
@@ -67,6 +96,7 @@ impl AppState {
             inner: Arc::new(InnerState {
                 config,
                 database,
+                storage,
                 relations: RwLock::new(Relations::load(&client).await.unwrap_or_default()),
                 cached_directories: TimedCachedValue::new(Duration::from_secs(60 * 30)),
                 cached_users: Cache::new(1000),
@@ -85,19 +115,21 @@ impl AppState {
         &self.inner.database
     }
 
+    /// Returns the storage backend that entry files are kept in.
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.inner.storage
+    }
+
+    /// Opens the trash within the storage backend.
+    pub async fn trash(&self) -> anyhow::Result<Trash> {
+        Trash::new(self.storage().clone()).await
+    }
+
     /// Sends an audit log entry.
     ///
     /// Errors are silently dropped, since they can't be handled anyway.
     pub async fn audit(&self, entry: AuditLogEntry) {
-        let err = self
-            .database()
-            .execute(
-                "INSERT INTO audit_log(id, entry_id, account_id, data) VALUES (?, ?, ?, ?)",
-                (entry.id, entry.entry_id, entry.account_id, entry.data),
-            )
-            .await;
-
-        if let Err(e) = err {
+        if let Err(e) = entry.insert(self.database()).await {
             tracing::error!(error=%e, "Could not insert audit log entry");
         }
     }
@@ -113,7 +145,19 @@ impl AppState {
         }
     }
 
-    pub fn cached_directories(&self) -> &TimedCachedValue<Vec<DirectoryEntry>> {
+    /// Records a download of each of the given files in the entry.
+    ///
+    /// This is done in the background so the download isn't held up by it.
+    pub fn record_downloads(&self, entry_id: i64, names: Vec<String>) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::stats::record(state.database(), entry_id, names).await {
+                tracing::error!(error=%e, entry_id, "Could not record downloads");
+            }
+        });
+    }
+
+    pub fn cached_directories(&self) -> &TimedCachedValue<DirectoryListing> {
         &self.inner.cached_directories
     }
 
@@ -311,6 +355,11 @@ impl AppState {
     }
 
     pub async fn directory_entries(&self) -> RwLockReadGuard<'_, Vec<DirectoryEntry>> {
+        RwLockReadGuard::map(self.directory_listing().await, |listing| &listing.entries)
+    }
+
+    /// Returns the cached directory entries along with their search index.
+    pub async fn directory_listing(&self) -> RwLockReadGuard<'_, DirectoryListing> {
         {
             let reader = self.inner.cached_directories.get().await;
             if let Some(lock) = reader {
@@ -319,12 +368,9 @@ impl AppState {
         }
 
         // Cache miss
-        let entries = self
-            .database()
-            .all("SELECT * FROM directory_entry ORDER BY name ASC", [])
-            .await
-            .unwrap_or_default();
-        self.inner.cached_directories.set(entries).await
+        let query = format!("{} ORDER BY name ASC", DirectoryEntry::SELECT_FULL);
+        let entries = self.database().all(query, []).await.unwrap_or_default();
+        self.inner.cached_directories.set(DirectoryListing::new(entries)).await
     }
 
     /// Gets the directory by ID via cache, if available.
@@ -335,14 +381,15 @@ impl AppState {
     /// All errors are coerced into None.
     pub async fn get_directory_entry(&self, id: i64) -> Option<DirectoryEntry> {
         if let Some(guard) = self.cached_directories().get().await {
-            let found = guard.iter().find(|x| x.id == id);
+            let found = guard.entries.iter().find(|x| x.id == id);
             // Cache hit, return a copy
             if found.is_some() {
                 return found.cloned();
             }
         }
 
-        self.database().get_by_id(id).await.ok().flatten()
+        let query = format!("{} WHERE id = ?", DirectoryEntry::SELECT_FULL);
+        self.database().get(query, [id]).await.ok().flatten()
     }
 
     /// Gets the directory entry's path.
@@ -351,7 +398,7 @@ impl AppState {
     /// when the only thing needed is the path.
     pub async fn get_directory_entry_path(&self, id: i64) -> Option<PathBuf> {
         if let Some(guard) = self.cached_directories().get().await {
-            let found = guard.iter().find(|x| x.id == id);
+            let found = guard.entries.iter().find(|x| x.id == id);
             // Cache hit, return a copy
             if let Some(hit) = found {
                 return Some(hit.path.clone());
@@ -370,7 +417,7 @@ impl AppState {
     /// Gets the directory entry's path by its AniList ID.
     pub async fn get_anilist_directory_entry_path(&self, id: u32) -> Option<PathBuf> {
         if let Some(guard) = self.cached_directories().get().await {
-            let found = guard.iter().find(|x| x.anilist_id == Some(id));
+            let found = guard.entries.iter().find(|x| x.anilist_id == Some(id));
             // Cache hit, return a copy
             if let Some(hit) = found {
                 return Some(hit.path.clone());
@@ -389,7 +436,7 @@ impl AppState {
     /// Gets the directory entry's path by its TMDB ID.
     pub async fn get_tmdb_directory_entry_path(&self, id: crate::tmdb::Id) -> Option<PathBuf> {
         if let Some(guard) = self.cached_directories().get().await {
-            let found = guard.iter().find(|x| x.tmdb_id == Some(id));
+            let found = guard.entries.iter().find(|x| x.tmdb_id == Some(id));
             // Cache hit, return a copy
             if let Some(hit) = found {
                 return Some(hit.path.clone());
@@ -412,7 +459,111 @@ impl AppState {
 
     /// Updates the anime relations to the one given
     pub async fn set_anime_relations(&self, relations: Relations) {
-        let mut guard = self.inner.relations.write().await;
-        *guard = relations;
+        {
+            let mut guard = self.inner.relations.write().await;
+            *guard = relations;
+        }
+        // This might have to ask AniList about a lot of entries
+        let state = self.clone();
+        tokio::spawn(async move { state.fill_external_ids().await });
+    }
+
+    /// Returns the MyAnimeList and Kitsu IDs of the AniList ID.
+    ///
+    /// The given MyAnimeList ID, which should come from AniList's `idMal`, takes
+    /// precedence over the anime relations since they only cover some media.
+    pub async fn external_ids(&self, anilist_id: u32, mal_id: Option<u32>) -> ExternalIds {
+        ExternalIds { mal_id, kitsu_id: None }.or(self.anime_relations().await.external_ids(anilist_id))
+    }
+
+    /// Fills in the MyAnimeList and Kitsu IDs that entries are missing.
+    ///
+    /// Like when creating an entry, the MyAnimeList ID from AniList is preferred over the
+    /// anime relations. IDs that entries already have are kept as-is. Errors are logged
+    /// rather than returned.
+    ///
+    /// AniList is only asked about an entry once, since a lot of media don't have a
+    /// MyAnimeList ID at all. Entries whose request failed are asked about next time.
+    pub async fn fill_external_ids(&self) {
+        let query = r#"
+            SELECT directory_entry.id, directory_entry.anilist_id
+            FROM directory_entry
+            LEFT JOIN entry_external_id ON entry_external_id.entry_id = directory_entry.id
+            WHERE directory_entry.anilist_id IS NOT NULL
+              AND entry_external_id.mal_id IS NULL
+              AND entry_external_id.anilist_checked_at IS NULL
+        "#;
+        let unchecked = self
+            .database()
+            .call(move |conn| -> rusqlite::Result<Vec<(i64, u32)>> {
+                let mut stmt = conn.prepare(query)?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!(error=%e, "Could not get entries missing a MyAnimeList ID");
+                Vec::new()
+            });
+
+        let mut mal_ids = HashMap::new();
+        let mut checked = Vec::new();
+        if !unchecked.is_empty() {
+            let anilist_ids = unchecked.iter().map(|(_, anilist_id)| *anilist_id).collect::<Vec<_>>();
+            let (media, failed) = anilist::search_by_ids(&self.client, &anilist_ids).await;
+            mal_ids.extend(media.into_iter().filter_map(|m| Some((m.id, m.mal_id?))));
+            let failed = failed.into_iter().collect::<HashSet<_>>();
+            checked.extend(
+                unchecked
+                    .into_iter()
+                    .filter(|(_, anilist_id)| !failed.contains(anilist_id))
+                    .map(|(entry_id, _)| entry_id),
+            );
+        }
+
+        let updates = {
+            let entries = self.directory_entries().await;
+            let relations = self.anime_relations().await;
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    let anilist_id = entry.anilist_id?;
+                    let ids = ExternalIds {
+                        mal_id: mal_ids.get(&anilist_id).copied(),
+                        kitsu_id: None,
+                    }
+                    .or(relations.external_ids(anilist_id));
+                    let missing = (ids.mal_id.is_some() && entry.mal_id.is_none())
+                        || (ids.kitsu_id.is_some() && entry.kitsu_id.is_none());
+                    missing.then_some((entry.id, ids))
+                })
+                .collect::<Vec<_>>()
+        };
+        if updates.is_empty() && checked.is_empty() {
+            return;
+        }
+
+        let count = updates.len();
+        let result = self
+            .database()
+            .call(move |conn| -> rusqlite::Result<()> {
+                let tx = conn.transaction()?;
+                for (entry_id, ids) in updates {
+                    relations::save_external_ids(&tx, entry_id, ids)?;
+                }
+                for entry_id in checked {
+                    relations::mark_anilist_checked(&tx, entry_id)?;
+                }
+                tx.commit()
+            })
+            .await;
+        match result {
+            Ok(()) if count == 0 => {}
+            Ok(()) => {
+                tracing::info!(count, "Filled in missing external IDs");
+                self.cached_directories().invalidate().await;
+            }
+            Err(e) => tracing::error!(error=%e, "Could not fill in external IDs"),
+        }
     }
 }
@@ -0,0 +1,218 @@
+//! Download statistics of files.
+//!
+//! Downloads are aggregated per file per day, in UTC. Nothing about who did
+//! the downloading is stored, only how many times it happened.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::Database;
+
+/// A file along with the number of times it was downloaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadedFile {
+    /// The ID of the entry that holds the file.
+    pub entry_id: i64,
+    /// The name of the entry that holds the file.
+    pub entry_name: String,
+    /// The file's name within the entry.
+    pub name: String,
+    /// The number of times the file was downloaded.
+    pub downloads: u64,
+}
+
+impl DownloadedFile {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            entry_id: row.get("entry_id")?,
+            entry_name: row.get("entry_name")?,
+            name: row.get("name")?,
+            downloads: row.get("downloads")?,
+        })
+    }
+}
+
+/// An entry along with the number of times its files were downloaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadedEntry {
+    pub entry_id: i64,
+    pub entry_name: String,
+    /// The total number of downloads of every file in the entry.
+    pub downloads: u64,
+}
+
+/// Records a download of each of the given files in the entry.
+///
+/// Names that do not refer to a recorded file are ignored.
+pub async fn record(database: &Database, entry_id: i64, names: Vec<String>) -> rusqlite::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    database
+        .call(move |conn| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    r#"
+                    INSERT INTO file_download(file_id, day, count)
+                    SELECT id, date('now'), 1 FROM file WHERE entry_id = ? AND name = ?
+                    ON CONFLICT(file_id, day) DO UPDATE SET count = count + 1
+                    "#,
+                )?;
+                for name in names {
+                    stmt.execute((entry_id, name))?;
+                }
+            }
+            tx.commit()
+        })
+        .await
+}
+
+/// Returns the total number of downloads of every downloaded file in the entry, keyed by file name.
+pub async fn for_entry(database: &Database, entry_id: i64) -> rusqlite::Result<HashMap<String, u64>> {
+    database
+        .call(move |conn| -> rusqlite::Result<HashMap<String, u64>> {
+            let query = r#"
+                SELECT file.name, SUM(file_download.count)
+                FROM file_download INNER JOIN file ON file.id = file_download.file_id
+                WHERE file.entry_id = ?
+                GROUP BY file.id
+            "#;
+            let mut stmt = conn.prepare_cached(query)?;
+            let rows = stmt.query_map([entry_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        })
+        .await
+}
+
+/// Returns the first day, as a `YYYY-MM-DD` string, that's within the last `days` days.
+fn since(days: Option<u16>) -> Option<String> {
+    let date = time::OffsetDateTime::now_utc().date() - time::Duration::days(days?.saturating_sub(1).into());
+    Some(date.to_string())
+}
+
+/// Returns the most downloaded files, optionally only counting the downloads of the last `days` days.
+pub async fn most_downloaded_files(
+    database: &Database,
+    days: Option<u16>,
+    limit: usize,
+) -> rusqlite::Result<Vec<DownloadedFile>> {
+    let since = since(days);
+    database
+        .call(move |conn| -> rusqlite::Result<Vec<DownloadedFile>> {
+            let query = r#"
+                SELECT file.entry_id, directory_entry.name AS entry_name, file.name, SUM(file_download.count) AS downloads
+                FROM file_download
+                INNER JOIN file ON file.id = file_download.file_id
+                INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+                WHERE ?1 IS NULL OR file_download.day >= ?1
+                GROUP BY file.id
+                ORDER BY downloads DESC, file.id
+                LIMIT ?2
+            "#;
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map((since, limit), DownloadedFile::from_row)?;
+            rows.collect()
+        })
+        .await
+}
+
+/// Returns the most downloaded entries, optionally only counting the downloads of the last `days` days.
+pub async fn most_downloaded_entries(
+    database: &Database,
+    days: Option<u16>,
+    limit: usize,
+) -> rusqlite::Result<Vec<DownloadedEntry>> {
+    let since = since(days);
+    database
+        .call(move |conn| -> rusqlite::Result<Vec<DownloadedEntry>> {
+            let query = r#"
+                SELECT file.entry_id, directory_entry.name AS entry_name, SUM(file_download.count) AS downloads
+                FROM file_download
+                INNER JOIN file ON file.id = file_download.file_id
+                INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+                WHERE ?1 IS NULL OR file_download.day >= ?1
+                GROUP BY file.entry_id
+                ORDER BY downloads DESC, file.entry_id
+                LIMIT ?2
+            "#;
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map((since, limit), |row| {
+                Ok(DownloadedEntry {
+                    entry_id: row.get("entry_id")?,
+                    entry_name: row.get("entry_name")?,
+                    downloads: row.get("downloads")?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+}
+
+/// Returns the oldest files that were never downloaded.
+pub async fn never_downloaded_files(database: &Database, limit: usize) -> rusqlite::Result<Vec<DownloadedFile>> {
+    database
+        .call(move |conn| -> rusqlite::Result<Vec<DownloadedFile>> {
+            let query = r#"
+                SELECT file.entry_id, directory_entry.name AS entry_name, file.name, 0 AS downloads
+                FROM file INNER JOIN directory_entry ON directory_entry.id = file.entry_id
+                WHERE NOT EXISTS (SELECT 1 FROM file_download WHERE file_download.file_id = file.id)
+                ORDER BY file.created_at, file.id
+                LIMIT ?
+            "#;
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map([limit], DownloadedFile::from_row)?;
+            rows.collect()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+    use crate::files::{self, StoredFile};
+
+    async fn database() -> Database {
+        let db =
+            test_database("INSERT INTO directory_entry(id, path, name) VALUES (1, 'a', 'a'), (2, 'b', 'b');").await;
+        let files = vec![
+            StoredFile::new(1, "01.srt".into(), b"one", None),
+            StoredFile::new(1, "02.srt".into(), b"two", None),
+            StoredFile::new(2, "01.srt".into(), b"three", None),
+        ];
+        files::record(&db, files).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn test_download_counts() {
+        let db = database().await;
+        record(&db, 1, vec!["01.srt".into(), "missing.srt".into()])
+            .await
+            .unwrap();
+        record(&db, 1, vec!["01.srt".into()]).await.unwrap();
+        record(&db, 2, vec!["01.srt".into()]).await.unwrap();
+
+        let counts = for_entry(&db, 1).await.unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts.get("01.srt"), Some(&2));
+
+        let files = most_downloaded_files(&db, None, 10).await.unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].entry_id, files[0].downloads), (1, 2));
+        assert_eq!(most_downloaded_files(&db, Some(7), 10).await.unwrap(), files);
+
+        let entries = most_downloaded_entries(&db, Some(1), 10).await.unwrap();
+        assert_eq!(
+            entries.iter().map(|e| (e.entry_id, e.downloads)).collect::<Vec<_>>(),
+            [(1, 2), (2, 1)]
+        );
+
+        let never = never_downloaded_files(&db, 10).await.unwrap();
+        assert_eq!(never.len(), 1);
+        assert_eq!((never[0].entry_id, never[0].name.as_str()), (1, "02.srt"));
+    }
+}
@@ -0,0 +1,445 @@
+//! The storage backend that entry files are kept in.
+//!
+//! Everything that touches the files of an entry, or the trash, goes through
+//! the [`Storage`] trait rather than the filesystem directly. Locations are
+//! still given as paths, e.g. an entry's `path` joined with a file name, but
+//! it's up to the implementation what they map to.
+//!
+//! The default implementation is [`FileSystem`], which maps them directly to
+//! the local filesystem.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+
+/// Metadata about a stored file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// The size of the file in bytes, or 0 for directories.
+    pub size: u64,
+    /// The date the file was last modified.
+    pub modified: OffsetDateTime,
+    /// Whether this is a directory rather than a file.
+    pub is_directory: bool,
+}
+
+/// A file found when listing a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    /// The file's name within the directory.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The date the file was last modified.
+    pub modified: OffsetDateTime,
+}
+
+/// A storage backend for files.
+#[async_trait]
+pub trait Storage: Send + Sync + 'static {
+    /// Lists the files directly within the directory, in no particular order.
+    ///
+    /// Directories and files with names that aren't valid UTF-8 are skipped.
+    async fn list(&self, directory: &Path) -> std::io::Result<Vec<FileInfo>>;
+
+    /// Lists the names of the directories directly within the directory, in no particular order.
+    async fn list_directories(&self, directory: &Path) -> std::io::Result<Vec<String>>;
+
+    /// Reads the entire contents of the file.
+    async fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+
+    /// Writes the file, creating it if it doesn't exist.
+    ///
+    /// If `overwrite` is `false` and the file already exists then this fails
+    /// with [`std::io::ErrorKind::AlreadyExists`].
+    async fn write(&self, path: &Path, bytes: &[u8], overwrite: bool) -> std::io::Result<()>;
+
+    /// Renames a file or directory.
+    ///
+    /// If the destination already exists then this fails with [`std::io::ErrorKind::AlreadyExists`].
+    async fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()>;
+
+    /// Deletes a file.
+    async fn delete(&self, path: &Path) -> std::io::Result<()>;
+
+    /// Returns the metadata of a file or directory, or `None` if it doesn't exist.
+    async fn stat(&self, path: &Path) -> std::io::Result<Option<Metadata>>;
+
+    /// Creates a directory along with all of its parents.
+    ///
+    /// This succeeds if the directory already exists.
+    async fn create_directory(&self, path: &Path) -> std::io::Result<()>;
+
+    /// Deletes a directory along with everything within it.
+    async fn delete_directory(&self, path: &Path) -> std::io::Result<()>;
+
+    /// Returns where the file is on the local filesystem, if it's stored there.
+    ///
+    /// This allows files to be served or streamed without reading them into memory first.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Returns whether the file or directory exists.
+pub async fn exists(storage: &dyn Storage, path: &Path) -> bool {
+    matches!(storage.stat(path).await, Ok(Some(_)))
+}
+
+/// Returns whether the path refers to a file.
+pub async fn is_file(storage: &dyn Storage, path: &Path) -> bool {
+    matches!(storage.stat(path).await, Ok(Some(m)) if !m.is_directory)
+}
+
+/// A stored file opened for reading, for use within blocking code.
+pub enum Reader {
+    File(BufReader<std::fs::File>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(f) => f.read(buf),
+            Self::Memory(m) => m.read(buf),
+        }
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Self::File(f) => f.seek(pos),
+            Self::Memory(m) => m.seek(pos),
+        }
+    }
+}
+
+/// Opens a stored file for reading.
+///
+/// Files on the local filesystem are read as needed, anything else is read into memory.
+pub async fn open(storage: &dyn Storage, path: &Path) -> std::io::Result<Reader> {
+    match storage.local_path(path) {
+        Some(local) => {
+            let file = tokio::fs::File::open(local).await?.into_std().await;
+            Ok(Reader::File(BufReader::new(file)))
+        }
+        None => Ok(Reader::Memory(Cursor::new(storage.read(path).await?))),
+    }
+}
+
+fn modified_date(metadata: &std::fs::Metadata) -> OffsetDateTime {
+    metadata
+        .modified()
+        .map(OffsetDateTime::from)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+fn already_exists() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination already exists")
+}
+
+/// Storage that maps paths directly to the local filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+#[async_trait]
+impl Storage for FileSystem {
+    async fn list(&self, directory: &Path) -> std::io::Result<Vec<FileInfo>> {
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(String::from) else {
+                continue;
+            };
+            files.push(FileInfo {
+                name,
+                size: metadata.len(),
+                modified: modified_date(&metadata),
+            });
+        }
+        Ok(files)
+    }
+
+    async fn list_directories(&self, directory: &Path) -> std::io::Result<Vec<String>> {
+        let mut directories = Vec::new();
+        let mut entries = tokio::fs::read_dir(directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                directories.push(name.to_owned());
+            }
+        }
+        Ok(directories)
+    }
+
+    async fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+
+    async fn write(&self, path: &Path, bytes: &[u8], overwrite: bool) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let mut fp = tokio::fs::OpenOptions::new()
+            .write(true)
+            .truncate(overwrite)
+            .create(overwrite)
+            .create_new(!overwrite)
+            .open(path)
+            .await?;
+        fp.write_all(bytes).await?;
+        fp.flush().await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        if tokio::fs::try_exists(to).await? {
+            return Err(already_exists());
+        }
+        tokio::fs::rename(from, to).await
+    }
+
+    async fn delete(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn stat(&self, path: &Path) -> std::io::Result<Option<Metadata>> {
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) => Ok(Some(Metadata {
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: modified_date(&metadata),
+                is_directory: metadata.is_dir(),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn create_directory(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    async fn delete_directory(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::remove_dir_all(path).await
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(path.to_path_buf())
+    }
+}
+
+/// Storage that keeps everything in memory.
+///
+/// This is mainly meant for tests.
+#[derive(Debug, Default)]
+pub struct Memory {
+    files: Mutex<BTreeMap<PathBuf, (Vec<u8>, OffsetDateTime)>>,
+    directories: Mutex<std::collections::BTreeSet<PathBuf>>,
+}
+
+fn not_found() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, "file not found")
+}
+
+impl Memory {
+    fn has_directory(&self, path: &Path) -> bool {
+        self.directories.lock().unwrap().contains(path)
+    }
+}
+
+#[async_trait]
+impl Storage for Memory {
+    async fn list(&self, directory: &Path) -> std::io::Result<Vec<FileInfo>> {
+        if !self.has_directory(directory) {
+            return Err(not_found());
+        }
+        let files = self.files.lock().unwrap();
+        Ok(files
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(directory))
+            .filter_map(|(path, (bytes, modified))| {
+                Some(FileInfo {
+                    name: path.file_name()?.to_str()?.to_owned(),
+                    size: bytes.len() as u64,
+                    modified: *modified,
+                })
+            })
+            .collect())
+    }
+
+    async fn list_directories(&self, directory: &Path) -> std::io::Result<Vec<String>> {
+        if !self.has_directory(directory) {
+            return Err(not_found());
+        }
+        let directories = self.directories.lock().unwrap();
+        Ok(directories
+            .iter()
+            .filter(|path| path.parent() == Some(directory))
+            .filter_map(|path| Some(path.file_name()?.to_str()?.to_owned()))
+            .collect())
+    }
+
+    async fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        let files = self.files.lock().unwrap();
+        files.get(path).map(|(bytes, _)| bytes.clone()).ok_or_else(not_found)
+    }
+
+    async fn write(&self, path: &Path, bytes: &[u8], overwrite: bool) -> std::io::Result<()> {
+        if !path.parent().is_some_and(|p| self.has_directory(p)) {
+            return Err(not_found());
+        }
+        let mut files = self.files.lock().unwrap();
+        if !overwrite && files.contains_key(path) {
+            return Err(already_exists());
+        }
+        files.insert(path.to_path_buf(), (bytes.to_vec(), OffsetDateTime::now_utc()));
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let mut directories = self.directories.lock().unwrap();
+        if files.contains_key(to) || directories.contains(to) {
+            return Err(already_exists());
+        }
+        if let Some(file) = files.remove(from) {
+            files.insert(to.to_path_buf(), file);
+            return Ok(());
+        }
+        if !directories.remove(from) {
+            return Err(not_found());
+        }
+        let moved = directories
+            .iter()
+            .filter_map(|p| Some((p.clone(), to.join(p.strip_prefix(from).ok()?))))
+            .collect::<Vec<_>>();
+        for (old, new) in moved {
+            directories.remove(&old);
+            directories.insert(new);
+        }
+        directories.insert(to.to_path_buf());
+        let moved = files
+            .keys()
+            .filter_map(|p| Some((p.clone(), to.join(p.strip_prefix(from).ok()?))))
+            .collect::<Vec<_>>();
+        for (old, new) in moved {
+            if let Some(file) = files.remove(&old) {
+                files.insert(new, file);
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, path: &Path) -> std::io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        files.remove(path).map(|_| ()).ok_or_else(not_found)
+    }
+
+    async fn stat(&self, path: &Path) -> std::io::Result<Option<Metadata>> {
+        if self.has_directory(path) {
+            return Ok(Some(Metadata {
+                size: 0,
+                modified: OffsetDateTime::UNIX_EPOCH,
+                is_directory: true,
+            }));
+        }
+        let files = self.files.lock().unwrap();
+        Ok(files.get(path).map(|(bytes, modified)| Metadata {
+            size: bytes.len() as u64,
+            modified: *modified,
+            is_directory: false,
+        }))
+    }
+
+    async fn create_directory(&self, path: &Path) -> std::io::Result<()> {
+        let mut directories = self.directories.lock().unwrap();
+        directories.extend(path.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    async fn delete_directory(&self, path: &Path) -> std::io::Result<()> {
+        if !self.has_directory(path) {
+            return Err(not_found());
+        }
+        self.directories.lock().unwrap().retain(|p| !p.starts_with(path));
+        self.files.lock().unwrap().retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the behaviour that every storage implementation should have.
+    async fn check_storage(storage: &dyn Storage, base: &Path) {
+        let entry = base.join("entry");
+        storage.create_directory(&entry).await.unwrap();
+        assert!(storage.stat(&entry).await.unwrap().is_some_and(|m| m.is_directory));
+        storage.create_directory(&entry).await.unwrap();
+
+        let file = entry.join("01.srt");
+        storage.write(&file, b"hello", false).await.unwrap();
+        let error = storage.write(&file, b"again", false).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(storage.read(&file).await.unwrap(), b"hello");
+        storage.write(&file, b"hi", true).await.unwrap();
+        assert_eq!(storage.read(&file).await.unwrap(), b"hi");
+        assert!(is_file(storage, &file).await);
+        assert!(!exists(storage, &entry.join("02.srt")).await);
+
+        storage.create_directory(&entry.join("nested")).await.unwrap();
+        let listed = storage.list(&entry).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].name.as_str(), listed[0].size), ("01.srt", 2));
+        assert_eq!(storage.list_directories(&entry).await.unwrap(), ["nested"]);
+
+        let renamed = entry.join("02.srt");
+        storage.write(&renamed, b"other", false).await.unwrap();
+        let error = storage.rename(&file, &renamed).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        storage.delete(&renamed).await.unwrap();
+        storage.rename(&file, &renamed).await.unwrap();
+        assert!(!exists(storage, &file).await);
+
+        let mut contents = String::new();
+        open(storage, &renamed)
+            .await
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hi");
+
+        let moved = base.join("moved");
+        storage.rename(&entry, &moved).await.unwrap();
+        assert_eq!(storage.read(&moved.join("02.srt")).await.unwrap(), b"hi");
+        storage.delete_directory(&moved).await.unwrap();
+        assert!(!exists(storage, &moved).await);
+        assert!(storage.list(&moved).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_system() {
+        let base = std::env::temp_dir().join(format!("jimaku-storage-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        check_storage(&FileSystem, &base).await;
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[tokio::test]
+    async fn test_memory() {
+        check_storage(&Memory::default(), Path::new("/library")).await;
+    }
+}
@@ -0,0 +1,917 @@
+//! Parsing and validation of text based subtitle formats.
+//!
+//! This is not meant to be a complete implementation of either format, it only
+//! understands enough of the structure to tell whether a file is a well formed
+//! subtitle file and to extract the cues within it.
+
+use std::{borrow::Cow, fmt::Write, path::Path};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A text based subtitle format that can be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// SubRip
+    Srt,
+    /// Advanced SubStation Alpha
+    Ass,
+    /// SubStation Alpha
+    Ssa,
+}
+
+impl Format {
+    /// Returns the format for the given file extension, if it's a known text format.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        if ext.eq_ignore_ascii_case("srt") {
+            Some(Self::Srt)
+        } else if ext.eq_ignore_ascii_case("ass") {
+            Some(Self::Ass)
+        } else if ext.eq_ignore_ascii_case("ssa") {
+            Some(Self::Ssa)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the format of the given path based off of its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension().and_then(|s| s.to_str()).and_then(Self::from_extension)
+    }
+
+    /// Returns the file extension used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::Ass => "ass",
+            Self::Ssa => "ssa",
+        }
+    }
+
+    /// Returns the MIME type used for this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Srt => "application/x-subrip; charset=utf-8",
+            Self::Ass | Self::Ssa => "text/x-ssa; charset=utf-8",
+        }
+    }
+}
+
+/// A point in time within a subtitle file, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut buffer = String::new();
+        self.write_to(&mut buffer, '.');
+        f.write_str(&buffer)
+    }
+}
+
+impl Timestamp {
+    /// Parses an SRT timestamp, e.g. `01:02:03,456`.
+    ///
+    /// A `.` is also accepted as the millisecond separator since it's a common mistake.
+    fn parse_srt(s: &str) -> Option<Self> {
+        let (rest, millis) = s.rsplit_once([',', '.'])?;
+        if millis.is_empty() || millis.len() > 3 {
+            return None;
+        }
+        let millis = parse_digits(millis)? * 10u64.pow(3 - millis.len() as u32);
+        Self::from_parts(rest, millis)
+    }
+
+    /// Parses an ASS timestamp, e.g. `1:02:03.45`.
+    fn parse_ass(s: &str) -> Option<Self> {
+        let (rest, fraction) = s.split_once('.')?;
+        if fraction.is_empty() || fraction.len() > 3 {
+            return None;
+        }
+        let millis = parse_digits(fraction)? * 10u64.pow(3 - fraction.len() as u32);
+        Self::from_parts(rest, millis)
+    }
+
+    fn from_parts(s: &str, millis: u64) -> Option<Self> {
+        let mut parts = s.split(':');
+        let hours = parse_digits(parts.next()?)?;
+        let minutes = parse_digits(parts.next()?)?;
+        let seconds = parse_digits(parts.next()?)?;
+        if parts.next().is_some() || minutes >= 60 || seconds >= 60 {
+            return None;
+        }
+        // The hours are unbounded so a malformed file could overflow
+        let total = hours
+            .checked_mul(60)?
+            .checked_add(minutes)?
+            .checked_mul(60)?
+            .checked_add(seconds)?
+            .checked_mul(1000)?
+            .checked_add(millis)?;
+        Some(Self(total))
+    }
+
+    /// Returns the timestamp in milliseconds.
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Writes the timestamp in the `H:MM:SS.cc` form used by ASS files.
+    fn write_ass(&self, buffer: &mut String) {
+        // ASS only has centisecond precision
+        let centis = self.0.saturating_add(5) / 10;
+        let seconds = (centis / 100) % 60;
+        let minutes = (centis / 6_000) % 60;
+        let hours = centis / 360_000;
+        let _ = write!(buffer, "{hours}:{minutes:02}:{seconds:02}.{:02}", centis % 100);
+    }
+
+    /// Writes the timestamp in `HH:MM:SS.mmm` form with the given millisecond separator.
+    fn write_to(&self, buffer: &mut String, separator: char) {
+        let millis = self.0 % 1000;
+        let seconds = (self.0 / 1000) % 60;
+        let minutes = (self.0 / 60_000) % 60;
+        let hours = self.0 / 3_600_000;
+        let _ = write!(buffer, "{hours:02}:{minutes:02}:{seconds:02}{separator}{millis:03}");
+    }
+}
+
+fn parse_digits(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// A single subtitle cue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// The text of the cue as written in the file.
+    ///
+    /// For SRT files multiple lines are separated by `\n`. For ASS files this
+    /// is the raw text which can contain override tags and `\N` line breaks.
+    pub text: String,
+    /// The name of the style used by the cue. This is only set for ASS and SSA files.
+    pub style: Option<String>,
+}
+
+/// A cue with its formatting removed, used to preview a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PreviewCue {
+    /// When the cue starts, in milliseconds.
+    #[schema(value_type = u64)]
+    pub start: Timestamp,
+    /// When the cue ends, in milliseconds.
+    #[schema(value_type = u64)]
+    pub end: Timestamp,
+    /// The text of the cue without any formatting. Multiple lines are separated by `\n`.
+    pub text: String,
+    /// The name of the style used by the cue. This is only given for ASS and SSA files.
+    pub style: Option<String>,
+}
+
+/// A parsed subtitle file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subtitle {
+    pub format: Format,
+    pub cues: Vec<Cue>,
+}
+
+/// A change to the timing of every cue in a subtitle file.
+///
+/// The framerate conversion is applied before the shift.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Retime {
+    /// The number of milliseconds to shift every timestamp by. This can be negative.
+    pub shift: i64,
+    /// The framerate the subtitle was timed for and the framerate to convert it to.
+    pub framerate: Option<(f64, f64)>,
+}
+
+impl Retime {
+    /// The maximum shift that can be applied, 24 hours.
+    pub const MAX_SHIFT: i64 = 24 * 3_600_000;
+
+    /// Creates and validates a retime from its optional parts.
+    ///
+    /// The framerates have to either both be given or both be missing.
+    pub fn new(shift: Option<i64>, from_fps: Option<f64>, to_fps: Option<f64>) -> Result<Self, &'static str> {
+        let shift = shift.unwrap_or(0);
+        if shift.abs() > Self::MAX_SHIFT {
+            return Err("shift can only be up to 24 hours");
+        }
+        let framerate = match (from_fps, to_fps) {
+            (None, None) => None,
+            (Some(from), Some(to)) => {
+                let valid = |fps: f64| fps.is_finite() && (1.0..=1000.0).contains(&fps);
+                if !valid(from) || !valid(to) {
+                    return Err("framerate must be between 1 and 1000");
+                }
+                Some((from, to))
+            }
+            _ => return Err("both from_fps and to_fps must be given"),
+        };
+        Ok(Self { shift, framerate })
+    }
+
+    /// Returns whether this retime leaves the timestamps unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.shift == 0 && self.framerate.map(|(from, to)| from == to).unwrap_or(true)
+    }
+
+    /// Applies the retime to a timestamp. Timestamps that end up negative are clamped to 0.
+    pub fn apply(&self, timestamp: Timestamp) -> Timestamp {
+        let mut millis = timestamp.0 as f64;
+        if let Some((from, to)) = self.framerate {
+            millis = millis * from / to;
+        }
+        let millis = (millis.round() as i64).saturating_add(self.shift);
+        Timestamp(millis.max(0) as u64)
+    }
+
+    /// Returns the retimed start and end of a cue.
+    ///
+    /// Returns `None` if the cue was shifted to before the start of the file.
+    fn apply_cue(&self, start: Timestamp, end: Timestamp) -> Option<(Timestamp, Timestamp)> {
+        let new_end = self.apply(end);
+        if new_end.0 == 0 && end.0 != 0 {
+            return None;
+        }
+        Some((self.apply(start), new_end))
+    }
+}
+
+/// A format that a subtitle file can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Conversion {
+    /// SubRip
+    Srt,
+    /// WebVTT
+    Vtt,
+}
+
+impl Conversion {
+    /// Returns the format for the given file extension, if it's a known format.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        if ext.eq_ignore_ascii_case("srt") {
+            Some(Self::Srt)
+        } else if ext.eq_ignore_ascii_case("vtt") {
+            Some(Self::Vtt)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the file extension used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+        }
+    }
+
+    /// Returns the MIME type used for this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Srt => "application/x-subrip; charset=utf-8",
+            Self::Vtt => "text/vtt; charset=utf-8",
+        }
+    }
+
+    /// Returns whether a file of the given format needs to be converted to be in this format.
+    pub fn is_needed_for(&self, format: Format) -> bool {
+        !matches!((self, format), (Self::Srt, Format::Srt))
+    }
+}
+
+/// Removes formatting that can't be represented outside of the original format.
+///
+/// For ASS files this removes override blocks and drawings and replaces the
+/// special line break and hard space escapes. SRT files sometimes contain ASS
+/// style positioning tags such as `{\an8}` which are removed as well.
+fn strip_formatting(format: Format, text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut drawing = false;
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.starts_with('{') && (format != Format::Srt || rest.starts_with("{\\")) {
+            if let Some(end) = rest.find('}') {
+                let block = &rest[1..end];
+                // \p1 and above turns on drawing mode until \p0
+                for tag in block.split('\\') {
+                    if let Some(scale) = tag.strip_prefix('p').and_then(parse_digits) {
+                        drawing = scale != 0;
+                    }
+                }
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+
+        if format != Format::Srt {
+            if let Some(escape) = rest.strip_prefix('\\') {
+                let replacement = match escape.bytes().next() {
+                    Some(b'N' | b'n') => Some('\n'),
+                    Some(b'h') => Some('\u{a0}'),
+                    _ => None,
+                };
+                if let Some(ch) = replacement {
+                    if !drawing {
+                        output.push(ch);
+                    }
+                    rest = &escape[1..];
+                    continue;
+                }
+            }
+        }
+
+        let ch = rest.chars().next().unwrap();
+        if !drawing {
+            output.push(ch);
+        }
+        rest = &rest[ch.len_utf8()..];
+    }
+    output
+}
+
+impl Subtitle {
+    /// Returns the cues in the order they should be displayed with their formatting removed.
+    ///
+    /// Cues that are left without any text are skipped.
+    pub fn plain_cues(&self) -> Vec<(Timestamp, Timestamp, String)> {
+        self.stripped_cues()
+            .into_iter()
+            .map(|(cue, text)| (cue.start, cue.end, text))
+            .collect()
+    }
+
+    /// Returns up to `limit` of the first cues to display with their formatting removed.
+    pub fn preview(&self, limit: usize) -> Vec<PreviewCue> {
+        self.stripped_cues()
+            .into_iter()
+            .take(limit)
+            .map(|(cue, text)| PreviewCue {
+                start: cue.start,
+                end: cue.end,
+                text,
+                style: cue.style.clone(),
+            })
+            .collect()
+    }
+
+    fn stripped_cues(&self) -> Vec<(&Cue, String)> {
+        let mut cues = self
+            .cues
+            .iter()
+            .filter_map(|cue| {
+                let text = strip_formatting(self.format, &cue.text);
+                let lines = text
+                    .lines()
+                    .map(str::trim_end)
+                    .filter(|s| !s.trim().is_empty())
+                    .collect::<Vec<_>>();
+                if lines.is_empty() {
+                    None
+                } else {
+                    Some((cue, lines.join("\n")))
+                }
+            })
+            .collect::<Vec<_>>();
+        // ASS events are not required to be in order
+        cues.sort_by_key(|(cue, _)| cue.start);
+        cues
+    }
+
+    /// Converts the subtitle to an SRT file.
+    pub fn to_srt(&self) -> String {
+        let mut buffer = String::new();
+        for (index, (start, end, text)) in self.plain_cues().into_iter().enumerate() {
+            let _ = writeln!(buffer, "{}", index + 1);
+            start.write_to(&mut buffer, ',');
+            buffer.push_str(" --> ");
+            end.write_to(&mut buffer, ',');
+            buffer.push('\n');
+            buffer.push_str(&text);
+            buffer.push_str("\n\n");
+        }
+        buffer
+    }
+
+    /// Converts the subtitle to a WebVTT file.
+    pub fn to_vtt(&self) -> String {
+        let mut buffer = String::from("WEBVTT\n\n");
+        for (start, end, text) in self.plain_cues() {
+            start.write_to(&mut buffer, '.');
+            buffer.push_str(" --> ");
+            end.write_to(&mut buffer, '.');
+            buffer.push('\n');
+            if self.format == Format::Srt {
+                // SRT formatting tags such as <i> are valid WebVTT
+                buffer.push_str(&text.replace("-->", "--&gt;"));
+            } else {
+                buffer.push_str(&text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"));
+            }
+            buffer.push_str("\n\n");
+        }
+        buffer
+    }
+
+    /// Retimes every cue in the subtitle.
+    ///
+    /// Cues that are shifted to before the start of the file are removed.
+    pub fn retime(&mut self, retime: &Retime) {
+        self.cues.retain_mut(|cue| match retime.apply_cue(cue.start, cue.end) {
+            Some((start, end)) => {
+                cue.start = start;
+                cue.end = end;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Writes the cues back out as an SRT file without touching their text.
+    ///
+    /// Unlike [`Self::to_srt`] this keeps formatting, so it only makes sense for SRT files.
+    fn to_raw_srt(&self) -> String {
+        let mut buffer = String::new();
+        for (index, cue) in self.cues.iter().enumerate() {
+            let _ = writeln!(buffer, "{}", index + 1);
+            cue.start.write_to(&mut buffer, ',');
+            buffer.push_str(" --> ");
+            cue.end.write_to(&mut buffer, ',');
+            buffer.push('\n');
+            buffer.push_str(&cue.text);
+            buffer.push_str("\n\n");
+        }
+        buffer
+    }
+
+    /// Converts the subtitle to the given format.
+    pub fn convert(&self, to: Conversion) -> String {
+        match to {
+            Conversion::Srt => self.to_srt(),
+            Conversion::Vtt => self.to_vtt(),
+        }
+    }
+}
+
+/// An error that occurred while parsing a subtitle file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The 1-indexed line the error occurred on, if it's specific to a line.
+    pub line: Option<usize>,
+    pub message: &'static str,
+}
+
+impl ParseError {
+    fn new(message: &'static str) -> Self {
+        Self { line: None, message }
+    }
+
+    fn at(line: usize, message: &'static str) -> Self {
+        Self {
+            line: Some(line + 1),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => f.write_str(self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Decodes the bytes of a subtitle file into text.
+///
+/// The encoding of the file is detected using [`crate::encoding::detect`].
+/// Files with NUL characters in them are assumed to be binary data and are rejected.
+/// Invalid sequences are replaced since only the structure of the file matters
+/// when parsing.
+pub fn decode(bytes: &[u8]) -> Result<Cow<'_, str>, ParseError> {
+    let text = crate::encoding::decode(bytes);
+    if text.contains('\0') {
+        return Err(ParseError::new("file contains binary data"));
+    }
+    Ok(text)
+}
+
+/// Parses the text of a subtitle file of the given format.
+pub fn parse(format: Format, text: &str) -> Result<Subtitle, ParseError> {
+    let cues = match format {
+        Format::Srt => parse_srt(text)?,
+        Format::Ass | Format::Ssa => parse_ass(text)?,
+    };
+    Ok(Subtitle { format, cues })
+}
+
+/// Converts the bytes of a subtitle file of the given format to another format.
+pub fn convert(format: Format, bytes: &[u8], to: Conversion) -> Result<String, ParseError> {
+    let text = decode(bytes)?;
+    parse(format, &text).map(|subtitle| subtitle.convert(to))
+}
+
+/// Returns up to `limit` of the first cues of a subtitle file of the given format.
+pub fn preview(format: Format, bytes: &[u8], limit: usize) -> Result<Vec<PreviewCue>, ParseError> {
+    let text = decode(bytes)?;
+    parse(format, &text).map(|subtitle| subtitle.preview(limit))
+}
+
+/// Retimes the bytes of a subtitle file of the given format.
+///
+/// If a conversion is given then the output is in that format, otherwise it's
+/// in the original format. ASS and SSA files keep everything other than the
+/// event timestamps intact.
+pub fn retime(format: Format, bytes: &[u8], retime: &Retime, to: Option<Conversion>) -> Result<String, ParseError> {
+    let text = decode(bytes)?;
+    let mut subtitle = parse(format, &text)?;
+    match (format, to) {
+        (_, Some(to)) if to.is_needed_for(format) => {
+            subtitle.retime(retime);
+            Ok(subtitle.convert(to))
+        }
+        (Format::Srt, _) => {
+            subtitle.retime(retime);
+            Ok(subtitle.to_raw_srt())
+        }
+        (Format::Ass | Format::Ssa, _) => Ok(retime_ass(&text, retime)),
+    }
+}
+
+/// Checks whether the bytes are a well formed subtitle file of the given format.
+pub fn validate(format: Format, bytes: &[u8]) -> Result<(), ParseError> {
+    let text = decode(bytes)?;
+    parse(format, &text).map(|_| ())
+}
+
+fn parse_srt(text: &str) -> Result<Vec<Cue>, ParseError> {
+    let mut cues = Vec::new();
+    let mut lines = text.lines().enumerate().peekable();
+    let mut previous: Option<u64> = None;
+    loop {
+        while lines.next_if(|(_, line)| line.trim().is_empty()).is_some() {}
+        let Some((number, line)) = lines.next() else {
+            break;
+        };
+
+        let index = parse_digits(line.trim()).ok_or(ParseError::at(number, "expected a cue number"))?;
+        if previous.is_some_and(|p| index <= p) {
+            return Err(ParseError::at(number, "cue numbers are out of order"));
+        }
+        previous = Some(index);
+
+        let (number, line) = lines
+            .next()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .ok_or(ParseError::at(number, "cue is missing its timestamps"))?;
+        let (start, end) = line
+            .split_once("-->")
+            .and_then(|(start, end)| {
+                // The end timestamp can be followed by positioning information
+                let end = end.split_whitespace().next()?;
+                Some((Timestamp::parse_srt(start.trim())?, Timestamp::parse_srt(end)?))
+            })
+            .ok_or(ParseError::at(number, "invalid cue timestamps"))?;
+        if end < start {
+            return Err(ParseError::at(number, "cue ends before it starts"));
+        }
+
+        let mut text = String::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(line);
+        }
+        cues.push(Cue {
+            start,
+            end,
+            text,
+            style: None,
+        });
+    }
+
+    if cues.is_empty() {
+        return Err(ParseError::new("no cues found"));
+    }
+    Ok(cues)
+}
+
+/// The column layout of the `[Events]` section as given by its `Format:` line.
+struct EventFormat {
+    fields: usize,
+    start: usize,
+    end: usize,
+    style: Option<usize>,
+}
+
+impl EventFormat {
+    fn parse(s: &str) -> Option<Self> {
+        let fields = s.split(',').map(|f| f.trim().to_ascii_lowercase()).collect::<Vec<_>>();
+        let start = fields.iter().position(|f| f == "start")?;
+        let end = fields.iter().position(|f| f == "end")?;
+        let style = fields.iter().position(|f| f == "style");
+        // Text has to be the last field since it can contain commas
+        if fields.last().map(String::as_str) != Some("text") {
+            return None;
+        }
+        Some(Self {
+            fields: fields.len(),
+            start,
+            end,
+            style,
+        })
+    }
+}
+
+fn parse_ass(text: &str) -> Result<Vec<Cue>, ParseError> {
+    let mut cues = Vec::new();
+    let mut section: Option<String> = None;
+    let mut has_script_info = false;
+    let mut has_events = false;
+    let mut format: Option<EventFormat> = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with("!:") {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let name = name.to_ascii_lowercase();
+            if !has_script_info && name != "script info" {
+                return Err(ParseError::at(number, "expected a [Script Info] section"));
+            }
+            has_script_info = true;
+            has_events |= name == "events";
+            section = Some(name);
+            continue;
+        }
+
+        if !has_script_info {
+            return Err(ParseError::at(number, "expected a [Script Info] section"));
+        }
+
+        if section.as_deref() != Some("events") {
+            continue;
+        }
+
+        let Some((kind, rest)) = line.split_once(':') else {
+            return Err(ParseError::at(number, "invalid line in [Events] section"));
+        };
+
+        match kind {
+            "Format" => {
+                format = Some(EventFormat::parse(rest).ok_or(ParseError::at(number, "invalid event format"))?);
+            }
+            "Dialogue" | "Comment" => {
+                let format = format
+                    .as_ref()
+                    .ok_or(ParseError::at(number, "event found before the Format line"))?;
+                let values = rest.trim_start().splitn(format.fields, ',').collect::<Vec<_>>();
+                if values.len() != format.fields {
+                    return Err(ParseError::at(number, "event has too few fields"));
+                }
+                let start = Timestamp::parse_ass(values[format.start].trim())
+                    .ok_or(ParseError::at(number, "invalid event start time"))?;
+                let end = Timestamp::parse_ass(values[format.end].trim())
+                    .ok_or(ParseError::at(number, "invalid event end time"))?;
+                if end < start {
+                    return Err(ParseError::at(number, "event ends before it starts"));
+                }
+                if kind == "Dialogue" {
+                    cues.push(Cue {
+                        start,
+                        end,
+                        text: values[format.fields - 1].to_owned(),
+                        style: format
+                            .style
+                            .map(|i| values[i].trim())
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_owned),
+                    });
+                }
+            }
+            // Other event types such as Picture, Sound, Movie, and Command are valid but ignored
+            _ => {}
+        }
+    }
+
+    if !has_script_info {
+        return Err(ParseError::new("missing [Script Info] section"));
+    }
+    if !has_events {
+        return Err(ParseError::new("missing [Events] section"));
+    }
+    if cues.is_empty() {
+        return Err(ParseError::new("no dialogue events found"));
+    }
+    Ok(cues)
+}
+
+/// Retimes the events of an ASS file in place.
+///
+/// The text must have already been parsed successfully.
+fn retime_ass(text: &str, retime: &Retime) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut in_events = false;
+    let mut format: Option<EventFormat> = None;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        let trimmed = content.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            in_events = name.eq_ignore_ascii_case("events");
+        } else if in_events {
+            match trimmed.split_once(':') {
+                Some(("Format", rest)) => format = EventFormat::parse(rest),
+                Some((kind @ ("Dialogue" | "Comment"), rest)) => {
+                    if let Some(format) = &format {
+                        if let Some(retimed) = retime_event(kind, rest, format, retime) {
+                            output.push_str(&retimed);
+                            output.push_str(ending);
+                        }
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+        output.push_str(line);
+    }
+    output
+}
+
+/// Retimes a single `Dialogue` or `Comment` event line.
+///
+/// Returns `None` if the event was shifted to before the start of the file.
+fn retime_event(kind: &str, rest: &str, format: &EventFormat, retime: &Retime) -> Option<String> {
+    let mut values = rest.trim_start().splitn(format.fields, ',').collect::<Vec<_>>();
+    let start = values.get(format.start).and_then(|s| Timestamp::parse_ass(s.trim()));
+    let end = values.get(format.end).and_then(|s| Timestamp::parse_ass(s.trim()));
+    let (Some(start), Some(end)) = (start, end) else {
+        return Some(format!("{kind}: {}", rest.trim_start()));
+    };
+
+    let (start, end) = retime.apply_cue(start, end)?;
+    let mut start_buffer = String::new();
+    start.write_ass(&mut start_buffer);
+    let mut end_buffer = String::new();
+    end.write_ass(&mut end_buffer);
+    values[format.start] = &start_buffer;
+    values[format.end] = &end_buffer;
+    Some(format!("{kind}: {}", values.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:02,500\r\nこんにちは\r\n世界\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000 X1:0\r\nまた\r\n";
+
+    const ASS: &str = r"[Script Info]
+; comment
+ScriptType: v4.00+
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize
+Style: Default,Arial,20
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Comment: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,note
+Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,{\i1}こんにちは, 世界\Nまた
+";
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(Timestamp::parse_srt("01:02:03,456"), Some(Timestamp(3_723_456)));
+        assert_eq!(Timestamp::parse_srt("00:00:01.5"), Some(Timestamp(1_500)));
+        assert_eq!(Timestamp::parse_srt("00:61:00,000"), None);
+        assert_eq!(Timestamp::parse_srt("00:00:00"), None);
+        assert_eq!(Timestamp::parse_ass("1:02:03.45"), Some(Timestamp(3_723_450)));
+        assert_eq!(Timestamp::parse_ass("0:00:0a.00"), None);
+        assert_eq!(Timestamp::parse_srt("99999999999999999:00:00,000"), None);
+        assert_eq!(Timestamp::parse_ass("99999999999999999:00:00.00"), None);
+        assert_eq!(Timestamp::parse_srt("99999999999999999999:00:00,000"), None);
+        assert!(parse(
+            Format::Srt,
+            "1\n99999999999999999:00:00,000 --> 99999999999999999:00:01,000\na"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_srt() {
+        let parsed = parse(Format::Srt, SRT).unwrap();
+        assert_eq!(parsed.cues.len(), 2);
+        assert_eq!(parsed.cues[0].text, "こんにちは\n世界");
+        assert_eq!(parsed.cues[1].start, Timestamp(3_000));
+
+        assert!(parse(Format::Srt, "").is_err());
+        assert_eq!(
+            parse(
+                Format::Srt,
+                "1\n00:00:01,000 --> 00:00:02,000\na\n\n1\n00:00:03,000 --> 00:00:04,000\nb"
+            )
+            .unwrap_err()
+            .line,
+            Some(5)
+        );
+        // truncated
+        assert!(parse(Format::Srt, "1\n00:00:01,000 --> 00:00:02,000\na\n\n2").is_err());
+        assert!(parse(Format::Srt, "1\n00:00:02,000 --> 00:00:01,000\na").is_err());
+        assert!(validate(Format::Srt, b"\x00\x01\x02").is_err());
+    }
+
+    #[test]
+    fn test_parse_ass() {
+        let parsed = parse(Format::Ass, ASS).unwrap();
+        assert_eq!(parsed.cues.len(), 1);
+        assert_eq!(parsed.cues[0].text, r"{\i1}こんにちは, 世界\Nまた");
+        assert_eq!(parsed.cues[0].end, Timestamp(2_500));
+
+        assert!(parse(Format::Ass, SRT).is_err());
+        assert!(parse(Format::Ass, "[Script Info]\nScriptType: v4.00+\n").is_err());
+        assert!(parse(Format::Ass, &ASS.replace("Start, End, ", "")).is_err());
+        assert!(parse(Format::Ass, &ASS.replace("0:00:02.50", "0:00:02")).is_err());
+    }
+
+    #[test]
+    fn test_preview() {
+        let cues = preview(Format::Ass, ASS.as_bytes(), 10).unwrap();
+        assert_eq!(
+            cues,
+            vec![PreviewCue {
+                start: Timestamp(1_000),
+                end: Timestamp(2_500),
+                text: String::from("こんにちは, 世界\nまた"),
+                style: Some(String::from("Default")),
+            }]
+        );
+        assert_eq!(cues[0].start.to_string(), "00:00:01.000");
+
+        let cues = preview(Format::Srt, SRT.as_bytes(), 1).unwrap();
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].text, "こんにちは\n世界");
+        assert_eq!(cues[0].style, None);
+    }
+
+    #[test]
+    fn test_strip_formatting() {
+        assert_eq!(strip_formatting(Format::Ass, r"{\i1}a{\i0}\Nb\hc"), "a\nb\u{a0}c");
+        assert_eq!(strip_formatting(Format::Ass, r"{\p1}m 0 0 l 100 0{\p0}text"), "text");
+        assert_eq!(strip_formatting(Format::Srt, r"{\an8}<i>a</i> {b}"), "<i>a</i> {b}");
+    }
+
+    #[test]
+    fn test_convert() {
+        let srt = convert(Format::Ass, ASS.as_bytes(), Conversion::Srt).unwrap();
+        assert_eq!(srt, "1\n00:00:01,000 --> 00:00:02,500\nこんにちは, 世界\nまた\n\n");
+
+        let vtt = convert(Format::Srt, SRT.as_bytes(), Conversion::Vtt).unwrap();
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.500\nこんにちは\n世界\n\n00:00:03.000 --> 00:00:04.000\nまた\n\n"
+        );
+
+        let vtt = convert(Format::Ass, ASS.replace("また", "<b>").as_bytes(), Conversion::Vtt).unwrap();
+        assert!(vtt.ends_with("こんにちは, 世界\n&lt;b&gt;\n\n"));
+    }
+
+    #[test]
+    fn test_retime() {
+        let shift = Retime::new(Some(-2_500), None, None).unwrap();
+        assert_eq!(shift.apply(Timestamp(3_000)), Timestamp(500));
+        assert_eq!(shift.apply(Timestamp(1_000)), Timestamp(0));
+        let scale = Retime::new(None, Some(25.0), Some(24.0)).unwrap();
+        assert_eq!(scale.apply(Timestamp(24_000)), Timestamp(25_000));
+        assert!(Retime::new(None, Some(25.0), None).is_err());
+        assert!(Retime::new(None, Some(0.0), Some(24.0)).is_err());
+        assert!(Retime::new(Some(Retime::MAX_SHIFT + 1), None, None).is_err());
+        assert!(Retime::new(None, Some(24.0), Some(24.0)).unwrap().is_identity());
+
+        // the first cue ends up before the start and is removed
+        let srt = retime(Format::Srt, SRT.as_bytes(), &shift, None).unwrap();
+        assert_eq!(srt, "1\n00:00:00,500 --> 00:00:01,500\nまた\n\n");
+        let shift = Retime::new(Some(1_005), None, None).unwrap();
+        let vtt = retime(Format::Srt, SRT.as_bytes(), &shift, Some(Conversion::Vtt)).unwrap();
+        assert!(vtt.starts_with("WEBVTT\n\n00:00:02.005 --> 00:00:03.505\n"));
+
+        let ass = retime(Format::Ass, ASS.as_bytes(), &shift, None).unwrap();
+        assert!(ass.contains("Comment: 0,0:00:01.01,0:00:02.01,Default,,0,0,0,,note\n"));
+        assert!(ass.contains("Dialogue: 0,0:00:02.01,0:00:03.51,Default,,0,0,0,,{\\i1}こんにちは, 世界\\Nまた\n"));
+        assert!(ass.starts_with("[Script Info]\n; comment\n"));
+        assert!(ass.contains("Style: Default,Arial,20\n"));
+    }
+}
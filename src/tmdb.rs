@@ -257,6 +257,11 @@ impl Info {
     pub fn is_adult(&self) -> bool {
         self.adult
     }
+
+    /// Returns every alternative title of the media, in any language.
+    pub fn alternative_titles(&self) -> Vec<String> {
+        self.alternative_titles.titles.iter().map(|t| t.title.clone()).collect()
+    }
 }
 
 #[derive(Debug, Deserialize)]
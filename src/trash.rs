@@ -7,15 +7,19 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
+use crate::storage::Storage;
+
 /// A trash can that sort of implements the FreeDesktop.org trash spec
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct Trash {
+    storage: Arc<dyn Storage>,
     files: PathBuf,
     info: PathBuf,
 }
@@ -29,33 +33,25 @@ pub struct TrashInfo {
     pub entry_id: i64,
     #[serde(default)]
     pub reason: Option<String>,
+    #[serde(default)]
+    pub uploader_id: Option<i64>,
 }
 
 pub type TrashListing = HashMap<PathBuf, TrashInfo>;
 
-fn create_directory(path: &Path) -> anyhow::Result<()> {
-    if path.exists() {
-        return Ok(());
-    }
-
-    if let Err(e) = std::fs::create_dir(path) {
-        if e.kind() != std::io::ErrorKind::AlreadyExists {
-            return Err(e).with_context(|| format!("could not create directory {}", path.display()));
-        }
-    }
-    Ok(())
-}
-
 impl Trash {
-    pub fn new() -> anyhow::Result<Self> {
+    pub async fn new(storage: Arc<dyn Storage>) -> anyhow::Result<Self> {
         let mut base = dirs::data_dir().context("could not find a data directory for current user")?;
         base.push(crate::PROGRAM_NAME);
-        create_directory(&base)?;
         let files = base.join("files");
         let info = base.join("info");
-        create_directory(&files)?;
-        create_directory(&info)?;
-        Ok(Self { files, info })
+        for path in [&files, &info] {
+            storage
+                .create_directory(path)
+                .await
+                .with_context(|| format!("could not create directory {}", path.display()))?;
+        }
+        Ok(Self { storage, files, info })
     }
 
     pub fn files_path(&self) -> &Path {
@@ -69,7 +65,13 @@ impl Trash {
     /// Puts the file in the trash.
     ///
     /// This does not trash directories.
-    pub async fn put(&self, path: PathBuf, entry_id: i64, reason: Option<String>) -> std::io::Result<()> {
+    pub async fn put(
+        &self,
+        path: PathBuf,
+        entry_id: i64,
+        reason: Option<String>,
+        uploader_id: Option<i64>,
+    ) -> std::io::Result<()> {
         let (new_location, info_location) = match path.file_name().and_then(|s| s.to_str()) {
             Some(filename) => {
                 let filename = format!("{entry_id}_{filename}");
@@ -78,42 +80,42 @@ impl Trash {
             None => return Err(std::io::Error::other("path has no filename")),
         };
 
+        let Some(metadata) = self.storage.stat(&path).await? else {
+            return Err(std::io::ErrorKind::NotFound.into());
+        };
         let info = TrashInfo {
-            path: path.canonicalize()?,
+            path: match self.storage.local_path(&path) {
+                Some(local) => local.canonicalize()?,
+                None => path.clone(),
+            },
             deletion_date: OffsetDateTime::now_utc(),
-            size: path.metadata()?.len(),
+            size: metadata.size,
             entry_id,
             reason,
+            uploader_id,
         };
 
-        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
-            let info_file = std::fs::File::create(info_location)?;
-            serde_json::to_writer(info_file, &info).map_err(std::io::Error::other)?;
-            std::fs::rename(path, new_location)
-        })
-        .await
-        .map_err(std::io::Error::other)?
+        let json = serde_json::to_vec(&info).map_err(std::io::Error::other)?;
+        self.storage.write(&info_location, &json, true).await?;
+        if crate::storage::exists(self.storage.as_ref(), &new_location).await {
+            self.storage.delete(&new_location).await?;
+        }
+        self.storage.rename(&path, &new_location).await
+    }
+
+    async fn read_info(&self, path: &Path) -> std::io::Result<TrashInfo> {
+        let json = self.storage.read(path).await?;
+        serde_json::from_slice(&json).map_err(std::io::Error::other)
     }
 
     /// Returns everything that is in the trash
     pub async fn list(&self) -> std::io::Result<TrashListing> {
-        let reader = self.info.read_dir()?;
-        tokio::task::spawn_blocking(move || {
-            let mut map = HashMap::new();
-            for file in reader {
-                let Ok(entry) = file else { continue };
-                let path = entry.path();
-                let Some(filename) = path.file_name().map(|x| Path::new(x).to_path_buf()) else {
-                    continue;
-                };
-                let json = std::fs::read_to_string(&path)?;
-                let value: TrashInfo = serde_json::from_str(&json).map_err(std::io::Error::other)?;
-                map.insert(filename, value);
-            }
-            Ok(map)
-        })
-        .await
-        .map_err(std::io::Error::other)?
+        let mut map = HashMap::new();
+        for file in self.storage.list(&self.info).await? {
+            let value = self.read_info(&self.info.join(&file.name)).await?;
+            map.insert(PathBuf::from(file.name), value);
+        }
+        Ok(map)
     }
 
     /// Permanently deletes the file
@@ -121,29 +123,38 @@ impl Trash {
     /// The path must be the filename of the deleted file, e.g. `foo.zip` if `foo.zip` is in the trash.
     /// This is equivalent to the key in the return value for [`Self::list`].
     pub async fn delete(&self, filename: PathBuf) -> std::io::Result<()> {
-        let trash_path = self.files.join(&filename);
-        let info_path = self.info.join(&filename);
-
-        tokio::task::spawn_blocking(move || {
-            std::fs::remove_file(info_path)?;
-            std::fs::remove_file(trash_path)
-        })
-        .await
-        .map_err(std::io::Error::other)?
+        self.storage.delete(&self.info.join(&filename)).await?;
+        self.storage.delete(&self.files.join(&filename)).await
     }
 
     /// Restores the file
-    pub async fn restore(&self, filename: PathBuf) -> std::io::Result<()> {
+    ///
+    /// Returns the info of the restored file.
+    pub async fn restore(&self, filename: PathBuf) -> std::io::Result<TrashInfo> {
         let trash_path = self.files.join(&filename);
         let info_path = self.info.join(&filename);
-        tokio::task::spawn_blocking(move || {
-            let json = std::fs::read_to_string(&info_path)?;
-            let value: TrashInfo = serde_json::from_str(&json).map_err(std::io::Error::other)?;
-            std::fs::rename(trash_path, value.path)?;
-            std::fs::remove_file(info_path)
-        })
-        .await
-        .map_err(std::io::Error::other)?
+        let value = self.read_info(&info_path).await?;
+        self.storage.rename(&trash_path, &value.path).await?;
+        self.storage.delete(&info_path).await?;
+        Ok(value)
+    }
+
+    /// Returns the info records whose trashed file no longer exists.
+    ///
+    /// These are given as the filename of the record, the same as the keys of [`Self::list`].
+    pub async fn broken_records(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut broken = Vec::new();
+        for file in self.storage.list(&self.info).await? {
+            if !crate::storage::exists(self.storage.as_ref(), &self.files.join(&file.name)).await {
+                broken.push(PathBuf::from(file.name));
+            }
+        }
+        Ok(broken)
+    }
+
+    /// Removes an info record without touching the trashed file.
+    pub async fn remove_record(&self, filename: &Path) -> std::io::Result<()> {
+        self.storage.delete(&self.info.join(filename)).await
     }
 
     /// Returns a URL to the trash item
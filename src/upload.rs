@@ -0,0 +1,550 @@
+//! Resumable uploads of files that are too large to send in a single request.
+//!
+//! An upload session is created with the file's name, size, and SHA-256 hash.
+//! The file is then sent in numbered chunks which can arrive in any order and
+//! be re-sent as many times as needed, e.g. after a dropped connection. The
+//! chunks are kept on disk until the session is finalized, at which point
+//! they're put together and checked against the hash given at creation.
+//!
+//! Sessions that go untouched for [`SESSION_LIFETIME_HOURS`] expire and their
+//! chunks are removed.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{
+    database::Table,
+    error::{ApiError, ApiErrorCode},
+    AppState, Database,
+};
+
+/// The maximum size of a file uploaded through a session, in bytes.
+pub const MAX_SESSION_UPLOAD_SIZE: u64 = 1024 * 1024 * 256;
+/// The chunk size used if the client doesn't request one, in bytes.
+pub const DEFAULT_CHUNK_SIZE: u64 = 1024 * 1024 * 8;
+/// The minimum chunk size, in bytes.
+///
+/// This keeps the number of chunks, and therefore requests, reasonable.
+pub const MIN_CHUNK_SIZE: u64 = 1024 * 256;
+/// The maximum number of sessions an account can have open at once.
+pub const MAX_SESSIONS_PER_ACCOUNT: usize = 5;
+/// The number of hours a session can go without any activity before it expires.
+pub const SESSION_LIFETIME_HOURS: i64 = 24;
+
+fn lifetime_modifier() -> String {
+    format!("-{SESSION_LIFETIME_HOURS} hours")
+}
+
+/// An in-progress chunked upload.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UploadSession {
+    /// The session ID.
+    pub id: String,
+    /// The ID of the entry the file is being uploaded to.
+    pub entry_id: i64,
+    #[serde(skip)]
+    pub account_id: i64,
+    /// The file's name.
+    pub name: String,
+    /// The file's total size in bytes.
+    pub size: u64,
+    /// The SHA-256 hash of the file's contents, as a lowercase hexadecimal string.
+    pub sha256: String,
+    /// The size of every chunk in bytes, except the last one which holds the remainder.
+    pub chunk_size: u64,
+    /// The upload options as JSON, applied when the session is finalized.
+    #[serde(skip)]
+    pub options: String,
+    /// The date the session was created, in UTC, as an RFC3339 string.
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(skip)]
+    pub updated_at: OffsetDateTime,
+}
+
+impl Table for UploadSession {
+    const NAME: &'static str = "upload_session";
+
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "entry_id",
+        "account_id",
+        "name",
+        "size",
+        "sha256",
+        "chunk_size",
+        "options",
+        "created_at",
+        "updated_at",
+    ];
+
+    type Id = String;
+
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            entry_id: row.get("entry_id")?,
+            account_id: row.get("account_id")?,
+            name: row.get("name")?,
+            size: row.get("size")?,
+            sha256: row.get("sha256")?,
+            chunk_size: row.get("chunk_size")?,
+            options: row.get("options")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+impl UploadSession {
+    /// The total number of chunks the file is split into.
+    pub fn chunks(&self) -> u32 {
+        self.size.div_ceil(self.chunk_size) as u32
+    }
+
+    /// The expected size of the chunk at the given index, or `None` if it's out of range.
+    pub fn chunk_length(&self, index: u32) -> Option<u64> {
+        let chunks = self.chunks();
+        if index >= chunks {
+            None
+        } else if index + 1 == chunks {
+            Some(self.size - self.chunk_size * u64::from(index))
+        } else {
+            Some(self.chunk_size)
+        }
+    }
+
+    /// The date the session expires if nothing else is uploaded to it.
+    pub fn expires_at(&self) -> OffsetDateTime {
+        self.updated_at + time::Duration::hours(SESSION_LIFETIME_HOURS)
+    }
+}
+
+/// The progress of an upload session.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UploadProgress {
+    #[serde(flatten)]
+    pub session: UploadSession,
+    /// The total number of chunks the file is split into.
+    pub chunks: u32,
+    /// The indices of the chunks that were received, in ascending order.
+    pub received: Vec<u32>,
+    /// The date the session expires if nothing else is uploaded to it, in UTC, as an RFC3339 string.
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
+/// The details needed to start an upload session.
+#[derive(Debug, Clone)]
+pub struct NewUploadSession {
+    pub entry_id: i64,
+    pub account_id: i64,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub chunk_size: Option<u64>,
+    pub options: String,
+}
+
+/// The on-disk storage of upload session chunks.
+///
+/// Every session gets a directory named after its ID, and every chunk is a
+/// file within it named after its index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadSessions {
+    base: PathBuf,
+}
+
+fn is_session_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn random_session_id() -> anyhow::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(crate::key::to_hex(&bytes))
+}
+
+impl UploadSessions {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut base = dirs::data_dir().context("could not find a data directory for current user")?;
+        base.push(crate::PROGRAM_NAME);
+        base.push("uploads");
+        Self::at(base)
+    }
+
+    /// Stores the session chunks within the given directory.
+    pub fn at(base: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&base).with_context(|| format!("could not create directory {}", base.display()))?;
+        Ok(Self { base })
+    }
+
+    fn session_path(&self, id: &str) -> Option<PathBuf> {
+        is_session_id(id).then(|| self.base.join(id))
+    }
+
+    /// Starts a new upload session.
+    pub async fn create(&self, database: &Database, new: NewUploadSession) -> Result<UploadSession, ApiError> {
+        if new.size == 0 {
+            return Err(ApiError::new("File cannot be empty"));
+        }
+        if new.size > MAX_SESSION_UPLOAD_SIZE {
+            return Err(ApiError::new(format!(
+                "File can only be up to {} MiB",
+                MAX_SESSION_UPLOAD_SIZE / 1024 / 1024
+            )));
+        }
+        let sha256 = new.sha256.to_ascii_lowercase();
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ApiError::new("SHA-256 hash must be 64 hexadecimal characters"));
+        }
+        let chunk_size = new.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if !(MIN_CHUNK_SIZE..=crate::MAX_UPLOAD_SIZE).contains(&chunk_size) {
+            return Err(ApiError::new(format!(
+                "Chunk size must be between {} KiB and {} MiB",
+                MIN_CHUNK_SIZE / 1024,
+                crate::MAX_UPLOAD_SIZE / 1024 / 1024
+            )));
+        }
+
+        let open: usize = database
+            .get_row(
+                "SELECT COUNT(*) FROM upload_session WHERE account_id = ? AND updated_at >= datetime('now', ?)",
+                (new.account_id, lifetime_modifier()),
+                |row| row.get(0),
+            )
+            .await?;
+        if open >= MAX_SESSIONS_PER_ACCOUNT {
+            return Err(ApiError::new(format!(
+                "Can only have up to {MAX_SESSIONS_PER_ACCOUNT} uploads in progress at once"
+            )));
+        }
+
+        let id = random_session_id()?;
+        let path = self.base.join(&id);
+        let query = r#"
+            INSERT INTO upload_session(id, entry_id, account_id, name, size, sha256, chunk_size, options)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING *
+        "#;
+        let params = (
+            id,
+            new.entry_id,
+            new.account_id,
+            new.name,
+            new.size,
+            sha256,
+            chunk_size,
+            new.options,
+        );
+        let Some(session): Option<UploadSession> = database.get(query, params).await? else {
+            return Err(ApiError::new("Could not create upload session").with_code(ApiErrorCode::ServerError));
+        };
+        // The row is inserted first so expire never sees a directory without a session
+        if let Err(e) = tokio::fs::create_dir(&path).await {
+            let _ = database
+                .execute("DELETE FROM upload_session WHERE id = ?", [session.id.clone()])
+                .await;
+            return Err(e.into());
+        }
+        Ok(session)
+    }
+
+    /// Returns the session with the given ID if it belongs to the account and hasn't expired.
+    pub async fn get(&self, database: &Database, id: &str, account_id: i64) -> Result<UploadSession, ApiError> {
+        let session: Option<UploadSession> = database
+            .get(
+                "SELECT * FROM upload_session WHERE id = ? AND account_id = ? AND updated_at >= datetime('now', ?)",
+                (id.to_owned(), account_id, lifetime_modifier()),
+            )
+            .await?;
+        session.ok_or_else(|| ApiError::not_found("Upload session not found"))
+    }
+
+    /// Returns the indices of the chunks that were received, in ascending order.
+    pub async fn received(&self, session: &UploadSession) -> std::io::Result<Vec<u32>> {
+        let Some(path) = self.session_path(&session.id) else {
+            return Ok(Vec::new());
+        };
+        let mut received = Vec::new();
+        let mut entries = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(index) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) {
+                received.push(index);
+            }
+        }
+        received.sort_unstable();
+        Ok(received)
+    }
+
+    /// Returns the progress of the session.
+    pub async fn progress(&self, session: UploadSession) -> std::io::Result<UploadProgress> {
+        Ok(UploadProgress {
+            chunks: session.chunks(),
+            received: self.received(&session).await?,
+            expires_at: session.expires_at(),
+            session,
+        })
+    }
+
+    /// Stores a chunk of the file, replacing it if it was already received.
+    pub async fn write_chunk(
+        &self,
+        database: &Database,
+        session: &mut UploadSession,
+        index: u32,
+        bytes: &[u8],
+    ) -> Result<(), ApiError> {
+        let Some(expected) = session.chunk_length(index) else {
+            return Err(ApiError::new(format!(
+                "Chunk index must be less than {}",
+                session.chunks()
+            )));
+        };
+        if bytes.len() as u64 != expected {
+            return Err(ApiError::new(format!(
+                "Chunk {index} must be {expected} bytes, got {}",
+                bytes.len()
+            )));
+        }
+        let Some(path) = self.session_path(&session.id) else {
+            return Err(ApiError::not_found("Upload session not found"));
+        };
+
+        // Written to a temporary file first so an interrupted write isn't counted as received
+        let partial = path.join(format!("{index}.part"));
+        tokio::fs::write(&partial, bytes).await?;
+        tokio::fs::rename(&partial, path.join(index.to_string())).await?;
+        let updated_at: Option<OffsetDateTime> = database
+            .get_row(
+                "UPDATE upload_session SET updated_at = CURRENT_TIMESTAMP WHERE id = ? RETURNING updated_at",
+                [session.id.clone()],
+                |row| row.get(0),
+            )
+            .await
+            .ok();
+        if let Some(updated_at) = updated_at {
+            session.updated_at = updated_at;
+        }
+        Ok(())
+    }
+
+    /// Puts the chunks of the file back together and verifies them against the session's size and hash.
+    pub async fn assemble(&self, session: &UploadSession) -> Result<Vec<u8>, ApiError> {
+        let received = self.received(session).await?;
+        let missing = (0..session.chunks())
+            .filter(|i| received.binary_search(i).is_err())
+            .count();
+        if missing != 0 {
+            return Err(ApiError::new(format!(
+                "Missing {missing} of {} chunks",
+                session.chunks()
+            )));
+        }
+        let Some(path) = self.session_path(&session.id) else {
+            return Err(ApiError::not_found("Upload session not found"));
+        };
+
+        let chunks = session.chunks();
+        let size = session.size;
+        let bytes = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+            let mut bytes = Vec::with_capacity(size as usize);
+            for index in 0..chunks {
+                bytes.extend(std::fs::read(path.join(index.to_string()))?);
+            }
+            Ok(bytes)
+        })
+        .await??;
+
+        if bytes.len() as u64 != session.size {
+            return Err(ApiError::new(format!(
+                "File must be {} bytes, got {}",
+                session.size,
+                bytes.len()
+            )));
+        }
+        if crate::files::sha256_hex(&bytes) != session.sha256 {
+            return Err(ApiError::new(
+                "SHA-256 hash does not match, re-upload the chunks or cancel the upload",
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Removes the session and its chunks.
+    pub async fn remove(&self, database: &Database, id: &str) -> anyhow::Result<()> {
+        database
+            .execute("DELETE FROM upload_session WHERE id = ?", [id.to_owned()])
+            .await?;
+        if let Some(path) = self.session_path(id) {
+            remove_directory(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes every expired session along with any chunks that no longer have a session.
+    ///
+    /// Returns the number of sessions that were removed.
+    pub async fn expire(&self, database: &Database) -> anyhow::Result<usize> {
+        let removed = database
+            .execute(
+                "DELETE FROM upload_session WHERE updated_at < datetime('now', ?)",
+                [lifetime_modifier()],
+            )
+            .await?;
+        let active: Vec<String> = database
+            .call(|conn| -> rusqlite::Result<Vec<String>> {
+                let mut stmt = conn.prepare("SELECT id FROM upload_session")?;
+                let rows = stmt.query_map([], |row| row.get(0))?;
+                rows.collect()
+            })
+            .await?;
+
+        let mut entries = tokio::fs::read_dir(&self.base).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if name.to_str().is_some_and(|id| !active.iter().any(|a| a == id)) {
+                remove_directory(&entry.path()).await?;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+async fn remove_directory(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_dir_all(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Periodically removes expired upload sessions.
+pub async fn expire_sessions_loop(state: AppState) {
+    let sessions = match UploadSessions::new() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::error!(error=%e, "Could not open upload session storage");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match sessions.expire(state.database()).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!(removed, "Removed expired upload sessions"),
+            Err(e) => tracing::error!(error=%e, "Could not remove expired upload sessions"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+
+    async fn database() -> Database {
+        test_database(
+            "INSERT INTO account(id, name, password) VALUES (1, 'user', '');
+             INSERT INTO directory_entry(id, path, name) VALUES (1, 'a', 'a');",
+        )
+        .await
+    }
+
+    fn storage(name: &str) -> UploadSessions {
+        let path = std::env::temp_dir().join(format!("jimaku-upload-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        UploadSessions::at(path).unwrap()
+    }
+
+    fn new_session(bytes: &[u8]) -> NewUploadSession {
+        NewUploadSession {
+            entry_id: 1,
+            account_id: 1,
+            name: "season.sup".into(),
+            size: bytes.len() as u64,
+            sha256: crate::files::sha256_hex(bytes),
+            chunk_size: Some(MIN_CHUNK_SIZE),
+            options: "{}".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_session() {
+        let db = database().await;
+        let sessions = storage("session");
+        let bytes = (0..MIN_CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let chunk = MIN_CHUNK_SIZE as usize;
+
+        let mut session = sessions.create(&db, new_session(&bytes)).await.unwrap();
+        assert_eq!(session.chunks(), 3);
+        assert_eq!(session.chunk_length(2), Some(100));
+        assert_eq!(session.chunk_length(3), None);
+        assert!(sessions.get(&db, &session.id, 2).await.is_err());
+
+        // chunks can arrive out of order and be re-sent
+        sessions
+            .write_chunk(&db, &mut session, 2, &bytes[chunk * 2..])
+            .await
+            .unwrap();
+        sessions
+            .write_chunk(&db, &mut session, 0, &bytes[..chunk])
+            .await
+            .unwrap();
+        assert!(sessions.write_chunk(&db, &mut session, 1, &bytes[..100]).await.is_err());
+        assert!(sessions.write_chunk(&db, &mut session, 3, &bytes[..100]).await.is_err());
+        assert_eq!(sessions.received(&session).await.unwrap(), [0, 2]);
+        assert!(sessions.assemble(&session).await.is_err());
+
+        sessions
+            .write_chunk(&db, &mut session, 1, &bytes[..chunk])
+            .await
+            .unwrap();
+        assert!(sessions.assemble(&session).await.is_err());
+        sessions
+            .write_chunk(&db, &mut session, 1, &bytes[chunk..chunk * 2])
+            .await
+            .unwrap();
+        let session = sessions.get(&db, &session.id, 1).await.unwrap();
+        assert_eq!(sessions.assemble(&session).await.unwrap(), bytes);
+
+        sessions.remove(&db, &session.id).await.unwrap();
+        assert!(sessions.get(&db, &session.id, 1).await.is_err());
+        assert!(!sessions.base.join(&session.id).exists());
+        let _ = std::fs::remove_dir_all(&sessions.base);
+    }
+
+    #[tokio::test]
+    async fn test_expire_sessions() {
+        let db = database().await;
+        let sessions = storage("expire");
+        let active = sessions.create(&db, new_session(b"active")).await.unwrap();
+        let expired = sessions.create(&db, new_session(b"expired")).await.unwrap();
+        db.execute(
+            "UPDATE upload_session SET updated_at = datetime('now', '-2 days') WHERE id = ?",
+            [expired.id.clone()],
+        )
+        .await
+        .unwrap();
+        std::fs::create_dir(sessions.base.join("orphan")).unwrap();
+
+        assert!(sessions.get(&db, &expired.id, 1).await.is_err());
+        assert_eq!(sessions.expire(&db).await.unwrap(), 1);
+        assert!(sessions.base.join(&active.id).exists());
+        assert!(!sessions.base.join(&expired.id).exists());
+        assert!(!sessions.base.join("orphan").exists());
+
+        for _ in 1..MAX_SESSIONS_PER_ACCOUNT {
+            sessions.create(&db, new_session(b"more")).await.unwrap();
+        }
+        assert!(sessions.create(&db, new_session(b"too many")).await.is_err());
+        let _ = std::fs::remove_dir_all(&sessions.base);
+    }
+}
@@ -42,6 +42,18 @@ pub fn empty_string_is_none<'de, D: Deserializer<'de>>(de: D) -> Result<Option<S
     Ok(opt.filter(|s| !s.is_empty()))
 }
 
+/// Distinguishes between a missing value and a `null` one, e.g. for partial updates.
+///
+/// This should be used with `#[serde(default)]` so that missing values are `None`
+/// and `null` values are `Some(None)`.
+pub fn nullable<'de, D, T>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
 pub fn inner_json<'de, D, T>(de: D) -> Result<T, D::Error>
 where
     D: Deserializer<'de>,
@@ -76,6 +88,40 @@ pub fn join_iter<T: ToString>(sep: impl AsRef<str>, mut iter: impl Iterator<Item
     buffer
 }
 
+/// Returns `true` if the text matches the glob pattern, ignoring case.
+///
+/// Only `*`, which matches any run of characters, and `?`, which matches a
+/// single character, are supported.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+    let text = text.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` match one more character and try again
+                Some((star, position)) => {
+                    backtrack = Some((star, position + 1));
+                    p = star + 1;
+                    t = position + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
 /// Returns the directory where logs are stored
 pub fn logs_directory() -> PathBuf {
     dirs::state_dir()
@@ -108,3 +154,21 @@ pub mod base64_bytes {
         BASE64_STANDARD.decode(s.as_bytes()).map_err(serde::de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.srt", "Show - 01.srt"));
+        assert!(glob_matches("*.SRT", "show - 01.srt"));
+        assert!(!glob_matches("*.srt", "show - 01.ass"));
+        assert!(glob_matches("*- 1?.*", "Show - 13.ass"));
+        assert!(!glob_matches("*- 1?.*", "Show - 3.ass"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("[Group]*", "[group] show.srt"));
+    }
+}
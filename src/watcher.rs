@@ -0,0 +1,303 @@
+//! Watching the subtitle directory for changes made outside of the site.
+//!
+//! Admins occasionally add or remove files in an entry's directory directly,
+//! e.g. over SSH. The watcher picks these up, records the files, bumps the
+//! entry's `last_updated_at`, and writes an audit log entry attributed to the
+//! system rather than an account.
+//!
+//! This uses inotify so it's only supported on Linux.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::{audit, files, AppState};
+
+/// How long to wait for more changes before processing them.
+///
+/// Copying several files at once produces a burst of events so they're batched together.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        ffi::{CString, OsStr, OsString},
+        io::Read,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+
+    pub use libc::{
+        IN_CLOSE_WRITE, IN_CREATE, IN_DELETE, IN_DELETE_SELF, IN_IGNORED, IN_ISDIR, IN_MOVED_FROM, IN_MOVED_TO,
+        IN_MOVE_SELF, IN_Q_OVERFLOW,
+    };
+
+    /// The size of the fixed part of an event, before the name.
+    const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+    /// A single event read from an inotify instance.
+    #[derive(Debug)]
+    pub struct Event {
+        pub wd: i32,
+        pub mask: u32,
+        pub name: Option<OsString>,
+    }
+
+    /// A minimal blocking wrapper around an inotify instance.
+    pub struct Inotify {
+        file: std::fs::File,
+    }
+
+    impl Inotify {
+        pub fn new() -> std::io::Result<Self> {
+            // SAFETY: inotify_init1 has no preconditions and returns either a new descriptor or -1
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // SAFETY: the descriptor was just created and nothing else owns it
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            Ok(Self { file: fd.into() })
+        }
+
+        /// Starts watching the path for the given events, returning the watch descriptor.
+        pub fn add_watch(&self, path: &Path, mask: u32) -> std::io::Result<i32> {
+            let path = CString::new(path.as_os_str().as_bytes())?;
+            // SAFETY: the descriptor is valid for the lifetime of self and the path is NUL terminated
+            let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) };
+            if wd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(wd)
+        }
+
+        /// Blocks until events are available and returns them.
+        pub fn read_events(&self, buffer: &mut [u8]) -> std::io::Result<Vec<Event>> {
+            let read = (&self.file).read(buffer)?;
+            Ok(parse_events(&buffer[..read]))
+        }
+    }
+
+    fn parse_events(mut buffer: &[u8]) -> Vec<Event> {
+        let field = |bytes: &[u8], offset: usize| -> [u8; 4] { bytes[offset..offset + 4].try_into().unwrap() };
+        let mut events = Vec::new();
+        while buffer.len() >= HEADER_SIZE {
+            let wd = i32::from_ne_bytes(field(buffer, 0));
+            let mask = u32::from_ne_bytes(field(buffer, 4));
+            let len = u32::from_ne_bytes(field(buffer, 12)) as usize;
+            let Some(name) = buffer.get(HEADER_SIZE..HEADER_SIZE + len) else {
+                break;
+            };
+            // The name is padded with NUL bytes
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            events.push(Event {
+                wd,
+                mask,
+                name: (!name.is_empty()).then(|| OsStr::from_bytes(name).to_os_string()),
+            });
+            buffer = &buffer[HEADER_SIZE + len..];
+        }
+        events
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn event(wd: i32, mask: u32, name: &[u8], len: u32) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&wd.to_ne_bytes());
+            bytes.extend_from_slice(&mask.to_ne_bytes());
+            bytes.extend_from_slice(&0u32.to_ne_bytes());
+            bytes.extend_from_slice(&len.to_ne_bytes());
+            bytes.extend_from_slice(name);
+            bytes.resize(HEADER_SIZE + len as usize, 0);
+            bytes
+        }
+
+        #[test]
+        fn test_parse_events() {
+            let mut buffer = event(1, IN_CREATE, b"01.srt", 16);
+            buffer.extend(event(2, IN_IGNORED, b"", 0));
+            buffer.extend(&event(3, IN_DELETE, b"truncated", 16)[..HEADER_SIZE + 4]);
+
+            let events = parse_events(&buffer);
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0].wd, 1);
+            assert_eq!(events[0].mask, IN_CREATE);
+            assert_eq!(events[0].name.as_deref(), Some(OsStr::new("01.srt")));
+            assert_eq!(events[1].wd, 2);
+            assert_eq!(events[1].name, None);
+        }
+    }
+}
+
+/// Watches the subtitle directory and every directory directly within it.
+///
+/// The directories that had files change in them are sent through the channel.
+/// This blocks forever so it should be run on its own thread.
+#[cfg(target_os = "linux")]
+fn watch(root: PathBuf, tx: UnboundedSender<PathBuf>) -> std::io::Result<()> {
+    use inotify::*;
+    use std::collections::HashMap;
+
+    const ROOT_MASK: u32 = IN_CREATE | IN_MOVED_TO | IN_DELETE_SELF | IN_MOVE_SELF;
+    const ENTRY_MASK: u32 = IN_CREATE | IN_CLOSE_WRITE | IN_DELETE | IN_MOVED_FROM | IN_MOVED_TO;
+
+    let inotify = Inotify::new()?;
+    let root_wd = inotify.add_watch(&root, ROOT_MASK)?;
+    let mut directories = HashMap::new();
+    let watch_directory =
+        |directories: &mut HashMap<i32, PathBuf>, path: PathBuf| match inotify.add_watch(&path, ENTRY_MASK) {
+            Ok(wd) => {
+                directories.insert(wd, path);
+            }
+            Err(e) => tracing::warn!(error=%e, path=%path.display(), "Could not watch directory"),
+        };
+    for entry in root.read_dir()? {
+        let entry = entry?;
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            watch_directory(&mut directories, entry.path());
+        }
+    }
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        for event in inotify.read_events(&mut buffer)? {
+            if event.mask & IN_Q_OVERFLOW != 0 {
+                tracing::warn!("Subtitle directory watcher missed events due to an overflow");
+                continue;
+            }
+            if event.wd == root_wd {
+                if event.mask & (IN_DELETE_SELF | IN_MOVE_SELF) != 0 {
+                    return Err(std::io::Error::other("subtitle directory was removed"));
+                }
+                if event.mask & IN_ISDIR != 0 {
+                    if let Some(name) = event.name {
+                        let path = root.join(name);
+                        watch_directory(&mut directories, path.clone());
+                        // A directory that was moved in might already have files
+                        let _ = tx.send(path);
+                    }
+                }
+                continue;
+            }
+            if event.mask & IN_IGNORED != 0 {
+                directories.remove(&event.wd);
+                continue;
+            }
+            if event.mask & IN_ISDIR == 0 {
+                if let Some(path) = directories.get(&event.wd) {
+                    if tx.send(path.clone()).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_root: PathBuf, _tx: UnboundedSender<PathBuf>) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Waits for a batch of changed directories.
+///
+/// Returns `None` once the watcher has stopped.
+async fn next_batch(rx: &mut UnboundedReceiver<PathBuf>) -> Option<BTreeSet<PathBuf>> {
+    let mut batch = BTreeSet::from([rx.recv().await?]);
+    while let Ok(Some(path)) = tokio::time::timeout(SETTLE_DELAY, rx.recv()).await {
+        batch.insert(path);
+    }
+    Some(batch)
+}
+
+/// Reconciles the entry that owns the directory with its files.
+///
+/// Changes that were already recorded, e.g. uploads made through the site, are ignored.
+async fn sync_entry(state: &AppState, directory: &Path) -> anyhow::Result<()> {
+    let Some(entry_id) = files::entry_id_by_path(state.database(), directory).await? else {
+        return Ok(());
+    };
+
+    let before: HashSet<String> = state
+        .database()
+        .call(move |conn| -> rusqlite::Result<HashSet<String>> {
+            let mut stmt = conn.prepare_cached("SELECT name FROM file WHERE entry_id = ?")?;
+            let names = stmt.query_map([entry_id], |row| row.get(0))?;
+            names.collect()
+        })
+        .await?;
+    let listed = files::sync_directory(state.database(), state.storage(), entry_id, directory).await?;
+    let after = listed.into_iter().map(|f| f.record.name).collect::<HashSet<_>>();
+
+    let mut added = after.difference(&before).cloned().collect::<Vec<_>>();
+    let mut removed = before.difference(&after).cloned().collect::<Vec<_>>();
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+    added.sort();
+    removed.sort();
+
+    if !added.is_empty() {
+        state
+            .database()
+            .execute(
+                "UPDATE directory_entry SET last_updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                [entry_id],
+            )
+            .await?;
+    }
+    state.cached_directories().invalidate().await;
+    tracing::info!(
+        entry_id,
+        added = added.len(),
+        removed = removed.len(),
+        "Recorded external file changes"
+    );
+    state
+        .audit(audit::AuditLogEntry::new(audit::ExternalFiles { added, removed }).with_entry(entry_id))
+        .await;
+    Ok(())
+}
+
+/// Watches the subtitle directory for changes made outside of the site.
+///
+/// This does nothing unless the `watch_subtitle_path` configuration option is enabled.
+pub async fn watch_loop(state: AppState) {
+    if !state.config().watch_subtitle_path {
+        return;
+    }
+
+    let root = state.config().subtitle_path.clone();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    // This is a plain thread rather than a blocking task since it never finishes,
+    // which would otherwise hold up the runtime from shutting down.
+    let spawned = std::thread::Builder::new()
+        .name(String::from("subtitle-watcher"))
+        .spawn(move || {
+            if let Err(e) = watch(root, tx) {
+                tracing::error!(error=%e, "Subtitle directory watcher stopped");
+            }
+        });
+    if let Err(e) = spawned {
+        tracing::error!(error=%e, "Could not start subtitle directory watcher");
+        return;
+    }
+
+    while let Some(batch) = next_batch(&mut rx).await {
+        for directory in batch {
+            if let Err(e) = sync_entry(&state, &directory).await {
+                tracing::warn!(error=%e, path=%directory.display(), "Could not sync watched directory");
+            }
+        }
+    }
+}
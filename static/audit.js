@@ -245,6 +245,25 @@ const auditLogTypes = Object.freeze({
     let files = data.files.map(fileToElement);
     return auditLogEntry(log.id, title, html('ul', files));
   },
+  retime_file: (data, log, info) => {
+    let title = [
+      data.api ? "[API] " : "",
+      userLink(log.account_id, info),
+      " retimed a file in ",
+      entryLink(log.entry_id, info),
+    ];
+    let contents = [
+      html('li', html('strong', 'Original: '), data.source),
+      html('li', html('strong', 'Saved as: '), data.name),
+    ];
+    if(data.shift !== 0) {
+      contents.push(html('li', html('strong', 'Shift: '), `${data.shift}ms`));
+    }
+    if(data.from_fps != null && data.to_fps != null) {
+      contents.push(html('li', html('strong', 'Framerate: '), `${data.from_fps} \u2192 ${data.to_fps}`));
+    }
+    return auditLogEntry(log.id, title, html('ul', contents));
+  },
   delete_files: (data, log, info) => {
     let title = [
       userLink(log.account_id, info),
@@ -301,6 +320,23 @@ const auditLogTypes = Object.freeze({
     let files = data.files.map(fileToElement);
     return auditLogEntry(log.id, title, html('ul', files));
   },
+  fsck_repair: (data, log, info) => {
+    let title = {
+      create_directory: ["The system recreated the missing directory of ", entryLink(log.entry_id, info)],
+      adopt_directory: ["The system adopted an orphaned directory as ", entryLink(log.entry_id, info)],
+      update_last_updated: ["The system recomputed the last updated date of ", entryLink(log.entry_id, info)],
+      prune_trash_record: ["The system removed a broken trash record"],
+    }[data.action];
+    return auditLogEntry(log.id, title, html('code', data.path));
+  },
+  external_files: (data, log, info) => {
+    let title = ["The system detected files changed outside the site in ", entryLink(log.entry_id, info)];
+    let files = [
+      ...data.added.map(name => html('li', 'Added ', html('code', name))),
+      ...data.removed.map(name => html('li', 'Removed ', html('code', name))),
+    ];
+    return auditLogEntry(log.id, title, html('ul', files));
+  },
   edit_entry: (data, log, info) => {
     let title = [
       userLink(log.account_id, info),
@@ -353,6 +389,13 @@ const auditLogTypes = Object.freeze({
         } else {
           return html('li', 'Changed TMDB URL from ', html('a.before', before, {href: before}), ' to ', html('a.after', after, {href: after}));
         }
+      case 'aliases':
+        let added = (after ?? []).filter(a => !(before ?? []).includes(a));
+        let removed = (before ?? []).filter(a => !(after ?? []).includes(a));
+        return [
+          ...added.map(a => html('li', 'Added alias ', html('span.after', a))),
+          ...removed.map(a => html('li', 'Removed alias ', html('span.before', a))),
+        ];
       case 'flags':
         let changes = [];
         for(const [flag, title] of Object.entries(FLAG_NAMES)) {
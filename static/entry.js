@@ -548,6 +548,7 @@ query ($id: Int) {
       english
       native
     }
+    synonyms
     isAdult
     format
   }
@@ -599,7 +600,7 @@ function modalAlertHook(modal) {
   return (e) => el.parentNode.insertBefore(e, el.nextSibling);
 }
 
-function updateEntryFields(titles, adult, movie) {
+function updateEntryFields(titles, adult, movie, aliases) {
   if (titles?.romaji) {
     document.getElementById('entry-name').value = titles.romaji;
   }
@@ -611,6 +612,12 @@ function updateEntryFields(titles, adult, movie) {
   }
   document.getElementById('entry-movie').checked = movie;
   document.getElementById('entry-adult').checked = adult;
+  if (aliases?.length) {
+    // Merge rather than replace so aliases added by editors are kept
+    let textarea = document.getElementById('entry-aliases');
+    let current = textarea.value.split('\n').map(s => s.trim()).filter(s => s.length !== 0);
+    textarea.value = [...new Set([...current, ...aliases])].join('\n');
+  }
 }
 
 async function getAnimeInfo(id) {
@@ -628,7 +635,7 @@ async function getAnimeInfo(id) {
     let js = await response.json();
     let media = js?.data?.Media;
     if(media) {
-      updateEntryFields(media.title, media.isAdult, media.format === 'MOVIE');
+      updateEntryFields(media.title, media.isAdult, media.format === 'MOVIE', media.synonyms);
     }
     showModalAlert(editModal, {level: 'success', content: `Updated info from AniList`});
   } else {
@@ -642,7 +649,7 @@ async function refreshTmdbNames(id) {
   if (response.ok) {
     let js = await response.json();
     if(js) {
-      updateEntryFields(js.title, js.adult, js.movie);
+      updateEntryFields(js.title, js.adult, js.movie, js.aliases);
     }
     showModalAlert(editModal, {level: 'success', content: `Updated info from TMDB`});
   } else {
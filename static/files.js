@@ -151,7 +151,23 @@ function __scoreByName(el, query) {
   return total;
 }
 
-function filterEntries(query) {
+let __searchSequence = 0;
+
+async function __fetchServerScores(query) {
+  let url = filterElement?.dataset.searchUrl;
+  if (!url) {
+    return null;
+  }
+  try {
+    let resp = await fetch(`${url}&query=${encodeURIComponent(query)}`);
+    return resp.ok ? await resp.json() : null;
+  } catch {
+    return null;
+  }
+}
+
+async function filterEntries(query) {
+  let sequence = ++__searchSequence;
   if (!query) {
     resetSearchFilter();
     return;
@@ -189,10 +205,15 @@ function filterEntries(query) {
       };
     });
   } else {
+    // The server matches romaji, hiragana and katakana interchangeably when it's available
+    let scores = await __fetchServerScores(query);
+    if (sequence !== __searchSequence) {
+      return;
+    }
     mapped = entries.map(e => {
       return {
         entry: e,
-        score: __scoreByName(e, query),
+        score: scores === null ? __scoreByName(e, query) : (scores[e.dataset.id] ?? MIN_SCORE),
       };
     })
   }